@@ -0,0 +1,45 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+use sc_chain_spec::{ChainSpecExtension, ChainSpecGroup};
+use sp_runtime::{BuildStorage, Storage};
+
+use substrate_archive::wasm_runtime::Block;
+
+/// Genesis config of an unknown runtime. Only raw chain specs can be loaded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Genesis;
+
+impl BuildStorage for Genesis {
+	fn assimilate_storage(&self, _: &mut Storage) -> Result<(), String> {
+		Err("Only raw chain specs are supported. Export one with `build-spec --raw`.".into())
+	}
+}
+
+/// Extensions commonly found in chain specs.
+/// Unknown extensions are ignored.
+#[derive(Default, Clone, Serialize, Deserialize, ChainSpecExtension, ChainSpecGroup)]
+#[serde(rename_all = "camelCase")]
+pub struct Extensions {
+	/// Block numbers with known hashes.
+	pub fork_blocks: sc_client_api::ForkBlocks<Block>,
+	/// Known bad block hashes.
+	pub bad_blocks: sc_client_api::BadBlocks<Block>,
+}
+
+pub type ChainSpec = sc_chain_spec::GenericChainSpec<Genesis, Extensions>;
@@ -0,0 +1,43 @@
+// Copyright 2018-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Archives any Substrate chain without linking its native runtime.
+//! Blocks are executed with the on-chain Wasm, and decoded only as far as the header.
+
+mod chain_spec;
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use substrate_archive::{
+	wasm_runtime::{Block, Executor, RuntimeApi},
+	ChainDb, CliOpts, ExecutionStrategy,
+};
+
+use self::chain_spec::ChainSpec;
+
+fn main() -> anyhow::Result<()> {
+	let cli = CliOpts::init();
+	let mut config = cli.config()?;
+	// there is no native runtime to execute with.
+	config.runtime.exec_strategy = ExecutionStrategy::AlwaysWasm;
+	// path to the raw chain spec JSON of the chain to archive.
+	let path = cli.chain_spec.as_deref().ok_or_else(|| anyhow!("--spec is required"))?;
+	let spec = ChainSpec::from_json_file(PathBuf::from(path)).map_err(|err| anyhow!("{}", err))?;
+
+	cli.run::<Block, RuntimeApi, Executor, ChainDb>(Box::new(spec), config)?;
+	Ok(())
+}
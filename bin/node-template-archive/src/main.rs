@@ -13,35 +13,29 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-mod cli_opts;
-
-use std::sync::{
-	atomic::{AtomicBool, Ordering},
-	Arc,
-};
+use std::path::PathBuf;
 
+use anyhow::anyhow;
 use node_template::service::Executor;
 use node_template_runtime::{opaque::Block, RuntimeApi};
 
-use substrate_archive::{Archive, ArchiveBuilder, SecondaryRocksDb};
+use substrate_archive::{ChainDb, CliOpts};
 
 fn main() -> anyhow::Result<()> {
-	let cli = cli_opts::CliOpts::init();
-	let config = cli.parse()?;
-
-	let mut archive = ArchiveBuilder::<Block, RuntimeApi, Executor, SecondaryRocksDb>::with_config(config)
-		.chain_spec(Box::new(cli.chain_spec))
-		.build()?;
-	archive.drive()?;
-
-	let running = Arc::new(AtomicBool::new(true));
-	let r = running.clone();
-
-	ctrlc::set_handler(move || {
-		r.store(false, Ordering::SeqCst);
-	})
-	.expect("Error setting Ctrl-C handler");
-	while running.load(Ordering::SeqCst) {}
-	archive.shutdown()?;
+	let cli = CliOpts::init();
+	let config = cli.config()?;
+	let spec = chain_spec(cli.chain_spec.as_deref().unwrap_or("local"))?;
+
+	cli.run::<Block, RuntimeApi, Executor, ChainDb>(Box::new(spec), config)?;
 	Ok(())
 }
+
+/// Chain spec to run in (dev/local), or the path to a chain spec JSON file.
+fn chain_spec(spec: &str) -> anyhow::Result<node_template::chain_spec::ChainSpec> {
+	let spec = match spec {
+		"dev" => node_template::chain_spec::development_config(),
+		"" | "local" => node_template::chain_spec::local_testnet_config(),
+		path => node_template::chain_spec::ChainSpec::from_json_file(PathBuf::from(path)),
+	};
+	spec.map_err(|err| anyhow!("Chain spec could not be loaded: {}", err))
+}
@@ -13,21 +13,12 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-mod cli_opts;
-
-use std::sync::{
-	atomic::{AtomicBool, Ordering},
-	Arc,
-};
-
 use anyhow::{anyhow, Result};
 use polkadot_service::kusama_runtime as ksm_rt;
 use polkadot_service::polkadot_runtime as dot_rt;
 use polkadot_service::westend_runtime as wnd_rt;
 use polkadot_service::Block;
-use substrate_archive::{
-	native_executor_instance, Archive, ArchiveBuilder, ArchiveConfig, ReadOnlyDb, SecondaryRocksDb,
-};
+use substrate_archive::{native_executor_instance, ChainDb, CliOpts};
 
 native_executor_instance!(
 	pub PolkadotExecutor,
@@ -51,50 +42,24 @@ native_executor_instance!(
 );
 
 pub fn main() -> Result<()> {
-	let cli = cli_opts::CliOpts::init();
-	let config = cli.parse()?;
-
-	let mut archive = run_archive::<SecondaryRocksDb>(&cli.chain_spec, config)?;
-	archive.drive()?;
-	let running = Arc::new(AtomicBool::new(true));
-	let r = running.clone();
-
-	ctrlc::set_handler(move || {
-		r.store(false, Ordering::SeqCst);
-	})
-	.expect("Error setting Ctrl-C handler");
-	while running.load(Ordering::SeqCst) {}
-	archive.boxed_shutdown()?;
+	let cli = CliOpts::init();
+	let config = cli.config()?;
 
-	Ok(())
-}
-
-fn run_archive<D: ReadOnlyDb + 'static>(
-	chain_spec: &str,
-	config: Option<ArchiveConfig>,
-) -> Result<Box<dyn Archive<Block, D>>> {
-	match chain_spec.to_ascii_lowercase().as_str() {
+	// One of kusama, westend, polkadot.
+	match cli.chain_spec.as_deref().unwrap_or("polkadot").to_ascii_lowercase().as_str() {
 		"kusama" | "ksm" => {
 			let spec = polkadot_service::chain_spec::kusama_config().map_err(|err| anyhow!("{}", err))?;
-			let archive = ArchiveBuilder::<Block, wnd_rt::RuntimeApi, KusamaExecutor, D>::with_config(config)
-				.chain_spec(Box::new(spec))
-				.build()?;
-			Ok(Box::new(archive))
+			cli.run::<Block, ksm_rt::RuntimeApi, KusamaExecutor, ChainDb>(Box::new(spec), config)?;
 		}
 		"westend" | "wnd" => {
 			let spec = polkadot_service::chain_spec::westend_config().map_err(|err| anyhow!("{}", err))?;
-			let archive = ArchiveBuilder::<Block, wnd_rt::RuntimeApi, WestendExecutor, D>::with_config(config)
-				.chain_spec(Box::new(spec))
-				.build()?;
-			Ok(Box::new(archive))
+			cli.run::<Block, wnd_rt::RuntimeApi, WestendExecutor, ChainDb>(Box::new(spec), config)?;
 		}
 		"polkadot" | "dot" => {
 			let spec = polkadot_service::chain_spec::polkadot_config().map_err(|err| anyhow!("{}", err))?;
-			let archive = ArchiveBuilder::<Block, dot_rt::RuntimeApi, PolkadotExecutor, D>::with_config(config)
-				.chain_spec(Box::new(spec))
-				.build()?;
-			Ok(Box::new(archive))
+			cli.run::<Block, dot_rt::RuntimeApi, PolkadotExecutor, ChainDb>(Box::new(spec), config)?;
 		}
-		c => Err(anyhow!("unknown chain {}", c)),
+		c => return Err(anyhow!("unknown chain {}", c)),
 	}
+	Ok(())
 }
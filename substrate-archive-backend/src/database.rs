@@ -17,17 +17,38 @@
 //! Custom Read-Only Database Instance using RocksDB Secondary features
 //! Will try catching up with primary database on every `get()`
 
+mod memory_db;
+mod parity_db;
+
 use std::{collections::HashMap, fmt, io, path::PathBuf};
 
 use kvdb::KeyValueDB;
 use kvdb_rocksdb::{Database, DatabaseConfig};
+use serde::{Deserialize, Serialize};
 
 use sp_database::{ColumnId, Database as DatabaseTrait, Transaction};
 
+pub use self::{memory_db::MemoryDb, parity_db::ParityDb};
+
 const NUM_COLUMNS: u32 = 11;
 
 pub type KeyValuePair = (Box<[u8]>, Box<[u8]>);
 
+/// The database a node is running with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DatabaseKind {
+	/// `--database rocksdb`, read through a RocksDB secondary instance.
+	RocksDb,
+	/// `--database paritydb`.
+	ParityDb,
+}
+
+impl Default for DatabaseKind {
+	fn default() -> Self {
+		Self::RocksDb
+	}
+}
+
 // Archive specific K/V database reader implementation
 pub trait ReadOnlyDb: Send + Sync {
 	/// Read key/value pairs from the database
@@ -36,8 +57,9 @@ pub trait ReadOnlyDb: Send + Sync {
 	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a>;
 	/// Catch up with the latest information added to the database
 	fn catch_up_with_primary(&self) -> io::Result<()>;
-	/// Open database as read-only
-	fn open_database(path: &str, cache_size: usize, db_path: PathBuf) -> io::Result<Self>
+	/// Open database as read-only.
+	/// Fails if the implementation cannot read databases of `kind`.
+	fn open_database(kind: DatabaseKind, path: &str, cache_size: usize, db_path: PathBuf) -> io::Result<Self>
 	where
 		Self: Sized;
 }
@@ -96,7 +118,16 @@ impl ReadOnlyDb for SecondaryRocksDb {
 		self.inner.try_catch_up_with_primary()
 	}
 
-	fn open_database(path: &str, cache_size: usize, db_path: PathBuf) -> io::Result<SecondaryRocksDb> {
+	fn open_database(
+		kind: DatabaseKind,
+		path: &str,
+		cache_size: usize,
+		db_path: PathBuf,
+	) -> io::Result<SecondaryRocksDb> {
+		if kind != DatabaseKind::RocksDb {
+			let msg = format!("SecondaryRocksDb cannot open a {:?} database", kind);
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+		}
 		// need to make sure this is `Some` to open secondary instance
 		let db_path = db_path.as_path().to_str().expect("Creating db path failed");
 		let mut db_config = Config {
@@ -129,6 +160,45 @@ impl ReadOnlyDb for SecondaryRocksDb {
 	}
 }
 
+/// Read-only database of a node, running with either RocksDB or ParityDb.
+#[derive(Debug)]
+pub enum ChainDb {
+	RocksDb(SecondaryRocksDb),
+	ParityDb(ParityDb),
+}
+
+impl ReadOnlyDb for ChainDb {
+	fn get(&self, col: u32, key: &[u8]) -> Option<Vec<u8>> {
+		match self {
+			Self::RocksDb(db) => ReadOnlyDb::get(db, col, key),
+			Self::ParityDb(db) => ReadOnlyDb::get(db, col, key),
+		}
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
+		match self {
+			Self::RocksDb(db) => db.iter(col),
+			Self::ParityDb(db) => db.iter(col),
+		}
+	}
+
+	fn catch_up_with_primary(&self) -> io::Result<()> {
+		match self {
+			Self::RocksDb(db) => ReadOnlyDb::catch_up_with_primary(db),
+			Self::ParityDb(db) => db.catch_up_with_primary(),
+		}
+	}
+
+	fn open_database(kind: DatabaseKind, path: &str, cache_size: usize, db_path: PathBuf) -> io::Result<ChainDb> {
+		match kind {
+			DatabaseKind::RocksDb => {
+				SecondaryRocksDb::open_database(kind, path, cache_size, db_path).map(Self::RocksDb)
+			}
+			DatabaseKind::ParityDb => ParityDb::open_database(kind, path, cache_size, db_path).map(Self::ParityDb),
+		}
+	}
+}
+
 type DbError = std::result::Result<(), sp_database::error::DatabaseError>;
 /// Preliminary trait for ReadOnlyDb
 impl<H: Clone + AsRef<[u8]>> DatabaseTrait<H> for SecondaryRocksDb {
@@ -0,0 +1,188 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-memory database in the column layout of a Substrate node.
+//! Meant for tests, which write a chain into it instead of running a node.
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt, io,
+	path::PathBuf,
+};
+
+use codec::Encode;
+use parking_lot::RwLock;
+
+use sp_runtime::{
+	traits::{Block as BlockT, Header as HeaderT},
+	Justifications,
+};
+
+use super::{DatabaseKind, KeyValuePair, ReadOnlyDb};
+use crate::util::{columns, meta_keys, number_index_key};
+
+#[derive(Default)]
+pub struct MemoryDb {
+	columns: RwLock<HashMap<u32, BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl fmt::Debug for MemoryDb {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let db = self.columns.read();
+		write!(f, "In-Memory Database with {} keys", db.values().map(BTreeMap::len).sum::<usize>())
+	}
+}
+
+impl MemoryDb {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Insert a value into a column.
+	pub fn insert(&self, col: u32, key: Vec<u8>, value: Vec<u8>) {
+		self.columns.write().entry(col).or_default().insert(key, value);
+	}
+
+	/// Insert the header and body of a block.
+	/// Canonical blocks are also added to the number index.
+	pub fn insert_block<B: BlockT>(&self, block: &B, canonical: bool) {
+		let header = block.header();
+		let lookup_key = lookup_key::<B>(header);
+		self.insert(columns::HEADER, lookup_key.clone(), header.encode());
+		self.insert(columns::BODY, lookup_key.clone(), block.extrinsics().encode());
+		self.insert(columns::KEY_LOOKUP, header.hash().as_ref().to_vec(), lookup_key.clone());
+		if canonical {
+			self.set_canonical::<B>(header);
+		}
+	}
+
+	/// Add a block to the number index, making it the canonical block with its number.
+	pub fn set_canonical<B: BlockT>(&self, header: &B::Header) {
+		let number = number_index_key(*header.number()).expect("block number fits in u32");
+		self.insert(columns::KEY_LOOKUP, number.to_vec(), lookup_key::<B>(header));
+	}
+
+	/// Insert trie nodes, keyed by their prefixed key.
+	pub fn insert_state(&self, nodes: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) {
+		let mut db = self.columns.write();
+		db.entry(columns::STATE).or_default().extend(nodes);
+	}
+
+	/// Remove trie nodes, as the node does when it prunes a state.
+	pub fn remove_state<'a>(&self, keys: impl IntoIterator<Item = &'a [u8]>) {
+		let mut db = self.columns.write();
+		if let Some(col) = db.get_mut(&columns::STATE) {
+			keys.into_iter().for_each(|key| {
+				col.remove(key);
+			});
+		}
+	}
+
+	/// Set the justifications of a block, which the node stores once the block is finalized.
+	pub fn set_justifications<B: BlockT>(&self, header: &B::Header, justifications: &Justifications) {
+		self.insert(columns::JUSTIFICATION, lookup_key::<B>(header), justifications.encode());
+	}
+
+	/// Set the genesis block.
+	pub fn set_genesis<B: BlockT>(&self, header: &B::Header) {
+		self.insert(columns::META, meta_keys::GENESIS_HASH.to_vec(), header.hash().encode());
+	}
+
+	/// Set the best block.
+	pub fn set_best<B: BlockT>(&self, header: &B::Header) {
+		self.insert(columns::META, meta_keys::BEST_BLOCK.to_vec(), lookup_key::<B>(header));
+	}
+
+	/// Set the last finalized block.
+	pub fn set_finalized<B: BlockT>(&self, header: &B::Header) {
+		self.insert(columns::META, meta_keys::FINALIZED_BLOCK.to_vec(), lookup_key::<B>(header));
+	}
+}
+
+/// Key of a block in the `HEADER` and `BODY` columns: its number followed by its hash.
+fn lookup_key<B: BlockT>(header: &B::Header) -> Vec<u8> {
+	let mut key = number_index_key(*header.number()).expect("block number fits in u32").to_vec();
+	key.extend_from_slice(header.hash().as_ref());
+	key
+}
+
+impl ReadOnlyDb for MemoryDb {
+	fn get(&self, col: u32, key: &[u8]) -> Option<Vec<u8>> {
+		self.columns.read().get(&col).and_then(|col| col.get(key).cloned())
+	}
+
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
+		let pairs = self
+			.columns
+			.read()
+			.get(&col)
+			.map(|col| col.iter().map(|(k, v)| (Box::from(&k[..]), Box::from(&v[..]))).collect::<Vec<_>>())
+			.unwrap_or_default();
+		Box::new(pairs.into_iter())
+	}
+
+	fn catch_up_with_primary(&self) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn open_database(_: DatabaseKind, _: &str, _: usize, _: PathBuf) -> io::Result<MemoryDb> {
+		Err(io::Error::new(io::ErrorKind::InvalidInput, "MemoryDb cannot be opened from a path"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::{read_header, read_meta};
+	use sp_runtime::{
+		generic::{self, BlockId},
+		traits::BlakeTwo256,
+		OpaqueExtrinsic,
+	};
+
+	type Header = generic::Header<u32, BlakeTwo256>;
+	type Block = generic::Block<Header, OpaqueExtrinsic>;
+
+	fn block(number: u32, parent: &Header) -> Block {
+		let header = Header::new(number, Default::default(), Default::default(), parent.hash(), Default::default());
+		Block::new(header, Vec::new())
+	}
+
+	#[test]
+	fn should_read_blocks_in_substrate_layout() {
+		let db = MemoryDb::new();
+		let genesis = Header::new(0, Default::default(), Default::default(), Default::default(), Default::default());
+		let canon = block(1, &genesis);
+		let mut fork = block(1, &genesis);
+		fork.header.state_root = [1; 32].into();
+		db.insert_block(&Block::new(genesis.clone(), Vec::new()), true);
+		db.insert_block(&canon, true);
+		db.insert_block(&fork, false);
+		db.set_genesis::<Block>(&genesis);
+		db.set_best::<Block>(&canon.header);
+		db.set_finalized::<Block>(&genesis);
+
+		let header = |id| read_header::<Block, _>(&db, columns::KEY_LOOKUP, columns::HEADER, id).unwrap();
+		assert_eq!(header(BlockId::Number(1)), Some(canon.header.clone()));
+		assert_eq!(header(BlockId::Hash(fork.header.hash())), Some(fork.header.clone()));
+		assert_eq!(header(BlockId::Number(2)), None);
+
+		let meta = read_meta::<Block, _>(&db, columns::HEADER).unwrap();
+		assert_eq!(meta.genesis_hash, genesis.hash());
+		assert_eq!(meta.best_hash, canon.header.hash());
+		assert_eq!(meta.finalized_number, 0);
+	}
+}
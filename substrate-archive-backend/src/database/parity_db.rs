@@ -0,0 +1,194 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Read-Only Database Instance of a node running with `--database paritydb`.
+//! ParityDb has no secondary instances. Catching up re-opens the database,
+//! which replays the commits the node has made since, so it is skipped while the files
+//! of the database are unchanged.
+
+use std::{
+	ffi::OsString,
+	fmt, fs, io,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+
+use parking_lot::{Mutex, RwLock};
+
+use super::{DatabaseKind, KeyValuePair, ReadOnlyDb};
+use crate::util::columns;
+
+/// Number of columns of the Substrate column layout in `util::columns`.
+const NUM_COLUMNS: u8 = 12;
+
+/// Names, sizes and modification times of the files of a database, which change with every commit.
+type Fingerprint = Vec<(OsString, u64, Option<SystemTime>)>;
+
+pub struct ParityDb {
+	inner: RwLock<parity_db::Db>,
+	path: PathBuf,
+	/// Fingerprint of the files the database was last opened from.
+	opened_from: Mutex<Fingerprint>,
+}
+
+impl fmt::Debug for ParityDb {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Read Only ParityDb at {}", self.path.display())
+	}
+}
+
+impl ParityDb {
+	/// Open the database at `path` as read-only.
+	pub fn open(path: PathBuf) -> io::Result<Self> {
+		let opened_from = fingerprint(&path)?;
+		let inner = Self::open_inner(&path)?;
+		Ok(Self { inner: RwLock::new(inner), path, opened_from: Mutex::new(opened_from) })
+	}
+
+	/// Options matching the ones Substrate opens a full node database with.
+	fn options(path: &Path) -> parity_db::Options {
+		let mut options = parity_db::Options::with_columns(path, NUM_COLUMNS);
+		let state = &mut options.columns[columns::STATE as usize];
+		state.ref_counted = true;
+		state.preimage = true;
+		state.uniform = true;
+		options
+	}
+
+	fn open_inner(path: &Path) -> io::Result<parity_db::Db> {
+		parity_db::Db::open_read_only(&Self::options(path)).map_err(other_io_error)
+	}
+
+	fn get(&self, col: u32, key: &[u8]) -> Option<Vec<u8>> {
+		match self.inner.read().get(col as u8, key) {
+			Ok(v) => v,
+			Err(e) => {
+				log::error!("{}", e.to_string());
+				None
+			}
+		}
+	}
+}
+
+impl ReadOnlyDb for ParityDb {
+	fn get(&self, col: u32, key: &[u8]) -> Option<Vec<u8>> {
+		self.get(col, key)
+	}
+
+	/// ParityDb only keeps the hashes of keys, so only the canonical number index of `KEY_LOOKUP`,
+	/// whose keys are known in advance, can be iterated.
+	/// Iterating any other column yields nothing.
+	fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
+		if col != columns::KEY_LOOKUP {
+			log::warn!("Iterating column {} is not supported by ParityDb", col);
+			return Box::new(std::iter::empty());
+		}
+		let lookups = (0..=u32::MAX).map(move |num| {
+			let key = num.to_be_bytes();
+			self.get(col, &key).map(|value| (Box::from(&key[..]), value.into_boxed_slice()))
+		});
+		Box::new(lookups.take_while(Option::is_some).flatten())
+	}
+
+	/// Re-open the database if the node has written to it since it was last opened.
+	fn catch_up_with_primary(&self) -> io::Result<()> {
+		let mut opened_from = self.opened_from.lock();
+		let current = fingerprint(&self.path)?;
+		if current == *opened_from {
+			return Ok(());
+		}
+		let db = Self::open_inner(&self.path)?;
+		*self.inner.write() = db;
+		*opened_from = current;
+		Ok(())
+	}
+
+	fn open_database(kind: DatabaseKind, path: &str, _: usize, _: PathBuf) -> io::Result<ParityDb> {
+		if kind != DatabaseKind::ParityDb {
+			let msg = format!("ParityDb cannot open a {:?} database", kind);
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+		}
+		log::info!(target: "db", "Open ParityDb at {}", path);
+		Self::open(PathBuf::from(path))
+	}
+}
+
+/// Fingerprint of the files of the database at `path`.
+/// Reading it only lists the directory, which is far cheaper than re-opening the database.
+fn fingerprint(path: &Path) -> io::Result<Fingerprint> {
+	let mut files = Vec::new();
+	for entry in fs::read_dir(path)? {
+		let entry = entry?;
+		let meta = entry.metadata()?;
+		files.push((entry.file_name(), meta.len(), meta.modified().ok()));
+	}
+	files.sort();
+	Ok(files)
+}
+
+fn other_io_error(e: parity_db::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Generate a database with the canonical number index of `blocks` blocks.
+	fn fixture(path: &Path, blocks: u32) {
+		let db = parity_db::Db::open_or_create(&ParityDb::options(path)).unwrap();
+		let tx = (0..blocks).map(|n| (columns::KEY_LOOKUP as u8, n.to_be_bytes().to_vec(), Some(vec![n as u8; 8])));
+		db.commit(tx.chain(std::iter::once((columns::HEADER as u8, b"header".to_vec(), Some(b"value".to_vec())))))
+			.unwrap();
+	}
+
+	#[test]
+	fn should_read_generated_database() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path();
+		fixture(path, 3);
+
+		let db = ParityDb::open_database(DatabaseKind::ParityDb, path.to_str().unwrap(), 0, PathBuf::new()).unwrap();
+		assert_eq!(db.get(columns::HEADER, b"header"), Some(b"value".to_vec()));
+		assert_eq!(db.get(columns::HEADER, b"missing"), None);
+		let numbers = db.iter(columns::KEY_LOOKUP).map(|(k, v)| (k.to_vec(), v.to_vec())).collect::<Vec<_>>();
+		assert_eq!(numbers, (0..3u32).map(|n| (n.to_be_bytes().to_vec(), vec![n as u8; 8])).collect::<Vec<_>>());
+		assert_eq!(db.iter(columns::HEADER).count(), 0);
+	}
+
+	#[test]
+	fn should_catch_up_with_node() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path();
+		fixture(path, 2);
+		let db = ParityDb::open(path.to_path_buf()).unwrap();
+		assert_eq!(db.iter(columns::KEY_LOOKUP).count(), 2);
+
+		fixture(path, 4);
+		assert_ne!(fingerprint(path).unwrap(), *db.opened_from.lock());
+		db.catch_up_with_primary().unwrap();
+		assert_eq!(db.iter(columns::KEY_LOOKUP).count(), 4);
+		// the database is only re-opened once the node wrote to it
+		assert_eq!(fingerprint(path).unwrap(), *db.opened_from.lock());
+	}
+
+	#[test]
+	fn should_not_open_rocksdb() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path();
+		assert!(ParityDb::open_database(DatabaseKind::RocksDb, path.to_str().unwrap(), 0, PathBuf::new()).is_err());
+	}
+}
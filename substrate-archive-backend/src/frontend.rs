@@ -15,38 +15,42 @@
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
 mod client;
+mod executor;
 
 use std::{
 	collections::HashMap,
 	convert::{TryFrom, TryInto},
-	path::PathBuf,
+	fs,
+	path::{Path, PathBuf},
 	str::FromStr,
 	sync::Arc,
 };
 
 use futures::{future::BoxFuture, task::SpawnExt};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use sc_client_api::{
 	execution_extensions::{ExecutionExtensions, ExecutionStrategies},
-	ExecutionStrategy,
+	ExecutionStrategy as SubstrateStrategy,
 };
-use sc_executor::{NativeExecutionDispatch, NativeExecutor, WasmExecutionMethod};
+use sc_executor::{NativeExecutionDispatch, WasmExecutionMethod};
 use sc_service::{ChainSpec, ClientConfig, LocalCallExecutor};
 use sp_api::ConstructRuntimeApi;
-use sp_core::traits::SpawnNamed;
+use sp_core::{hexdisplay::HexDisplay, traits::SpawnNamed};
 use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+use sp_wasm_interface::Function;
 
 pub use self::client::{Client, GetMetadata};
+use self::executor::ArchiveExecutor;
 use crate::{database::ReadOnlyDb, error::BackendError, read_only_backend::ReadOnlyBackend, RuntimeApiCollection};
 
 /// Archive Client Condensed Type
 pub type TArchiveClient<TBl, TRtApi, TExecDisp, D> = Client<TFullCallExecutor<TBl, TExecDisp, D>, TBl, TRtApi, D>;
 
 /// Full client call executor type.
-type TFullCallExecutor<TBl, TExecDisp, D> = LocalCallExecutor<TBl, ReadOnlyBackend<TBl, D>, NativeExecutor<TExecDisp>>;
+type TFullCallExecutor<TBl, TExecDisp, D> = LocalCallExecutor<TBl, ReadOnlyBackend<TBl, D>, ArchiveExecutor<TExecDisp>>;
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 pub enum ExecutionMethod {
 	Interpreted,
 	Compiled,
@@ -67,18 +71,50 @@ impl From<ExecutionMethod> for WasmExecutionMethod {
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// Whether to execute blocks with the native runtime compiled into the archive, or the on-chain Wasm.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ExecutionStrategy {
+	/// Execute with the on-chain Wasm runtime.
+	AlwaysWasm,
+	/// Execute with the native runtime if its version matches the on-chain runtime, else with Wasm.
+	NativeWhenPossible,
+	/// Execute with the native runtime if its version matches, falling back to Wasm on failure.
+	NativeElseWasm,
+	/// Execute each block both natively and with Wasm, and compare the results.
+	///
+	/// The native runtime is used in the `Syncing` execution context,
+	/// the Wasm runtime in the `Importing` context.
+	/// Blocks whose runtime version the native runtime can't execute are only executed with Wasm.
+	Both,
+}
+
+impl Default for ExecutionStrategy {
+	fn default() -> Self {
+		Self::NativeElseWasm
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RuntimeConfig {
 	/// How to execute the runtime code: interpreted (default) or JIT compiled.
 	#[serde(default)]
 	pub exec_method: ExecutionMethod,
+	/// Whether to execute blocks natively or with Wasm. Defaults to native, falling back to Wasm.
+	#[serde(default)]
+	pub exec_strategy: ExecutionStrategy,
 	/// Number of threads to spawn for block execution.
 	#[serde(default = "default_block_workers")]
 	pub block_workers: usize,
 	/// Number of 64KB Heap pages to allocate for wasm execution.
 	pub wasm_pages: Option<u64>,
+	/// Memory to spend on caching trie nodes shared by all block workers, in MB. 0 disables the cache.
+	#[serde(default = "default_trie_cache_size")]
+	pub trie_cache_size: usize,
 	/// Path to WASM blobs to override the on-chain WASM with (required for state change tracing).
 	pub wasm_runtime_overrides: Option<PathBuf>,
+	/// Directory to cache the runtimes compiled with the `Compiled` execution method in, across restarts.
+	/// Used for block execution, metadata and runtime versions.
+	pub wasm_cache_path: Option<PathBuf>,
 	/// code substitutes that should be used for the on chain wasm.
 	///
 	/// NOTE: Not to be confused with 'wasm_runtime_overrides'. code_substitutes
@@ -93,15 +129,63 @@ impl RuntimeConfig {
 	pub fn set_code_substitutes(&mut self, spec: &dyn ChainSpec) {
 		self.code_substitutes = spec.code_substitutes();
 	}
+
+	/// The directory to cache compiled runtimes in, if runtimes are compiled and a `wasm_cache_path` is set.
+	///
+	/// Every version of the archive gets its own subdirectory, and within it every executor configuration
+	/// and set of host functions, so artifacts compiled for another configuration are never loaded.
+	/// Wasmtime keys the artifacts within the subdirectory by the code they were compiled from.
+	/// The subdirectories of other archive versions are removed.
+	pub(crate) fn wasm_cache_dir(&self, host_functions: &[&'static dyn Function]) -> Option<PathBuf> {
+		if !matches!(self.exec_method, ExecutionMethod::Compiled) {
+			return None;
+		}
+		let path = self.wasm_cache_path.as_ref()?;
+		remove_stale_wasm_caches(path);
+		let mut key = format!("{:?}:{:?}", self.exec_method, self.wasm_pages);
+		for f in host_functions {
+			key.push_str(&format!(":{}{:?}", f.name(), f.signature()));
+		}
+		let dir = path
+			.join(env!("CARGO_PKG_VERSION"))
+			.join(HexDisplay::from(&sp_core::hashing::blake2_128(key.as_bytes())).to_string());
+		match fs::create_dir_all(&dir) {
+			Ok(()) => Some(dir),
+			Err(e) => {
+				log::warn!("Not caching compiled runtimes, could not create {}: {}", dir.display(), e);
+				None
+			}
+		}
+	}
+}
+
+/// Remove the artifacts compiled by other versions of the archive, which are never loaded again.
+fn remove_stale_wasm_caches(path: &Path) {
+	let entries = match fs::read_dir(path) {
+		Ok(entries) => entries,
+		// nothing was cached yet
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(|e| e.ok()) {
+		let stale = entry.file_name() != env!("CARGO_PKG_VERSION") && entry.path().is_dir();
+		if stale {
+			if let Err(e) = fs::remove_dir_all(entry.path()) {
+				log::warn!("Could not remove stale compiled runtimes in {}: {}", entry.path().display(), e);
+			}
+		}
+	}
 }
 
 impl Default for RuntimeConfig {
 	fn default() -> RuntimeConfig {
 		Self {
 			exec_method: ExecutionMethod::Interpreted,
+			exec_strategy: ExecutionStrategy::default(),
 			block_workers: default_block_workers(),
 			wasm_pages: None,
+			trie_cache_size: default_trie_cache_size(),
 			wasm_runtime_overrides: None,
+			wasm_cache_path: None,
 			code_substitutes: Default::default(),
 		}
 	}
@@ -112,6 +196,10 @@ fn default_block_workers() -> usize {
 	num_cpus::get()
 }
 
+const fn default_trie_cache_size() -> usize {
+	128
+}
+
 impl<B> TryFrom<RuntimeConfig> for ClientConfig<B>
 where
 	B: BlockT,
@@ -154,12 +242,13 @@ where
 	Dispatch: NativeExecutionDispatch + 'static,
 	<Runtime::RuntimeApi as sp_api::ApiExt<Block>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
 {
-	let backend = Arc::new(ReadOnlyBackend::new(db, true));
+	let backend = Arc::new(ReadOnlyBackend::with_trie_cache(db, true, config.trie_cache_size * 1024 * 1024));
 
-	let executor = NativeExecutor::<Dispatch>::new(config.exec_method.into(), config.wasm_pages, config.block_workers);
+	let executor = ArchiveExecutor::<Dispatch>::new(&config);
+	let strategies = execution_strategies(config.exec_strategy);
 	let executor =
 		LocalCallExecutor::new(backend.clone(), executor, Box::new(TaskExecutor::new()), config.try_into()?)?;
-	let client = Client::new(backend, executor, ExecutionExtensions::new(execution_strategies(), None, None))?;
+	let client = Client::new(backend, executor, ExecutionExtensions::new(strategies, None, None))?;
 	Ok(client)
 }
 
@@ -191,12 +280,60 @@ impl SpawnNamed for TaskExecutor {
 	}
 }
 
-fn execution_strategies() -> ExecutionStrategies {
+fn execution_strategies(strategy: ExecutionStrategy) -> ExecutionStrategies {
+	let strategy = match strategy {
+		ExecutionStrategy::AlwaysWasm => SubstrateStrategy::AlwaysWasm,
+		ExecutionStrategy::NativeWhenPossible => SubstrateStrategy::NativeWhenPossible,
+		ExecutionStrategy::NativeElseWasm => SubstrateStrategy::NativeElseWasm,
+		// Substrate's `Both` only compares the return value of a call, not the storage changes.
+		// Instead, the contexts are split so that both results can be compared by the caller.
+		ExecutionStrategy::Both => {
+			return ExecutionStrategies {
+				syncing: SubstrateStrategy::NativeWhenPossible,
+				importing: SubstrateStrategy::AlwaysWasm,
+				block_construction: SubstrateStrategy::NativeElseWasm,
+				offchain_worker: SubstrateStrategy::NativeElseWasm,
+				other: SubstrateStrategy::NativeElseWasm,
+			}
+		}
+	};
 	ExecutionStrategies {
-		syncing: ExecutionStrategy::NativeElseWasm,
-		importing: ExecutionStrategy::NativeElseWasm,
-		block_construction: ExecutionStrategy::NativeElseWasm,
-		offchain_worker: ExecutionStrategy::NativeElseWasm,
-		other: ExecutionStrategy::NativeElseWasm,
+		syncing: strategy,
+		importing: strategy,
+		block_construction: strategy,
+		offchain_worker: strategy,
+		other: strategy,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_wasm_interface::HostFunctions;
+
+	#[test]
+	fn should_key_wasm_cache_dir_by_host_functions() {
+		let path = tempfile::tempdir().expect("temporary directory");
+		let mut config = RuntimeConfig::default();
+		config.wasm_cache_path = Some(path.path().into());
+		let funs = sp_io::SubstrateHostFunctions::host_functions();
+		// interpreted runtimes are not cached
+		assert_eq!(config.wasm_cache_dir(&funs), None);
+
+		config.exec_method = ExecutionMethod::Compiled;
+		let dir = config.wasm_cache_dir(&funs).expect("compiled runtimes are cached");
+		assert!(dir.starts_with(path.path()) && dir.is_dir());
+		assert_eq!(config.wasm_cache_dir(&funs), Some(dir.clone()));
+		assert_ne!(config.wasm_cache_dir(&funs[1..]), Some(dir.clone()));
+
+		config.wasm_pages = Some(64);
+		assert_ne!(config.wasm_cache_dir(&funs), Some(dir.clone()));
+
+		// artifacts of other archive versions are removed, the current ones are kept
+		let stale = path.path().join("0.0.0").join("artifacts");
+		fs::create_dir_all(&stale).expect("stale cache directory");
+		config.wasm_pages = None;
+		assert_eq!(config.wasm_cache_dir(&funs), Some(dir.clone()));
+		assert!(!path.path().join("0.0.0").exists() && dir.is_dir());
 	}
 }
@@ -0,0 +1,143 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Executor of the runtime for the archive client.
+//!
+//! Dispatches calls like Substrate's `NativeExecutor`, but runs Wasm with a `WasmExecutor`
+//! that keeps compiled runtimes in the on-disk cache, which `NativeExecutor` has no way to configure.
+
+use std::{marker::PhantomData, panic::UnwindSafe, sync::Arc};
+
+use codec::{Decode, Encode};
+
+use sc_executor::{
+	error::{Error, Result},
+	NativeExecutionDispatch, NativeVersion, RuntimeInfo, RuntimeVersion, WasmExecutor,
+};
+use sp_core::{
+	traits::{CodeExecutor, Externalities, RuntimeCode},
+	NativeOrEncoded,
+};
+use sp_wasm_interface::{Function, HostFunctions};
+
+use super::RuntimeConfig;
+
+/// Executes the native runtime of `D` or the on-chain Wasm runtime,
+/// caching compiled Wasm runtimes on disk if the [`RuntimeConfig`] has a `wasm_cache_path`.
+pub struct ArchiveExecutor<D> {
+	wasm: Arc<WasmExecutor>,
+	native_version: NativeVersion,
+	_marker: PhantomData<D>,
+}
+
+impl<D: NativeExecutionDispatch> ArchiveExecutor<D> {
+	pub fn new(config: &RuntimeConfig) -> Self {
+		let host_functions = host_functions::<D>();
+		let cache_dir = config.wasm_cache_dir(&host_functions);
+		let wasm = WasmExecutor::new(
+			config.exec_method.into(),
+			config.wasm_pages,
+			host_functions,
+			config.block_workers,
+			cache_dir,
+		);
+		Self { wasm: Arc::new(wasm), native_version: D::native_version(), _marker: PhantomData }
+	}
+}
+
+/// Host functions of the Substrate runtime interface, overridden by the host functions the runtime of `D` extends.
+fn host_functions<D: NativeExecutionDispatch>() -> Vec<&'static dyn Function> {
+	let extended = D::ExtendHostFunctions::host_functions();
+	let mut funs = sp_io::SubstrateHostFunctions::host_functions()
+		.into_iter()
+		.filter(|f| !extended.iter().any(|e| e.name() == f.name()))
+		.collect::<Vec<_>>();
+	funs.extend(extended);
+	funs
+}
+
+impl<D> Clone for ArchiveExecutor<D> {
+	fn clone(&self) -> Self {
+		Self { wasm: self.wasm.clone(), native_version: self.native_version.clone(), _marker: PhantomData }
+	}
+}
+
+impl<D: NativeExecutionDispatch> RuntimeInfo for ArchiveExecutor<D> {
+	fn native_version(&self) -> &NativeVersion {
+		&self.native_version
+	}
+
+	fn runtime_version(&self, ext: &mut dyn Externalities, runtime_code: &RuntimeCode) -> Result<RuntimeVersion> {
+		self.wasm.with_instance(runtime_code, ext, false, |_, _, version, _| {
+			Ok(version.cloned().ok_or_else(|| Error::Other("Unknown runtime version".into())))
+		})
+	}
+}
+
+impl<D: NativeExecutionDispatch + 'static> CodeExecutor for ArchiveExecutor<D> {
+	type Error = Error;
+
+	fn call<
+		R: Decode + Encode + PartialEq,
+		NC: FnOnce() -> std::result::Result<R, Box<dyn std::error::Error + Send + Sync>> + UnwindSafe,
+	>(
+		&self,
+		ext: &mut dyn Externalities,
+		runtime_code: &RuntimeCode,
+		method: &str,
+		data: &[u8],
+		use_native: bool,
+		native_call: Option<NC>,
+	) -> (Result<NativeOrEncoded<R>>, bool) {
+		let mut used_native = false;
+		let result = self.wasm.with_instance(runtime_code, ext, false, |_, instance, onchain_version, mut ext| {
+			let onchain_version = onchain_version.ok_or_else(|| Error::Other("Unknown runtime version".into()))?;
+			let can_call_with = onchain_version.can_call_with(&self.native_version.runtime_version);
+			match (use_native, can_call_with, native_call) {
+				(false, _, _) | (_, false, _) => with_externalities_safe(&mut **ext, move || {
+					instance.call_export(method, data).map(NativeOrEncoded::Encoded)
+				}),
+				(true, true, Some(call)) => {
+					used_native = true;
+					with_externalities_safe(&mut **ext, move || {
+						call().map(NativeOrEncoded::Native).map_err(|e| Error::Other(e.to_string()))
+					})
+				}
+				(true, true, None) => {
+					used_native = true;
+					Ok(D::dispatch(&mut **ext, method, data).map(NativeOrEncoded::Encoded))
+				}
+			}
+		});
+		(result, used_native)
+	}
+}
+
+/// Run `f` with `ext` as the externalities of the runtime, turning a panic of the runtime into an error.
+fn with_externalities_safe<F, U>(ext: &mut dyn Externalities, f: F) -> Result<U>
+where
+	F: UnwindSafe + FnOnce() -> U,
+{
+	sp_externalities::set_and_run_with_externalities(ext, move || {
+		std::panic::catch_unwind(f).map_err(|e| match e.downcast::<String>() {
+			Ok(msg) => Error::RuntimePanicked(*msg),
+			Err(e) => match e.downcast::<&'static str>() {
+				Ok(msg) => Error::RuntimePanicked(msg.to_string()),
+				Err(_) => Error::RuntimePanicked("Unknown panic".into()),
+			},
+		})
+	})
+}
@@ -25,6 +25,7 @@ mod frontend;
 mod read_only_backend;
 mod runtime_version_cache;
 mod util;
+pub mod wasm_runtime;
 
 use std::sync::Arc;
 
@@ -36,11 +37,11 @@ use sp_version::GetRuntimeVersion;
 use self::frontend::GetMetadata;
 // re-exports
 pub use self::{
-	database::{KeyValuePair, ReadOnlyDb, SecondaryRocksDb},
+	database::{ChainDb, DatabaseKind, KeyValuePair, MemoryDb, ParityDb, ReadOnlyDb, SecondaryRocksDb},
 	error::BackendError,
-	frontend::{runtime_api, ExecutionMethod, RuntimeConfig, TArchiveClient},
-	read_only_backend::ReadOnlyBackend,
-	runtime_version_cache::RuntimeVersionCache,
+	frontend::{runtime_api, ExecutionMethod, ExecutionStrategy, RuntimeConfig, TArchiveClient},
+	read_only_backend::{ChainHead, ReadOnlyBackend},
+	runtime_version_cache::{CodeHash, RuntimeVersionCache},
 };
 
 pub type Meta<B> = Arc<dyn GetMetadata<B>>;
@@ -27,8 +27,9 @@ mod blockchain_backend;
 mod main_backend;
 mod misc_backend;
 mod state_backend;
+mod trie_cache;
 
-use std::{convert::TryInto, sync::Arc};
+use std::{convert::TryInto, ops::RangeInclusive, sync::Arc};
 
 use codec::Decode;
 use hash_db::Prefix;
@@ -36,15 +37,33 @@ use kvdb::DBValue;
 
 use sc_client_api::backend::StateBackend;
 use sp_blockchain::{Backend as _, HeaderBackend as _};
+use sp_core::storage::{ChildInfo, ChildType, PrefixedStorageKey};
 use sp_runtime::{
 	generic::{BlockId, SignedBlock},
-	traits::{Block as BlockT, HashFor, Header as HeaderT},
+	traits::{Block as BlockT, HashFor, Header as HeaderT, NumberFor},
 	Justifications,
 };
+use sp_trie::PrefixedMemoryDB;
 
 pub use self::state_backend::TrieState;
-use self::state_backend::{DbState, StateVault};
-use crate::{database::ReadOnlyDb, error::Result, util::columns};
+use self::{
+	state_backend::{DbState, StateVault},
+	trie_cache::TrieCache,
+};
+use crate::{
+	database::ReadOnlyDb,
+	error::Result,
+	util::{self, columns},
+};
+
+/// Best and last finalized block of the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHead<Block: BlockT> {
+	pub best_hash: Block::Hash,
+	pub best_number: NumberFor<Block>,
+	pub finalized_hash: Block::Hash,
+	pub finalized_number: NumberFor<Block>,
+}
 
 pub struct ReadOnlyBackend<Block: BlockT, D: ReadOnlyDb> {
 	db: Arc<D>,
@@ -58,10 +77,52 @@ where
 	D: ReadOnlyDb + 'static,
 {
 	pub fn new(db: Arc<D>, prefix_keys: bool) -> Self {
-		let vault = Arc::new(StateVault::new(db.clone(), prefix_keys));
+		let vault = Arc::new(StateVault::new(db.clone(), prefix_keys, None));
+		Self { db, storage: vault }
+	}
+
+	/// Create a backend that caches up to `cache_size` bytes of trie nodes,
+	/// shared by all the states it creates.
+	pub fn with_trie_cache(db: Arc<D>, prefix_keys: bool, cache_size: usize) -> Self {
+		let cache = if cache_size > 0 { Some(TrieCache::new(cache_size)) } else { None };
+		let vault = Arc::new(StateVault::new(db.clone(), prefix_keys, cache));
 		Self { db, storage: vault }
 	}
 
+	/// Cache the trie nodes of the post-state of an executed block,
+	/// so that executing its child does not read them from disk.
+	/// Does nothing if the backend has no trie cache.
+	pub fn cache_post_state(&self, mut post_state: PrefixedMemoryDB<HashFor<Block>>) {
+		let nodes = post_state.drain().into_iter().filter(|(_, (_, rc))| *rc > 0).map(|(key, (node, _))| (key, node));
+		self.storage.cache_nodes(nodes);
+	}
+
+	/// Catch up with the node, and read its best and last finalized block from the meta column.
+	pub fn head(&self) -> Result<ChainHead<Block>> {
+		self.db.catch_up_with_primary()?;
+		let meta = util::read_meta::<Block, D>(&*self.db, columns::HEADER)?;
+		Ok(ChainHead {
+			best_hash: meta.best_hash,
+			best_number: meta.best_number,
+			finalized_hash: meta.finalized_hash,
+			finalized_number: meta.finalized_number,
+		})
+	}
+
+	/// Hash of the block numbered `number` in the canonical chain of the node.
+	pub fn canonical_hash(&self, number: u64) -> Result<Option<Block::Hash>> {
+		match self.db.get(columns::KEY_LOOKUP, &util::number_index_key(number)?) {
+			// the lookup key is the block number followed by the block hash
+			Some(lookup_key) if lookup_key.len() > 4 => Ok(Some(Block::Hash::decode(&mut &lookup_key[4..])?)),
+			_ => Ok(None),
+		}
+	}
+
+	/// Hash of the genesis block of the node, if its database has one.
+	pub fn genesis_hash(&self) -> Result<Option<Block::Hash>> {
+		Ok(util::read_genesis_hash(&*self.db)?)
+	}
+
 	/// get a reference to the backing database
 	pub fn backing_db(&self) -> Arc<D> {
 		self.db.clone()
@@ -113,6 +174,32 @@ where
 		self.state_at(hash).map(|state| state.keys(prefix))
 	}
 
+	/// All key-value pairs in the state of a block, including the pairs of its default child tries.
+	/// Keys of child tries are prefixed with the storage key of their child trie.
+	/// Returns `None` if the node does not have the state of the block.
+	pub fn state_pairs(&self, hash: Block::Hash) -> Result<Option<Vec<(Vec<u8>, Vec<u8>)>>> {
+		let state = match self.state_at(hash) {
+			Some(state) => state,
+			None => return Ok(None),
+		};
+		let mut pairs = state.pairs();
+		let child_tries = pairs
+			.iter()
+			.filter_map(|(key, _)| {
+				let (_, child_key) = ChildType::from_prefixed_key(PrefixedStorageKey::new_ref(key))?;
+				Some((key.clone(), ChildInfo::new_default(child_key)))
+			})
+			.collect::<Vec<_>>();
+		for (prefixed_key, child_info) in child_tries {
+			for key in state.child_keys(&child_info, &[]) {
+				if let Some(value) = state.child_storage(&child_info, &key)? {
+					pairs.push(([prefixed_key.as_slice(), key.as_slice()].concat(), value));
+				}
+			}
+		}
+		Ok(Some(pairs))
+	}
+
 	/// Get a block from the canon chain
 	/// This also tries to catch up with the primary rocksdb instance
 	pub fn block(&self, id: &BlockId<Block>) -> Option<SignedBlock<Block>> {
@@ -129,32 +216,44 @@ where
 		&'a self,
 		fun: impl Fn(u32) -> bool + 'a,
 	) -> Result<impl Iterator<Item = SignedBlock<Block>> + 'a> {
-		let readable_db = self.db.clone();
 		self.db.catch_up_with_primary()?;
 		Ok(self.db.iter(super::util::columns::KEY_LOOKUP).take_while(|(_, value)| !value.is_empty()).filter_map(
 			move |(key, value)| {
 				let arr: &[u8; 4] = key[0..4].try_into().ok()?;
 				let num = u32::from_be_bytes(*arr);
 				if key.len() == 4 && fun(num) {
-					let head: Option<Block::Header> = readable_db
-						.get(super::util::columns::HEADER, &value)
-						.map(|bytes| Decode::decode(&mut &bytes[..]).ok())
-						.flatten();
-					let body: Option<Vec<Block::Extrinsic>> = readable_db
-						.get(super::util::columns::BODY, &value)
-						.map(|bytes| Decode::decode(&mut &bytes[..]).ok())
-						.flatten();
-					let justif: Option<Justifications> = readable_db
-						.get(super::util::columns::JUSTIFICATION, &value)
-						.map(|bytes| Decode::decode(&mut &bytes[..]).ok())
-						.flatten();
-					construct_block(head, body, justif)
+					self.read_block(&value)
 				} else {
 					None
 				}
 			},
 		))
 	}
+
+	/// Iterate over the blocks of the canonical chain numbered in `range`, in order.
+	/// Catches up with the primary database first.
+	/// Each number is looked up in the canonical number index,
+	/// so only the blocks in `range` are read instead of every key of `KEY_LOOKUP`.
+	pub fn iter_blocks_in(&self, range: RangeInclusive<u64>) -> Result<impl Iterator<Item = SignedBlock<Block>> + '_> {
+		self.db.catch_up_with_primary()?;
+		// keys of the number index are 32-bit
+		let end = (*range.end()).min(u64::from(u32::MAX));
+		Ok((*range.start()..=end).filter_map(move |num| {
+			let lookup_key = self.db.get(columns::KEY_LOOKUP, &util::number_index_key(num).ok()?)?;
+			self.read_block(&lookup_key)
+		}))
+	}
+
+	/// Read the block stored under `lookup_key`, if its header and body are in the database.
+	fn read_block(&self, lookup_key: &[u8]) -> Option<SignedBlock<Block>> {
+		let head: Option<Block::Header> =
+			self.db.get(columns::HEADER, lookup_key).map(|bytes| Decode::decode(&mut &bytes[..]).ok()).flatten();
+		let body: Option<Vec<Block::Extrinsic>> =
+			self.db.get(columns::BODY, lookup_key).map(|bytes| Decode::decode(&mut &bytes[..]).ok()).flatten();
+		let justif: Option<Justifications> =
+			self.db.get(columns::JUSTIFICATION, lookup_key).map(|bytes| Decode::decode(&mut &bytes[..]).ok()).flatten();
+		construct_block(head, body, justif)
+	}
 }
 
 struct DbGenesisStorage<Block: BlockT>(pub Block::Hash);
@@ -27,6 +27,7 @@ use sp_core::storage::ChildInfo;
 use sp_runtime::traits::{Block as BlockT, HashFor};
 use sp_state_machine::{StateMachineStats, TrieBackend, UsageInfo as StateUsageInfo};
 
+use super::trie_cache::TrieCache;
 use crate::database::ReadOnlyDb;
 
 /// DB-backed patricia trie state, transaction type is an overlay of changes to commit.
@@ -38,6 +39,8 @@ pub struct StateVault<Block: BlockT, D: ReadOnlyDb> {
 	/// disk backend
 	pub db: Arc<D>,
 	prefix_keys: bool,
+	/// trie nodes shared by all states read from this vault
+	cache: Option<TrieCache>,
 	_marker: PhantomData<Block>,
 }
 
@@ -46,8 +49,16 @@ where
 	Block: BlockT,
 	D: ReadOnlyDb,
 {
-	pub fn new(db: Arc<D>, prefix_keys: bool) -> Self {
-		Self { db, prefix_keys, _marker: PhantomData }
+	pub fn new(db: Arc<D>, prefix_keys: bool, cache: Option<TrieCache>) -> Self {
+		Self { db, prefix_keys, cache, _marker: PhantomData }
+	}
+
+	/// Cache trie nodes written by executing a block,
+	/// so that the next block is executed on top of them without reading them from disk.
+	pub fn cache_nodes(&self, nodes: impl Iterator<Item = (Vec<u8>, DBValue)>) {
+		if let Some(cache) = self.cache.as_ref().filter(|_| self.prefix_keys) {
+			nodes.for_each(|(key, node)| cache.insert(key, node));
+		}
 	}
 }
 
@@ -57,11 +68,11 @@ where
 	D: ReadOnlyDb,
 {
 	fn get(&self, key: &Block::Hash, prefix: Prefix) -> Result<Option<DBValue>, String> {
-		if self.prefix_keys {
-			let key = sp_trie::prefixed_key::<HashFor<Block>>(key, prefix);
-			Ok(self.db.get(super::columns::STATE, &key))
-		} else {
-			Ok(self.db.get(super::columns::STATE, key.as_ref()))
+		let key =
+			if self.prefix_keys { sp_trie::prefixed_key::<HashFor<Block>>(key, prefix) } else { key.as_ref().to_vec() };
+		match &self.cache {
+			Some(cache) => Ok(cache.get_or_load(key, |key| self.db.get(super::columns::STATE, key))),
+			None => Ok(self.db.get(super::columns::STATE, &key)),
 		}
 	}
 }
@@ -0,0 +1,95 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cache of trie nodes shared by every state created from a backend.
+//! Trie nodes are keyed by the hash of their contents, so a cached node never becomes stale
+//! and the cache can be shared between blocks and threads without invalidation.
+
+use kvdb::DBValue;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+pub struct TrieCache {
+	inner: Mutex<Inner>,
+	/// Maximum size of the cached keys and nodes, in bytes.
+	budget: usize,
+}
+
+struct Inner {
+	nodes: LruCache<Vec<u8>, DBValue>,
+	size: usize,
+}
+
+impl TrieCache {
+	/// Create a cache holding up to `budget` bytes of trie nodes.
+	pub fn new(budget: usize) -> Self {
+		Self { inner: Mutex::new(Inner { nodes: LruCache::unbounded(), size: 0 }), budget }
+	}
+
+	/// Get a node from the cache, or `load` it and cache it on a miss.
+	pub fn get_or_load(&self, key: Vec<u8>, load: impl FnOnce(&[u8]) -> Option<DBValue>) -> Option<DBValue> {
+		if let Some(node) = self.inner.lock().nodes.get(&key) {
+			return Some(node.clone());
+		}
+		let node = load(&key)?;
+		self.insert(key, node.clone());
+		Some(node)
+	}
+
+	/// Insert a node, evicting the least recently used nodes if the cache is over budget.
+	pub fn insert(&self, key: Vec<u8>, node: DBValue) {
+		let len = key.len() + node.len();
+		if len > self.budget {
+			return;
+		}
+		let mut inner = self.inner.lock();
+		if let Some(old) = inner.nodes.put(key.clone(), node) {
+			inner.size -= key.len() + old.len();
+		}
+		inner.size += len;
+		while inner.size > self.budget {
+			match inner.nodes.pop_lru() {
+				Some((k, v)) => inner.size -= k.len() + v.len(),
+				None => break,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_evict_least_recently_used_over_budget() {
+		let cache = TrieCache::new(12);
+		cache.insert(vec![1], vec![0; 3]);
+		cache.insert(vec![2], vec![0; 3]);
+		// touch the first node so the second one is evicted
+		assert!(cache.get_or_load(vec![1], |_| None).is_some());
+		cache.insert(vec![3], vec![0; 3]);
+		assert!(cache.get_or_load(vec![1], |_| None).is_some());
+		assert!(cache.get_or_load(vec![2], |_| None).is_none());
+		assert!(cache.get_or_load(vec![3], |_| None).is_some());
+	}
+
+	#[test]
+	fn should_cache_loaded_nodes() {
+		let cache = TrieCache::new(1024);
+		assert_eq!(cache.get_or_load(vec![1], |_| Some(vec![42])), Some(vec![42]));
+		assert_eq!(cache.get_or_load(vec![1], |_| panic!("node should be cached")), Some(vec![42]));
+	}
+}
@@ -16,18 +16,15 @@
 //! A cache of runtime versions
 //! Will only call the `runtime_version` function once per wasm blob
 
-use std::{
-	collections::hash_map::DefaultHasher,
-	hash::{Hash, Hasher as _},
-	sync::Arc,
-};
+use std::sync::Arc;
 
 use arc_swap::ArcSwap;
 use codec::Decode;
 use hashbrown::HashMap;
+use parking_lot::Mutex;
 
 use sc_executor::{WasmExecutionMethod, WasmExecutor};
-use sp_core::traits::ReadRuntimeVersion;
+use sp_core::{hexdisplay::HexDisplay, traits::ReadRuntimeVersion};
 use sp_runtime::{
 	generic::SignedBlock,
 	traits::{Block as BlockT, Header as _, NumberFor},
@@ -40,18 +37,26 @@ use sp_wasm_interface::HostFunctions;
 use crate::{
 	database::ReadOnlyDb,
 	error::{BackendError, Result},
+	frontend::RuntimeConfig,
 	read_only_backend::ReadOnlyBackend,
 };
 
+/// Blake2-256 hash of a WASM blob.
+pub type CodeHash = [u8; 32];
+
 pub struct RuntimeVersionCache<B: BlockT, D: ReadOnlyDb> {
 	/// Hash of the WASM Blob -> RuntimeVersion
-	versions: ArcSwap<HashMap<u64, RuntimeVersion>>,
+	versions: ArcSwap<HashMap<CodeHash, RuntimeVersion>>,
+	/// Versions read from a WASM blob since the last call to `take_new_versions`
+	new_versions: Mutex<Vec<(CodeHash, RuntimeVersion)>>,
 	backend: Arc<ReadOnlyBackend<B, D>>,
 	exec: WasmExecutor,
 }
 
 impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
-	pub fn new(backend: Arc<ReadOnlyBackend<B, D>>) -> Self {
+	/// Create a cache reading versions with an interpreted runtime,
+	/// or a compiled runtime cached on disk if `config` compiles runtimes and has a `wasm_cache_path`.
+	pub fn new(backend: Arc<ReadOnlyBackend<B, D>>, config: &RuntimeConfig) -> Self {
 		// all _available_ functions
 		// sp_io::storage::HostFunctions
 		// sp_io::default_child_storage
@@ -75,8 +80,28 @@ impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
 			.collect::<Vec<_>>();
 
 		// TODO: https://github.com/paritytech/substrate-archive/issues/247
-		let exec = WasmExecutor::new(WasmExecutionMethod::Interpreted, Some(128), funs, 1, None);
-		Self { versions: ArcSwap::from_pointee(HashMap::new()), backend, exec }
+		// compiling a runtime only pays off if the artifact is kept across restarts.
+		let cache_dir = config.wasm_cache_dir(&funs);
+		let method = if cache_dir.is_some() { WasmExecutionMethod::Compiled } else { WasmExecutionMethod::Interpreted };
+		let exec = WasmExecutor::new(method, Some(128), funs, 1, cache_dir);
+		Self { versions: ArcSwap::from_pointee(HashMap::new()), new_versions: Mutex::new(Vec::new()), backend, exec }
+	}
+
+	/// Add versions that are already known, for instance from a previous run,
+	/// so that their WASM blobs don't need to be executed again.
+	pub fn seed(&self, versions: impl IntoIterator<Item = (CodeHash, RuntimeVersion)>) {
+		let versions = versions.into_iter().collect::<Vec<_>>();
+		self.versions.rcu(|cache| {
+			let mut cache = HashMap::clone(&cache);
+			cache.extend(versions.iter().cloned());
+			cache
+		});
+	}
+
+	/// Take the versions that were read from a WASM blob since the last call,
+	/// to persist them.
+	pub fn take_new_versions(&self) -> Vec<(CodeHash, RuntimeVersion)> {
+		std::mem::take(&mut *self.new_versions.lock())
 	}
 
 	/// Get a version of the runtime for some Block Hash
@@ -86,11 +111,11 @@ impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
 		// Getting code from the backend is the slowest part of this. Takes an average of 6ms
 		let code = self.backend.storage(hash, well_known_keys::CODE).ok_or(BackendError::StorageNotExist)?;
 
-		let code_hash = make_hash(&code);
+		let code_hash = sp_core::hashing::blake2_256(&code);
 		if self.versions.load().contains_key(&code_hash) {
 			Ok(self.versions.load().get(&code_hash).cloned())
 		} else {
-			log::debug!("Adding new runtime code hash to cache: {:#X?}", code_hash);
+			log::debug!("Adding new runtime code hash to cache: 0x{}", HexDisplay::from(&code_hash));
 			let mut ext = BasicExternalities::default();
 			ext.register_extension(sp_core::traits::ReadRuntimeVersionExt::new(self.exec.clone()));
 			let version = decode_version(self.exec.read_runtime_version(&code, &mut ext)?.as_slice())?;
@@ -100,6 +125,7 @@ impl<B: BlockT, D: ReadOnlyDb + 'static> RuntimeVersionCache<B, D> {
 				cache.insert(code_hash, version.clone());
 				cache
 			});
+			self.new_versions.lock().push((code_hash, version.clone()));
 			Ok(Some(version))
 		}
 	}
@@ -165,10 +191,3 @@ fn decode_version(version: &[u8]) -> Result<sp_version::RuntimeVersion> {
 		Ok(v)
 	}
 }
-
-// Make a hash out of a byte string using the default hasher.
-fn make_hash<K: Hash + ?Sized>(val: &K) -> u64 {
-	let mut state = DefaultHasher::new();
-	val.hash(&mut state);
-	state.finish()
-}
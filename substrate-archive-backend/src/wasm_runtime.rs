@@ -0,0 +1,116 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A runtime without native code, for archives of chains whose native runtime is not linked.
+//!
+//! Only the client side of the runtime api generated by `impl_runtime_apis!` is used,
+//! which calls into the on-chain Wasm by name. The native implementations are never executed,
+//! since the native version never matches the on-chain runtime and [`Executor`] rejects every native call.
+
+use sc_executor::{error::Error as ExecutorError, NativeExecutionDispatch, NativeVersion};
+use sp_api::impl_runtime_apis;
+use sp_core::OpaqueMetadata;
+use sp_externalities::Externalities;
+use sp_inherents::{CheckInherentsResult, InherentData};
+use sp_runtime::{
+	create_runtime_str, generic,
+	traits::{BlakeTwo256, Block as BlockT},
+	ApplyExtrinsicResult, OpaqueExtrinsic,
+};
+use sp_version::RuntimeVersion;
+
+/// Block numbers are assumed to be `u32`, like in the default Substrate node.
+pub type BlockNumber = u32;
+/// Header of a chain hashing with Blake2-256.
+pub type Header = generic::Header<BlockNumber, BlakeTwo256>;
+/// A block whose extrinsics are not decoded.
+pub type Block = generic::Block<Header, OpaqueExtrinsic>;
+
+/// Version of the native runtime. Its spec name does not match any chain, so Wasm is always used.
+pub const VERSION: RuntimeVersion = RuntimeVersion {
+	spec_name: create_runtime_str!("substrate-archive-wasm-only"),
+	impl_name: create_runtime_str!("substrate-archive-wasm-only"),
+	authoring_version: 0,
+	spec_version: 0,
+	impl_version: 0,
+	apis: RUNTIME_API_VERSIONS,
+	transaction_version: 0,
+};
+
+/// Body of the native implementations of the runtime api.
+///
+/// Native calls only reach the implementations through [`NativeExecutionDispatch::dispatch`],
+/// and [`Executor::dispatch`] rejects every method without calling into them.
+fn no_native() -> ! {
+	unreachable!("the Wasm-only runtime has no native code, `Executor::dispatch` rejects every call")
+}
+
+pub struct Runtime;
+
+impl_runtime_apis! {
+	impl sp_api::Core<Block> for Runtime {
+		fn version() -> RuntimeVersion {
+			VERSION
+		}
+
+		fn execute_block(_: Block) {
+			no_native()
+		}
+
+		fn initialize_block(_: &<Block as BlockT>::Header) {
+			no_native()
+		}
+	}
+
+	impl sp_api::Metadata<Block> for Runtime {
+		fn metadata() -> OpaqueMetadata {
+			no_native()
+		}
+	}
+
+	impl sp_block_builder::BlockBuilder<Block> for Runtime {
+		fn apply_extrinsic(_: <Block as BlockT>::Extrinsic) -> ApplyExtrinsicResult {
+			no_native()
+		}
+
+		fn finalize_block() -> <Block as BlockT>::Header {
+			no_native()
+		}
+
+		fn inherent_extrinsics(_: InherentData) -> Vec<<Block as BlockT>::Extrinsic> {
+			no_native()
+		}
+
+		fn check_inherents(_: Block, _: InherentData) -> CheckInherentsResult {
+			no_native()
+		}
+	}
+}
+
+/// Executor that refuses every native call, so that the on-chain Wasm is executed instead.
+pub struct Executor;
+
+impl NativeExecutionDispatch for Executor {
+	type ExtendHostFunctions = ();
+
+	fn dispatch(_: &mut dyn Externalities, method: &str, _: &[u8]) -> sc_executor::error::Result<Vec<u8>> {
+		Err(ExecutorError::MethodNotFound(method.to_owned()))
+	}
+
+	fn native_version() -> NativeVersion {
+		NativeVersion { runtime_version: VERSION, can_author_with: Default::default() }
+	}
+}
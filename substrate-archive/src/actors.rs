@@ -19,20 +19,31 @@
 mod actor_pool;
 mod workers;
 
-use std::{marker::PhantomData, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+use std::{
+	convert::TryFrom,
+	marker::PhantomData,
+	panic::AssertUnwindSafe,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
 
+use codec::Decode;
 use coil::Job as _;
 use futures::{future::BoxFuture, FutureExt};
 use hashbrown::HashSet;
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use xtra::{prelude::*, spawn::Smol, Disconnected};
 
 use sc_client_api::backend;
-use sp_api::{ApiExt, ConstructRuntimeApi};
+use sp_api::{ApiExt, ConstructRuntimeApi, RuntimeVersion};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
-use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::traits::{Block as BlockT, NumberFor};
 
-use substrate_archive_backend::{ApiAccess, Meta, ReadOnlyBackend, ReadOnlyDb};
+use substrate_archive_backend::{ApiAccess, Meta, ReadOnlyBackend, ReadOnlyDb, RuntimeConfig};
 
 use self::workers::GetState;
 pub use self::{
@@ -41,9 +52,13 @@ pub use self::{
 };
 use crate::{
 	archive::Archive,
-	database::{models::BlockModelDecoder, queries, Channel, Listener},
+	database::{
+		self,
+		queries::{self, Stage},
+		Channel, DatabaseConfig, DbConn, Listener,
+	},
 	error::Result,
-	tasks::{Environment, TaskExecutor},
+	tasks::{Env, Environment, TaskExecutor},
 	types::Die,
 };
 
@@ -57,10 +72,13 @@ where
 	B::Hash: Unpin,
 {
 	pub backend: Arc<ReadOnlyBackend<B, D>>,
-	pub pg_url: String,
+	pub database: DatabaseConfig,
 	pub meta: Meta<B>,
 	pub control: ControlConfig,
+	pub runtime: RuntimeConfig,
 	pub tracing_targets: Option<String>,
+	/// Version of the native runtime, if blocks are executed both natively and with Wasm to compare the results.
+	pub native_version: Option<RuntimeVersion>,
 }
 
 impl<B: BlockT + Unpin, D: ReadOnlyDb> Clone for SystemConfig<B, D>
@@ -70,15 +88,32 @@ where
 	fn clone(&self) -> SystemConfig<B, D> {
 		SystemConfig {
 			backend: Arc::clone(&self.backend),
-			pg_url: self.pg_url.clone(),
+			database: self.database.clone(),
 			meta: self.meta.clone(),
 			control: self.control,
+			runtime: self.runtime.clone(),
 			tracing_targets: self.tracing_targets.clone(),
+			native_version: self.native_version.clone(),
 		}
 	}
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+/// Which blocks are archived, with regard to the finality of the node.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FinalityMode {
+	/// Archive and execute every block of the canonical chain, marking blocks `finalized` as finality advances.
+	All,
+	/// Only archive and execute finalized blocks.
+	Finalized,
+}
+
+impl Default for FinalityMode {
+	fn default() -> Self {
+		Self::All
+	}
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 pub struct ControlConfig {
 	/// Number of database actors to be spawned in the actor pool.
 	#[serde(default = "default_db_actor_pool_size")]
@@ -93,9 +128,53 @@ pub struct ControlConfig {
 	/// Maximum tasks to queue in the threadpool.
 	#[serde(default = "default_task_workers")]
 	pub(crate) max_tasks: usize,
-	/// Maximum amount of blocks to index at once.
-	#[serde(default = "default_max_block_load")]
-	pub(crate) max_block_load: u32,
+	/// Memory to spend on blocks loaded from the chain data but not yet inserted into the database, in MB.
+	#[serde(default = "default_crawl_memory_budget")]
+	pub(crate) crawl_memory_budget: usize,
+	/// Deprecated: number of blocks to load at once.
+	/// Converted into `crawl_memory_budget`, which it overrides, when the archive is built.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub(crate) max_block_load: Option<u32>,
+	/// Number of queued tasks above which backfilling pauses until the queue drains.
+	#[serde(default = "default_max_queued_blocks")]
+	pub(crate) max_queued_blocks: i64,
+	/// Blocks within this distance of the best block of the node are crawled first,
+	/// and executed ahead of the historical blocks.
+	#[serde(default = "default_tip_distance")]
+	pub(crate) tip_distance: u32,
+	/// Number of workers executing the blocks near the tip, besides the `task_workers` of coil.
+	#[serde(default = "default_tip_workers")]
+	pub(crate) tip_workers: usize,
+	/// Milliseconds between catching up with the node to check whether its head advanced.
+	#[serde(default = "default_catch_up_interval")]
+	pub(crate) catch_up_interval: u64,
+	/// Longest time between catching up with the node, in milliseconds.
+	/// The interval doubles up to this while the head of the node does not advance.
+	#[serde(default = "default_max_catch_up_backoff")]
+	pub(crate) max_catch_up_backoff: u64,
+	/// Whether to archive every block, or only finalized blocks.
+	#[serde(default)]
+	pub(crate) finality: FinalityMode,
+	/// Number of contiguous blocks executed in order by one task.
+	/// Above 1, historical blocks are executed sequentially in ranges of this many blocks,
+	/// each on top of the post-state its parent left in the trie cache.
+	/// Blocks near the tip are always executed on their own.
+	#[serde(default = "default_execution_range")]
+	pub(crate) execution_range: u32,
+	/// Store each distinct storage value once in `storage_values`
+	/// and reference it by hash from `storage`.
+	#[serde(default)]
+	pub(crate) dedup_storage: bool,
+	/// Times a block that failed to execute is retried before it is marked dead.
+	#[serde(default = "default_max_retries")]
+	pub(crate) max_retries: u32,
+	/// Seconds to wait before retrying a failed block. Doubles with every attempt.
+	#[serde(default = "default_retry_backoff")]
+	pub(crate) retry_backoff: u64,
+	/// Verify that executing a block results in the state root stated in its header.
+	/// Storage of blocks that don't is recorded in `state_root_mismatches` instead of being archived.
+	#[serde(default)]
+	pub(crate) verify_state_root: bool,
 }
 
 impl Default for ControlConfig {
@@ -105,11 +184,37 @@ impl Default for ControlConfig {
 			task_workers: default_task_workers(),
 			task_timeout: default_task_timeout(),
 			max_tasks: default_max_tasks(),
-			max_block_load: default_max_block_load(),
+			crawl_memory_budget: default_crawl_memory_budget(),
+			max_block_load: None,
+			max_queued_blocks: default_max_queued_blocks(),
+			tip_distance: default_tip_distance(),
+			tip_workers: default_tip_workers(),
+			catch_up_interval: default_catch_up_interval(),
+			max_catch_up_backoff: default_max_catch_up_backoff(),
+			finality: FinalityMode::default(),
+			execution_range: default_execution_range(),
+			dedup_storage: false,
+			max_retries: default_max_retries(),
+			retry_backoff: default_retry_backoff(),
+			verify_state_root: false,
+		}
+	}
+}
+
+impl ControlConfig {
+	/// Replace `crawl_memory_budget` with the memory taken by the deprecated `max_block_load` blocks, if it is set.
+	pub(crate) fn apply_max_block_load(&mut self) {
+		if let Some(blocks) = self.max_block_load.take() {
+			log::warn!("`max_block_load` is deprecated, set `crawl_memory_budget` instead");
+			self.crawl_memory_budget = (blocks as usize * BLOCK_SIZE_ESTIMATE / (1024 * 1024)).max(1);
 		}
 	}
 }
 
+/// Estimated memory taken by a loaded block, in bytes,
+/// which converts the deprecated `max_block_load` into a `crawl_memory_budget`.
+const BLOCK_SIZE_ESTIMATE: usize = 2 * 1024;
+
 const fn default_db_actor_pool_size() -> usize {
 	4
 }
@@ -126,30 +231,78 @@ const fn default_max_tasks() -> usize {
 	64
 }
 
-const fn default_max_block_load() -> u32 {
+const fn default_crawl_memory_budget() -> usize {
+	256
+}
+
+const fn default_max_queued_blocks() -> i64 {
 	100_000
 }
 
+const fn default_tip_distance() -> u32 {
+	64
+}
+
+const fn default_tip_workers() -> usize {
+	1
+}
+
+const fn default_catch_up_interval() -> u64 {
+	500
+}
+
+const fn default_max_catch_up_backoff() -> u64 {
+	6000
+}
+
+const fn default_execution_range() -> u32 {
+	1
+}
+
+const fn default_max_retries() -> u32 {
+	5
+}
+
+const fn default_retry_backoff() -> u64 {
+	30
+}
+
+/// How often failed blocks that are due are re-queued, the indexer cursors advanced,
+/// the digests of blocks archived before digests were decoded are decoded,
+/// and newly crawled blocks queued in ranges when executing sequentially.
+const RETRY_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Maximum number of archived blocks whose digest is decoded by one sweep.
+const DECODE_BATCH_SIZE: i64 = 1000;
+
+/// How often the tip queue is checked while it is empty.
+const TIP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Maximum number of blocks taken off the tip queue at once.
+const TIP_BATCH_SIZE: i64 = 16;
+
 impl<B: BlockT + Unpin, D: ReadOnlyDb> SystemConfig<B, D>
 where
 	B::Hash: Unpin,
 {
 	pub fn new(
 		backend: Arc<ReadOnlyBackend<B, D>>,
-		pg_url: String,
+		database: DatabaseConfig,
 		meta: Meta<B>,
 		control: ControlConfig,
+		runtime: RuntimeConfig,
 		tracing_targets: Option<String>,
+		native_version: Option<RuntimeVersion>,
 	) -> Self {
-		Self { backend, pg_url, meta, control, tracing_targets }
+		Self { backend, database, meta, control, runtime, tracing_targets, native_version }
 	}
 
 	pub fn backend(&self) -> &Arc<ReadOnlyBackend<B, D>> {
 		&self.backend
 	}
 
-	pub fn pg_url(&self) -> &str {
-		self.pg_url.as_str()
+	pub fn database(&self) -> &DatabaseConfig {
+		&self.database
 	}
 
 	pub fn meta(&self) -> &Meta<B> {
@@ -160,7 +313,7 @@ where
 struct Actors<B: BlockT + Unpin, D: ReadOnlyDb + 'static>
 where
 	B::Hash: Unpin,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	storage: Address<workers::StorageAggregator<B>>,
 	blocks: Address<workers::BlocksIndexer<B, D>>,
@@ -175,7 +328,7 @@ where
 	D: ReadOnlyDb + 'static,
 	B: BlockT + Unpin,
 	B::Hash: Unpin,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	config: SystemConfig<B, D>,
 	start_tx: flume::Sender<()>,
@@ -197,7 +350,7 @@ where
 		+ Sync
 		+ 'static,
 	C: ApiAccess<B, ReadOnlyBackend<B, D>, R> + 'static,
-	NumberFor<B>: Into<u32> + From<u32> + Unpin,
+	NumberFor<B>: Into<u64> + From<u32> + Unpin,
 	B::Hash: Unpin,
 	B::Header: serde::de::DeserializeOwned,
 {
@@ -242,26 +395,64 @@ where
 	async fn main_loop(conf: SystemConfig<B, D>, rx: flume::Receiver<()>, client: Arc<C>) -> Result<()> {
 		let actors = Self::spawn_actors(conf.clone()).await?;
 		let pool = actors.db_pool.send(GetState::Pool.into()).await??.pool();
-		let listener = Self::init_listeners(conf.pg_url()).await?;
 		let mut conn = pool.acquire().await?;
-		Self::restore_missing_storage(&mut *conn).await?;
+		// highest block number the sweep has queued in ranges. The listener queues the blocks crawled below it.
+		let queued_up_to = Arc::new(AtomicU64::new(0));
+		if conf.control.execution_range > 1 {
+			let crawled = queries::advance_cursor(&mut *conn, Stage::Crawled).await?;
+			queued_up_to.store(crawled.unwrap_or(0), Ordering::SeqCst);
+		}
+		let listener = Self::init_listeners(&conf, queued_up_to.clone()).await?;
+		queries::release_tip_blocks(&mut *conn).await?;
+		Self::restore_missing_storage(&mut *conn, conf.control.execution_range).await?;
 		let env = Environment::<B, R, C, D>::new(
 			conf.backend().clone(),
 			client,
 			actors.storage.clone(),
+			pool.clone(),
 			conf.tracing_targets.clone(),
+			conf.control.max_retries,
+			conf.control.retry_backoff,
+			conf.control.verify_state_root,
+			conf.native_version.clone(),
 		);
+		let tip_env = Arc::new(AssertUnwindSafe(env.clone()));
+		let tip_workers = (0..conf.control.tip_workers)
+			.map(|_| smol::spawn(Self::execute_tip_blocks(tip_env.clone(), pool.clone())))
+			.collect::<Vec<_>>();
 		let env = AssertUnwindSafe(env);
 
 		let runner = coil::Runner::builder(env, TaskExecutor, &pool)
 			.register_job::<crate::tasks::execute_block::Job<B, R, C, D>>()
+			.register_job::<crate::tasks::execute_block_range::Job<B, R, C, D>>()
 			.num_threads(conf.control.task_workers)
 			// times out if tasks don't start execution on the threadpool within 20 seconds.
 			.timeout(Duration::from_secs(conf.control.task_timeout))
 			.max_tasks(conf.control.max_tasks)
 			.build()?;
 
+		let mut last_sweep = std::time::Instant::now();
 		loop {
+			if last_sweep.elapsed() >= RETRY_SWEEP_INTERVAL {
+				let mut conn = pool.acquire().await?;
+				if let Err(e) = Self::retry_failed_blocks(&mut *conn).await {
+					log::error!("Failed to re-queue failed blocks: {:?}", e);
+				}
+				if let Err(e) = Self::decode_archived_digests(&mut conn, conf.backend().clone()).await {
+					log::error!("Failed to decode the digests of archived blocks: {:?}", e);
+				}
+				for stage in &[Stage::Crawled, Stage::Executed, Stage::Decoded] {
+					if let Err(e) = queries::step_cursor(&mut *conn, *stage, queries::CURSOR_STEP_BLOCKS).await {
+						log::error!("Failed to advance the {:?} cursor: {:?}", stage, e);
+					}
+				}
+				if conf.control.execution_range > 1 {
+					if let Err(e) = Self::queue_block_ranges(&mut *conn, &queued_up_to, &conf.control).await {
+						log::error!("Failed to queue block ranges: {:?}", e);
+					}
+				}
+				last_sweep = std::time::Instant::now();
+			}
 			let tasks = runner.run_all_sync_tasks().fuse();
 			futures::pin_mut!(tasks);
 			futures::select! {
@@ -278,13 +469,16 @@ where
 				_ = rx.recv_async() => break,
 			}
 		}
+		for worker in tip_workers {
+			worker.cancel().await;
+		}
 		Self::kill_actors(actors).await?;
 		listener.kill_async().await;
 		Ok(())
 	}
 
 	async fn spawn_actors(conf: SystemConfig<B, D>) -> Result<Actors<B, D>> {
-		let db = workers::DatabaseActor::<B>::new(conf.pg_url().into()).await?;
+		let db = workers::DatabaseActor::<B>::new(conf.database(), conf.control.dedup_storage).await?;
 		let db_pool =
 			actor_pool::ActorPool::new(db, conf.control.db_actor_pool_size).create(None).spawn(&mut Smol::Global);
 		let storage = workers::StorageAggregator::new(db_pool.clone()).create(None).spawn(&mut Smol::Global);
@@ -309,12 +503,26 @@ where
 		Ok(())
 	}
 
-	async fn init_listeners(pg_url: &str) -> Result<Listener> {
-		Listener::builder(pg_url, move |notif, conn| {
+	/// Queue new blocks for execution.
+	/// Blocks within `tip_distance` of the best block of the node are queued in the tip queue.
+	/// When executing sequentially, other blocks are left to be queued in ranges by the sweep,
+	/// unless they were crawled below `queued_up_to`, which the sweep has already passed.
+	async fn init_listeners(conf: &SystemConfig<B, D>, queued_up_to: Arc<AtomicU64>) -> Result<Listener> {
+		let backend = conf.backend().clone();
+		let tip_distance = conf.control.tip_distance;
+		let sequential = conf.control.execution_range > 1;
+		Listener::builder(conf.database(), move |notif, conn| {
+			let backend = backend.clone();
+			let queued_up_to = queued_up_to.clone();
 			async move {
 				let sql_block = queries::get_full_block_by_id(conn, notif.id).await?;
-				let b = sql_block.into_block_and_spec()?;
-				crate::tasks::execute_block::<B, R, C, D>(b.0, PhantomData).enqueue(conn).await?;
+				let (hash, block_num) = sql_block.hash_and_number::<B>()?;
+				let best: u64 = backend.info().best_number.into();
+				if best.saturating_sub(block_num) <= u64::from(tip_distance) {
+					queries::enqueue_tip_block(conn, hash.as_ref(), block_num).await?;
+				} else if !sequential || block_num <= queued_up_to.load(Ordering::SeqCst) {
+					crate::tasks::execute_block::<B, R, C, D>(hash, block_num, PhantomData).enqueue(conn).await?;
+				}
 				Ok(())
 			}
 			.boxed()
@@ -327,30 +535,197 @@ where
 	/// Checks if any blocks that should be executed are missing
 	/// from the task queue.
 	/// If any are found, they are re-queued.
-	async fn restore_missing_storage(conn: &mut sqlx::PgConnection) -> Result<()> {
-		let blocks: HashSet<u32> = queries::get_all_blocks::<B>(conn)
-			.await?
-			.map(|b| Ok((*b?.header().number()).into()))
-			.collect::<Result<_>>()?;
-		let mut missing_storage_blocks = queries::blocks_storage_intersection(conn).await?;
-		let difference: HashSet<u32> = missing_storage_blocks
+	/// Blocks are re-queued in ranges of `execution_range` blocks when executing sequentially.
+	async fn restore_missing_storage(conn: &mut sqlx::PgConnection, execution_range: u32) -> Result<()> {
+		queries::advance_cursor(conn, Stage::Executed).await?;
+		let skipped = queries::skipped_block_hashes(conn).await?;
+		let mut missing_storage_blocks = queries::unexecuted_blocks(conn).await?;
+		// failed blocks are re-queued by the retry sweep
+		missing_storage_blocks.retain(|(hash, _)| !skipped.contains(hash));
+		if missing_storage_blocks.is_empty() {
+			log::info!("No missing storage to restore");
+			return Ok(());
+		}
+		let blocks = queries::queued_block_numbers(conn).await?;
+		let difference: HashSet<u64> = missing_storage_blocks
 			.iter()
-			.map(|b| b.block_num as u32)
-			.collect::<HashSet<u32>>()
+			.map(|(_, num)| *num)
+			.collect::<HashSet<u64>>()
 			.difference(&blocks)
 			.copied()
 			.collect();
-		missing_storage_blocks.retain(|b| difference.contains(&(b.block_num as u32)));
-		let jobs: Vec<crate::tasks::execute_block::Job<B, R, C, D>> =
-			BlockModelDecoder::with_vec(missing_storage_blocks)?
-				.into_iter()
-				.map(|b| crate::tasks::execute_block::<B, R, C, D>(b.inner.block, PhantomData))
-				.collect();
-		log::info!("Restoring {} missing storage entries. This could take a few minutes...", jobs.len());
-		coil::JobExt::enqueue_batch(jobs, &mut *conn).await?;
+		missing_storage_blocks.retain(|(_, num)| difference.contains(num));
+		log::info!(
+			"Restoring {} missing storage entries. This could take a few minutes...",
+			missing_storage_blocks.len()
+		);
+		let blocks = Self::decode_block_refs(missing_storage_blocks)?;
+		if execution_range > 1 {
+			coil::JobExt::enqueue_batch(Self::execute_range_jobs(blocks, execution_range), &mut *conn).await?;
+		} else {
+			coil::JobExt::enqueue_batch(Self::execute_block_jobs(blocks), &mut *conn).await?;
+		}
 		log::info!("Storage restored");
 		Ok(())
 	}
+
+	/// Queue the blocks crawled above `queued_up_to` in ranges of `execution_range` blocks,
+	/// up to the `crawled` cursor and as long as the task queue holds fewer than `max_queued_blocks` blocks.
+	async fn queue_block_ranges(
+		conn: &mut sqlx::PgConnection,
+		queued_up_to: &AtomicU64,
+		control: &ControlConfig,
+	) -> Result<()> {
+		let from = queued_up_to.load(Ordering::SeqCst);
+		let crawled = match queries::cursor(conn, Stage::Crawled).await? {
+			Some(crawled) if crawled > from => crawled,
+			_ => return Ok(()),
+		};
+		let queued = queries::queued_tasks(conn).await?.saturating_mul(i64::from(control.execution_range));
+		let room = control.max_queued_blocks.saturating_sub(queued);
+		if room <= 0 {
+			return Ok(());
+		}
+		let to = crawled.min(from.saturating_add(u64::try_from(room).unwrap_or(u64::MAX)));
+		// blocks crawled below `to` from now on are queued by the listener
+		queued_up_to.store(to, Ordering::SeqCst);
+
+		// coil holds no jobs above `from`: blocks below the crawled cursor on startup are queued by
+		// `restore_missing_storage`, and later blocks by the listener only once `queued_up_to` passed them.
+		let queued = queries::tip_block_numbers(conn, from + 1, to).await?;
+		// failed blocks are re-queued by the retry sweep
+		let skipped = queries::skipped_block_hashes(conn).await?;
+		let blocks = queries::unexecuted_block_refs(conn, from + 1, to)
+			.await?
+			.into_iter()
+			.filter(|(hash, num)| !queued.contains(num) && !skipped.contains(hash));
+		let blocks = Self::decode_block_refs(blocks)?;
+		if blocks.is_empty() {
+			return Ok(());
+		}
+		log::debug!("Queueing {} blocks up to {} for sequential execution", blocks.len(), to);
+		coil::JobExt::enqueue_batch(Self::execute_range_jobs(blocks, control.execution_range), conn).await?;
+		Ok(())
+	}
+
+	/// Re-queues failed blocks whose retry is due,
+	/// and forgets about failed blocks that have since been executed successfully.
+	async fn retry_failed_blocks(conn: &mut sqlx::PgConnection) -> Result<()> {
+		queries::clear_recovered_blocks(conn).await?;
+		let blocks = queries::take_due_failed_blocks(conn).await?;
+		if blocks.is_empty() {
+			return Ok(());
+		}
+		let jobs = Self::execute_block_jobs(Self::decode_block_refs(blocks)?);
+		log::info!("Retrying {} failed blocks", jobs.len());
+		coil::JobExt::enqueue_batch(jobs, &mut *conn).await?;
+		Ok(())
+	}
+
+	/// Execute the blocks queued at the tip of the chain as they come in,
+	/// ahead of the tasks of coil. Each of the `tip_workers` runs this loop.
+	async fn execute_tip_blocks(env: Arc<Env<B, R, C, D>>, pool: sqlx::PgPool) {
+		loop {
+			match Self::execute_tip_batch(&env, &pool).await {
+				Ok(0) => smol::Timer::after(TIP_POLL_INTERVAL).await,
+				Ok(n) => log::debug!("Executed {} blocks at the tip", n),
+				Err(e) => {
+					log::error!("Failed to take blocks off the tip queue: {:?}", e);
+					smol::Timer::after(TIP_POLL_INTERVAL).await
+				}
+			};
+		}
+	}
+
+	/// Execute a batch of blocks taken off the tip queue, returning how many were taken.
+	///
+	/// Blocks that fail to execute are recorded in `failed_blocks` and retried by the retry sweep,
+	/// so they are removed from the queue like executed blocks.
+	/// Blocks that can't be removed are released to be taken again.
+	async fn execute_tip_batch(env: &Arc<Env<B, R, C, D>>, pool: &sqlx::PgPool) -> Result<usize> {
+		let mut conn = pool.acquire().await?;
+		let blocks = queries::take_tip_blocks(&mut *conn, TIP_BATCH_SIZE).await?;
+		std::mem::drop(conn);
+		let taken = blocks.len();
+		for (hash, number) in blocks {
+			match B::Hash::decode(&mut hash.as_slice()) {
+				Ok(block) => {
+					let env = env.clone();
+					if let Err(e) = smol::unblock(move || crate::tasks::execute_ref(&env, block, number)).await {
+						log::error!("Failed to execute block {} at the tip: {}", number, e);
+					}
+				}
+				Err(e) => log::error!("Dropping block {} with an invalid hash from the tip queue: {}", number, e),
+			}
+			if let Err(e) = Self::finish_tip_block(pool, &hash, true).await {
+				log::error!("Failed to remove block {} from the tip queue: {:?}", number, e);
+				if let Err(e) = Self::finish_tip_block(pool, &hash, false).await {
+					log::error!("Failed to release block {}, which stays taken until restart: {:?}", number, e);
+				}
+			}
+		}
+		Ok(taken)
+	}
+
+	/// Remove a block from the tip queue if it is `done`, or release it to be taken again.
+	async fn finish_tip_block(pool: &sqlx::PgPool, hash: &[u8], done: bool) -> Result<()> {
+		let mut conn = pool.acquire().await?;
+		if done {
+			queries::finish_tip_block(&mut *conn, hash).await
+		} else {
+			queries::release_tip_block(&mut *conn, hash).await
+		}
+	}
+
+	/// Decode the digests of a batch of blocks archived before digests were decoded on insert.
+	async fn decode_archived_digests(conn: &mut DbConn, backend: Arc<ReadOnlyBackend<B, D>>) -> Result<()> {
+		let blocks = queries::undecoded_blocks(&mut *conn, DECODE_BATCH_SIZE).await?;
+		if blocks.is_empty() {
+			return Ok(());
+		}
+		let decoded = database::decode_digests(conn, backend, blocks).await?;
+		log::info!("Decoded the digests of {} archived blocks", decoded);
+		Ok(())
+	}
+
+	/// Split `blocks` into runs of consecutive numbers of at most `range` blocks,
+	/// and create an `execute_block_range` job for each run.
+	fn execute_range_jobs(
+		mut blocks: Vec<(B::Hash, u64)>,
+		range: u32,
+	) -> Vec<crate::tasks::execute_block_range::Job<B, R, C, D>> {
+		blocks.sort_by_key(|(_, num)| *num);
+		let mut jobs = Vec::new();
+		let mut run: Vec<B::Hash> = Vec::new();
+		let mut start = 0;
+		for (hash, num) in blocks {
+			if !run.is_empty() && (num != start + run.len() as u64 || run.len() as u64 >= u64::from(range)) {
+				let hashes = std::mem::take(&mut run);
+				jobs.push(crate::tasks::execute_block_range::<B, R, C, D>(hashes, start, PhantomData));
+			}
+			if run.is_empty() {
+				start = num;
+			}
+			run.push(hash);
+		}
+		if !run.is_empty() {
+			jobs.push(crate::tasks::execute_block_range::<B, R, C, D>(run, start, PhantomData));
+		}
+		jobs
+	}
+
+	/// Create `execute_block` jobs referencing `blocks`.
+	fn execute_block_jobs(blocks: Vec<(B::Hash, u64)>) -> Vec<crate::tasks::execute_block::Job<B, R, C, D>> {
+		blocks
+			.into_iter()
+			.map(|(hash, number)| crate::tasks::execute_block::<B, R, C, D>(hash, number, PhantomData))
+			.collect()
+	}
+
+	/// Decode the hashes of blocks referenced by their encoded hash and number.
+	fn decode_block_refs(blocks: impl IntoIterator<Item = (Vec<u8>, u64)>) -> Result<Vec<(B::Hash, u64)>> {
+		blocks.into_iter().map(|(hash, num)| Ok((B::Hash::decode(&mut hash.as_slice())?, num))).collect()
+	}
 }
 
 #[async_trait::async_trait(?Send)]
@@ -367,7 +742,7 @@ where
 		+ Sync
 		+ 'static,
 	C: ApiAccess<B, ReadOnlyBackend<B, D>, R> + 'static,
-	NumberFor<B>: Into<u32> + From<u32> + Unpin,
+	NumberFor<B>: Into<u64> + From<u32> + Unpin,
 	B::Hash: Unpin,
 	B::Header: serde::de::DeserializeOwned,
 {
@@ -16,6 +16,7 @@
 
 mod blocks;
 mod database;
+mod head_watcher;
 mod metadata;
 mod storage_aggregator;
 
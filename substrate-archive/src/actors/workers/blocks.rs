@@ -13,37 +13,51 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::{ops::RangeInclusive, sync::Arc, time::Duration};
 
+use codec::{Decode, Encode};
 use xtra::prelude::*;
 
+use sp_api::RuntimeVersion;
+use sp_blockchain::Backend as _;
 use sp_runtime::{
-	generic::SignedBlock,
+	generic::{BlockId, SignedBlock},
 	traits::{Block as BlockT, Header as _, NumberFor},
 };
-use substrate_archive_backend::{ReadOnlyBackend, ReadOnlyDb, RuntimeVersionCache};
+use substrate_archive_backend::{CodeHash, ReadOnlyBackend, ReadOnlyDb, RuntimeVersionCache};
 
 use crate::{
 	actors::{
 		actor_pool::ActorPool,
 		workers::{
 			database::{DatabaseActor, GetState},
+			head_watcher::HeadWatcher,
 			metadata::MetadataActor,
 		},
-		SystemConfig,
+		FinalityMode, SystemConfig,
 	},
-	database::queries,
+	database::{
+		self,
+		queries::{self, Stage},
+		JustificationModel,
+	},
+	digest::{self, DecodedDigest},
 	error::{ArchiveError, Result},
-	types::{BatchBlock, Block, Die},
+	types::{timestamp_key, BatchBlock, Block, Die},
 };
 
 type DatabaseAct<B> = Address<ActorPool<DatabaseActor<B>>>;
 type MetadataAct<B> = Address<MetadataActor<B>>;
 
+/// How often the depth of the task queue is checked while it is full.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of blocks checked against the canonical chain at once when marking them finalized.
+const FINALIZE_BATCH_SIZE: i64 = 10_000;
+
 pub struct BlocksIndexer<B: BlockT, D>
 where
 	D: ReadOnlyDb,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B: Unpin,
 	B::Hash: Unpin,
 {
@@ -53,41 +67,52 @@ where
 	meta: MetadataAct<B>,
 	rt_cache: Arc<RuntimeVersionCache<B, D>>,
 	/// the last maximum block number from which we are sure every block before then is indexed
-	last_max: u32,
-	/// the maximum amount of blocks to index at once
-	max_block_load: u32,
+	last_max: u64,
+	/// the maximum encoded size of the blocks loaded at once, in bytes
+	chunk_size: usize,
+	/// the number of queued tasks above which backfilling pauses
+	max_queued_blocks: i64,
+	/// the number of blocks below the best block of the node that are crawled as the tip
+	tip_distance: u64,
+	/// the first and last block crawled at the tip, above `last_max`, while backfilling
+	tip: Option<(u64, u64)>,
+	/// time between catching up with the node to check whether its head advanced
+	catch_up_interval: Duration,
+	/// longest time between catching up with the node while its head does not advance
+	max_catch_up_backoff: Duration,
+	/// whether to crawl every block, or only finalized blocks
+	finality: FinalityMode,
 }
 
 impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> BlocksIndexer<B, D>
 where
 	B::Hash: Unpin,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	pub fn new(conf: &SystemConfig<B, D>, db: DatabaseAct<B>, meta: MetadataAct<B>) -> Self {
 		Self {
-			rt_cache: Arc::new(RuntimeVersionCache::new(conf.backend.clone())),
+			rt_cache: Arc::new(RuntimeVersionCache::new(conf.backend.clone(), &conf.runtime)),
 			last_max: 0,
 			backend: conf.backend().clone(),
 			db,
 			meta,
-			max_block_load: conf.control.max_block_load,
+			// a chunk is being loaded, one is waiting in the channel, and one is being inserted,
+			// plus its copy with versions.
+			chunk_size: conf.control.crawl_memory_budget * 1024 * 1024 / 4,
+			max_queued_blocks: conf.control.max_queued_blocks,
+			tip_distance: conf.control.tip_distance.into(),
+			tip: None,
+			catch_up_interval: Duration::from_millis(conf.control.catch_up_interval),
+			max_catch_up_backoff: Duration::from_millis(conf.control.max_catch_up_backoff),
+			finality: conf.control.finality,
 		}
 	}
 
-	/// A async wrapper around the backend fn `iter_blocks` which
-	/// runs in a `spawn_blocking` async task (its own thread)
-	async fn collect_blocks(&self, fun: impl Fn(u32) -> bool + Send + 'static) -> Result<Vec<Block<B>>> {
-		let backend = self.backend.clone();
-		let now = std::time::Instant::now();
-		let gather_blocks = move || -> Result<Vec<SignedBlock<B>>> {
-			Ok(backend.iter_blocks(|n| fun(n))?.enumerate().map(|(_, b)| b).collect())
-		};
-		let blocks = smol::unblock(gather_blocks).await?;
-		log::info!("Took {:?} to load {} blocks", now.elapsed(), blocks.len());
+	/// Finds the versions of all the blocks, returns a new set of type `Block`.
+	/// panics if our search fails to get the version for a block.
+	async fn with_versions(&self, blocks: Vec<SignedBlock<B>>) -> Result<Vec<Block<B>>> {
 		let cache = self.rt_cache.clone();
 		let blocks = smol::unblock(move || {
-			// Finds the versions of all the blocks, returns a new set of type `Block`.
-			// panics if our search fails to get the version for a block.
 			cache.find_versions(&blocks).map(|versions| {
 				blocks
 					.into_iter()
@@ -105,71 +130,300 @@ where
 		Ok(blocks)
 	}
 
-	/// Collect blocks according to the predicate `fun` and send those blocks to
-	///  the metadata actor.
-	async fn collect_and_send(&self, fun: impl Fn(u32) -> bool + Send + 'static) -> Result<()> {
-		self.meta.send(BatchBlock::new(self.collect_blocks(fun).await?)).await?;
-		Ok(())
+	/// Read the timestamps of blocks from their state,
+	/// and resolve the authors of blocks with a slot claim from the state at their parents.
+	async fn with_state_data(&self, mut blocks: Vec<Block<B>>) -> Vec<Block<B>> {
+		let backend = self.backend.clone();
+		smol::unblock(move || {
+			let timestamp_key = timestamp_key();
+			for block in blocks.iter_mut() {
+				let header = block.inner.block.header();
+				block.timestamp = backend
+					.storage(header.hash(), &timestamp_key)
+					.and_then(|now| u64::decode(&mut now.as_slice()).ok());
+				if let Some(claim) = DecodedDigest::new(header).slot {
+					let (author_index, author) = digest::resolve_author(&backend, header, &claim);
+					block.author_index = author_index;
+					block.author = author;
+				}
+			}
+			blocks
+		})
+		.await
+	}
+
+	/// Seed the runtime version cache with the persisted versions,
+	/// and persist the versions of spec versions that were archived before versions were persisted.
+	async fn seed_versions(&self) -> Result<()> {
+		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+		let versions = queries::runtime_versions(&mut conn)
+			.await?
+			.into_iter()
+			.map(|(hash, version)| {
+				Ok((CodeHash::decode(&mut hash.as_slice())?, RuntimeVersion::decode(&mut version.as_slice())?))
+			})
+			.collect::<Result<Vec<_>>>()?;
+		log::debug!("Seeding {} persisted runtime versions", versions.len());
+		self.rt_cache.seed(versions);
+
+		let hashes = queries::unversioned_spec_blocks(&mut conn)
+			.await?
+			.into_iter()
+			.map(|hash| B::Hash::decode(&mut hash.as_slice()))
+			.collect::<Result<Vec<_>, _>>()?;
+		std::mem::drop(conn);
+		if !hashes.is_empty() {
+			let cache = self.rt_cache.clone();
+			smol::unblock(move || hashes.into_iter().try_for_each(|hash| cache.get(hash).map(|_| ()))).await?;
+		}
+		self.persist_versions().await
+	}
+
+	/// Persist the runtime versions that were read from the Wasm since the last call.
+	async fn persist_versions(&self) -> Result<()> {
+		let versions = self.rt_cache.take_new_versions();
+		if versions.is_empty() {
+			return Ok(());
+		}
+		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+		let versions = versions.into_iter().map(|(hash, v)| (hash.to_vec(), v.spec_version, v.encode())).collect();
+		queries::insert_runtime_versions(&mut conn, versions).await
+	}
+
+	/// Wait until the task queue has room for more blocks.
+	async fn wait_for_queue(&self) -> Result<()> {
+		loop {
+			let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+			if queries::queued_tasks(&mut conn).await? < self.max_queued_blocks {
+				return Ok(());
+			}
+			// return the connection to the pool while waiting, the database actors need it to drain the queue
+			std::mem::drop(conn);
+			log::debug!("Task queue is full, waiting before crawling more blocks");
+			smol::Timer::after(QUEUE_POLL_INTERVAL).await;
+		}
+	}
+
+	/// Stream the blocks of the canonical chain numbered in `ranges` to the metadata actor.
+	///
+	/// Blocks are loaded by the backend fn `iter_blocks_in` on a blocking thread, in chunks of
+	/// at most `chunk_size` encoded bytes. A chunk is only loaded once the previous one has
+	/// been inserted into the database.
+	/// Returns the highest block number sent, if any.
+	async fn stream_blocks(&self, ranges: Vec<RangeInclusive<u64>>, pace: Pace) -> Result<Option<u64>> {
+		let backend = self.backend.clone();
+		let chunk_size = self.chunk_size;
+		let (tx, rx) = flume::bounded::<Vec<SignedBlock<B>>>(1);
+		let loader = smol::unblock(move || -> Result<()> {
+			let mut chunk = Vec::new();
+			let mut size = 0;
+			for range in ranges {
+				for block in backend.iter_blocks_in(range)? {
+					size += block.encoded_size();
+					chunk.push(block);
+					if size >= chunk_size {
+						size = 0;
+						// stop if the stream was cut short, or after the single chunk of a backfill
+						if tx.send(std::mem::take(&mut chunk)).is_err() || pace == Pace::Backfill {
+							return Ok(());
+						}
+					}
+				}
+			}
+			if !chunk.is_empty() {
+				let _ = tx.send(chunk);
+			}
+			Ok(())
+		});
+
+		let mut max: Option<u64> = None;
+		while let Ok(chunk) = rx.recv_async().await {
+			let now = std::time::Instant::now();
+			if pace != Pace::Tip {
+				self.wait_for_queue().await?;
+			}
+			let blocks = self.with_versions(chunk).await?;
+			let blocks = self.with_state_data(blocks).await;
+			self.persist_versions().await?;
+			max = blocks.iter().map(|b| (*b.inner.block.header().number()).into()).max().max(max);
+			log::debug!("Took {:?} to load {} blocks", now.elapsed(), blocks.len());
+			self.meta.send(BatchBlock::new(blocks)).await?;
+		}
+		std::mem::drop(rx);
+		loader.await?;
+		Ok(max)
 	}
 
 	/// First run of indexing
 	/// gets any blocks that are missing from database and indexes those.
-	/// sets the `last_max` value.
+	/// sets the `last_max` value to the `crawled` cursor.
 	async fn re_index(&mut self) -> Result<()> {
+		self.seed_versions().await?;
 		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
-		let cur_max = if let Some(m) = queries::max_block(&mut conn).await? {
-			m
+		let cursor = if let Some(c) = queries::advance_cursor(&mut conn, Stage::Crawled).await? {
+			c
 		} else {
 			// a `None` means that the blocks table is not populated yet
 			log::info!("{} missing blocks", 0);
 			return Ok(());
 		};
+		let gaps = queries::block_gaps(&mut conn, Stage::Crawled).await?;
+		let checked = queries::cursor(&mut conn, Stage::Finalized).await?;
+		std::mem::drop(conn);
 
-		let mut missing_blocks = 0;
-		let mut min = self.last_max;
-		loop {
-			let batch = queries::missing_blocks_min_max(&mut conn, min, self.max_block_load).await?;
-			if !batch.is_empty() {
-				missing_blocks += batch.len();
-				min += self.max_block_load;
-				self.collect_and_send(move |n| batch.contains(&n)).await?;
-			} else {
-				break;
+		let missing_blocks: u64 = gaps.iter().map(|(start, end)| end - start + 1).sum();
+		log::info!("{} missing blocks", missing_blocks);
+		if !gaps.is_empty() {
+			let ranges = gaps.into_iter().map(|(start, end)| start..=end).collect::<Vec<_>>();
+			self.stream_blocks(ranges.clone(), Pace::Gaps).await?;
+			// the gaps below the `finalized` cursor are not checked by later crawls
+			if let Some(checked) = checked {
+				for range in ranges.into_iter().filter(|r| *r.start() <= checked) {
+					self.mark_finalized(*range.start(), checked.min(*range.end())).await?;
+				}
 			}
 		}
-
-		self.last_max = cur_max;
-		log::info!("{} missing blocks", missing_blocks);
+		self.last_max = self.last_max.max(cursor);
 
 		Ok(())
 	}
 
-	/// Crawl up to `max_block_load` blocks that are greater than the last max
-	async fn crawl(&mut self) -> Result<Vec<Block<B>>> {
-		let copied_last_max = self.last_max;
-		let max_to_collect = copied_last_max + self.max_block_load;
-		let blocks = self
-			.collect_blocks(move |n| {
-				if copied_last_max == 0 {
-					// includes the genesis block
-					n >= copied_last_max && n <= max_to_collect
-				} else {
-					n > copied_last_max && n <= max_to_collect
+	/// Check the archived blocks above the `finalized` cursor up to `finalized`,
+	/// and move the cursor to `finalized`.
+	///
+	/// Blocks that are not part of the finalized chain were reorganized out, and are replaced by the finalized blocks.
+	async fn check_finalized(&self, finalized: u64) -> Result<()> {
+		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+		let from = queries::cursor(&mut conn, Stage::Finalized).await?.map_or(0, |c| c + 1);
+		std::mem::drop(conn);
+		if from > finalized {
+			return Ok(());
+		}
+		self.mark_finalized(from, finalized).await?;
+		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+		queries::set_cursor(&mut conn, Stage::Finalized, finalized).await
+	}
+
+	/// Mark the archived blocks of the canonical chain numbered `from..=to` as finalized,
+	/// and archive the justifications the node stored for them once they were finalized.
+	///
+	/// Archived blocks that were reorganized out of the canonical chain are deleted,
+	/// and the canonical blocks with their numbers are archived and marked finalized instead.
+	async fn mark_finalized(&self, mut from: u64, to: u64) -> Result<()> {
+		let mut conn = self.db.send(GetState::Conn.into()).await??.conn();
+		loop {
+			let blocks = queries::unfinalized_blocks(&mut conn, from, to, FINALIZE_BATCH_SIZE).await?;
+			let last = match blocks.last() {
+				Some((num, _)) => *num,
+				None => return Ok(()),
+			};
+			let backend = self.backend.clone();
+			let (hashes, justifications, reorged) = smol::unblock(move || -> Result<_> {
+				let (mut hashes, mut justifications, mut reorged) =
+					(Vec::with_capacity(blocks.len()), Vec::new(), Vec::new());
+				for (num, hash) in blocks {
+					let canonical = match backend.canonical_hash(num)? {
+						Some(canonical) => canonical,
+						None => continue,
+					};
+					if canonical.as_ref() != hash.as_slice() {
+						match backend.block(&BlockId::Hash(canonical)) {
+							Some(block) => reorged.push((hash, block)),
+							None => continue,
+						}
+					}
+					if let Some(proofs) = backend.justifications(BlockId::Hash(canonical))? {
+						justifications.extend(JustificationModel::from_justifications(
+							num,
+							canonical.as_ref(),
+							&proofs,
+						));
+					}
+					hashes.push(canonical.as_ref().to_vec());
 				}
+				Ok((hashes, justifications, reorged))
 			})
 			.await?;
-		self.last_max = blocks
-			.iter()
-			.map(|b| (*b.inner.block.header().number()).into())
-			.fold(self.last_max, |ac, e| if e > ac { e } else { ac });
-		Ok(blocks)
+			if !reorged.is_empty() {
+				let (stale, canonical): (Vec<_>, Vec<_>) = reorged.into_iter().unzip();
+				let removed = queries::remove_blocks(&mut conn, stale).await?;
+				log::info!("Replacing {} blocks that were reorganized out of the finalized chain", removed);
+				let blocks = self.with_versions(canonical).await?;
+				let blocks = self.with_state_data(blocks).await;
+				self.persist_versions().await?;
+				// the actors are done inserting the blocks once the metadata actor handled them
+				self.meta.send(BatchBlock::new(blocks)).await?;
+			}
+			database::insert_justifications::<B>(&mut conn, justifications).await?;
+			let marked = queries::mark_finalized(&mut conn, hashes).await?;
+			log::debug!("Marked {} blocks up to #{} finalized", marked, last);
+			match last.checked_add(1) {
+				Some(next) if next <= to => from = next,
+				_ => return Ok(()),
+			}
+		}
 	}
+
+	/// Crawl the blocks that are greater than the last max, up to the `best` block of the node.
+	///
+	/// New blocks within `tip_distance` of the best block of the node are crawled first, all at once.
+	/// The blocks between the last max and the tip are backfilled one chunk per crawl,
+	/// so that the tip is never waiting for a long backfill.
+	/// Returns whether the backfill caught up with the tip.
+	async fn crawl(&mut self, best: u64) -> Result<bool> {
+		let last_max = self.last_max;
+		// `last_max` of 0 includes the genesis block
+		let first = if last_max == 0 { 0 } else { last_max + 1 };
+
+		let floor = best.saturating_sub(self.tip_distance);
+		let tip_from = first.max(floor).max(self.tip.map_or(0, |(_, max)| max + 1));
+		let tip = self.stream_blocks(vec![tip_from..=best], Pace::Tip).await?;
+		if let Some(max) = tip {
+			let start = match self.tip {
+				Some((start, _)) => start,
+				None if last_max == 0 => floor,
+				None => floor.max(last_max + 1),
+			};
+			self.tip = Some((start, max));
+		}
+
+		let backfill_end = match self.tip {
+			Some((start, _)) => start.checked_sub(1),
+			None => Some(best),
+		};
+		let backfill =
+			self.stream_blocks(backfill_end.map(|end| vec![first..=end]).unwrap_or_default(), Pace::Backfill).await?;
+		match backfill {
+			Some(max) => {
+				self.last_max = self.last_max.max(max);
+				Ok(false)
+			}
+			// backfill caught up with the tip
+			None => {
+				if let Some((_, max)) = self.tip.take() {
+					self.last_max = self.last_max.max(max);
+				}
+				Ok(true)
+			}
+		}
+	}
+}
+
+/// How `BlocksIndexer::stream_blocks` paces the blocks it sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pace {
+	/// Send all blocks right away.
+	Tip,
+	/// Load and send a single chunk, once the task queue has room for it.
+	Backfill,
+	/// Send all blocks, each chunk once the task queue has room for it.
+	Gaps,
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT, D: ReadOnlyDb + 'static> Actor for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B: Unpin,
 	B::Hash: Unpin,
 {
@@ -179,35 +433,59 @@ where
 		let addr = ctx.address().expect("Actor just started");
 		addr.do_send(ReIndex).expect("Actor cannot be disconnected; just started");
 
-		smol::spawn(async move {
-			loop {
-				if addr.send(Crawl).await.is_err() {
-					break;
-				}
-			}
-		})
-		.detach();
+		let watcher = HeadWatcher::new(self.backend.clone(), self.catch_up_interval, self.max_catch_up_backoff);
+		smol::spawn(watcher.run(addr)).detach();
 	}
 }
 
-struct Crawl;
+/// Crawl the new blocks, up to the `best` block of the node,
+/// or up to its `finalized` block if only finalized blocks are archived.
+pub(super) struct Crawl {
+	pub best: u64,
+	pub finalized: u64,
+}
+
 impl Message for Crawl {
-	type Result = ();
+	type Result = CrawlStatus;
+}
+
+/// Outcome of a [`Crawl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum CrawlStatus {
+	/// Every block up to the best block was crawled.
+	CaughtUp,
+	/// More blocks are left to backfill.
+	Behind,
+	/// Crawling failed, and is retried after a backoff.
+	Failed,
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> Handler<Crawl> for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B::Hash: Unpin,
 {
-	async fn handle(&mut self, _: Crawl, ctx: &mut Context<Self>) {
-		match self.crawl().await {
-			Err(e) => log::error!("{}", e.to_string()),
-			Ok(b) => {
-				if !b.is_empty() && self.meta.send(BatchBlock::new(b)).await.is_err() {
-					ctx.stop();
-				}
+	async fn handle(&mut self, crawl: Crawl, ctx: &mut Context<Self>) -> CrawlStatus {
+		let head = match self.finality {
+			FinalityMode::All => crawl.best,
+			FinalityMode::Finalized => crawl.finalized,
+		};
+		let res = match self.crawl(head).await {
+			Ok(true) => self.check_finalized(crawl.finalized).await.map(|()| true),
+			res => res,
+		};
+		match res {
+			Ok(true) => CrawlStatus::CaughtUp,
+			Ok(false) => CrawlStatus::Behind,
+			// stop if disconnected from the metadata actor
+			Err(ArchiveError::Disconnected) => {
+				ctx.stop();
+				CrawlStatus::Failed
+			}
+			Err(e) => {
+				log::error!("{}", e.to_string());
+				CrawlStatus::Failed
 			}
 		}
 	}
@@ -221,7 +499,7 @@ impl Message for ReIndex {
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> Handler<ReIndex> for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: ReIndex, ctx: &mut Context<Self>) {
@@ -237,10 +515,263 @@ where
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin, D: ReadOnlyDb + 'static> Handler<Die> for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) {
 		ctx.stop();
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		actors::{ControlConfig, StorageAggregator},
+		tasks::{execute_ref, Environment},
+		DatabaseConfig,
+	};
+	use sp_core::H256;
+	use sp_storage::{well_known_keys::CODE, ChildInfo};
+	use std::panic::AssertUnwindSafe;
+	use substrate_archive_backend::{runtime_api, ExecutionStrategy, MemoryDb, RuntimeConfig, TArchiveClient};
+	use test_common::{
+		runtime::{Block as TestBlock, Executor, RuntimeApi},
+		runtime_code, ChainBuilder, CHILD_PAIR, CHILD_STORAGE_KEY, NUMBER_KEY,
+	};
+	use xtra::spawn::Smol;
+
+	type TestClient = TArchiveClient<TestBlock, RuntimeApi, Executor, MemoryDb>;
+
+	/// Create an indexer of the fixture `chain`, along with its client and the database actors it sends blocks to.
+	///
+	/// Deletes the dummy genesis block of the `TestGuard`, which would collide with the genesis block of `chain`.
+	async fn fixture_indexer(
+		chain: &ChainBuilder,
+	) -> Result<(BlocksIndexer<TestBlock, MemoryDb>, Arc<TestClient>, DatabaseAct<TestBlock>)> {
+		let mut runtime = RuntimeConfig::default();
+		runtime.exec_strategy = ExecutionStrategy::AlwaysWasm;
+		let client = Arc::new(runtime_api::<TestBlock, RuntimeApi, Executor, MemoryDb>(chain.db(), runtime.clone())?);
+		let conf = SystemConfig::new(
+			client.backend(),
+			DatabaseConfig::new(crate::DATABASE_URL.as_str()),
+			client.clone(),
+			ControlConfig::default(),
+			runtime,
+			None,
+			None,
+		);
+		sqlx::query("DELETE FROM blocks").execute(&*crate::PG_POOL).await?;
+		let db = DatabaseActor::<TestBlock>::new(conf.database(), false).await?;
+		let db = ActorPool::new(db, 1).create(None).spawn(&mut Smol::Global);
+		let meta = MetadataActor::new(db.clone(), conf.meta().clone()).await?.create(None).spawn(&mut Smol::Global);
+		Ok((BlocksIndexer::new(&conf, db.clone(), meta), client, db))
+	}
+
+	#[test]
+	fn should_find_runtime_upgrade_in_fixture_chain() -> Result<()> {
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(3);
+		chain.fork(blocks[0], Vec::new());
+		chain.upgrade(2);
+		chain.extend(2);
+
+		let backend = Arc::new(ReadOnlyBackend::<TestBlock, MemoryDb>::new(chain.db(), true));
+		// forks are not part of the canonical chain
+		let blocks = backend.iter_blocks(|_| true)?.collect::<Vec<_>>();
+		assert_eq!(blocks.len(), 7);
+		// only the numbers in range are looked up, numbers above the best block are skipped
+		let numbers = |range| -> Result<Vec<u32>> {
+			Ok(backend.iter_blocks_in(range)?.map(|b| *b.block.header().number()).collect())
+		};
+		assert_eq!(numbers(2..=4)?, vec![2, 3, 4]);
+		assert_eq!(numbers(5..=100)?, vec![5, 6]);
+
+		let cache = RuntimeVersionCache::new(backend, &RuntimeConfig::default());
+		let versions = cache.find_versions(&blocks)?;
+		for number in 0..7 {
+			let range = versions.iter().find(|v| v.contains_block(&number)).expect("every block has a version");
+			assert_eq!(range.version.spec_version, if number < 4 { 1 } else { 2 });
+		}
+		assert_eq!(cache.take_new_versions().len(), 2);
+		Ok(())
+	}
+
+	#[test]
+	fn should_crawl_and_execute_fixture_chain() -> Result<()> {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		chain.extend(2);
+		chain.upgrade(2);
+		chain.extend(1);
+
+		let (rows, client, storage) = smol::block_on(async {
+			let (mut indexer, client, db) = fixture_indexer(&chain).await?;
+			indexer.re_index().await?;
+			while !indexer.crawl(4).await? {}
+
+			let mut conn = crate::PG_POOL.acquire().await?;
+			let rows: Vec<(Vec<u8>, Vec<u8>, i64, i32)> =
+				sqlx::query_as("SELECT hash, parent_hash, block_num, spec FROM blocks ORDER BY block_num")
+					.fetch_all(&mut *conn)
+					.await?;
+			let storage = StorageAggregator::new(db).create(None).spawn(&mut Smol::Global);
+			Ok::<_, ArchiveError>((rows, client, storage))
+		})?;
+
+		// every block of the canonical chain is archived once, with the spec version it was authored with
+		assert_eq!(rows.iter().map(|r| (r.2, r.3)).collect::<Vec<_>>(), vec![(0, 1), (1, 1), (2, 1), (3, 2), (4, 2)]);
+		for (row, parent) in rows.iter().skip(1).zip(rows.iter()) {
+			assert_eq!(row.1, parent.0);
+		}
+		assert_eq!(rows.last().map(|r| r.0.clone()), Some(chain.best().as_bytes().to_vec()));
+
+		let env = AssertUnwindSafe(Environment::<TestBlock, RuntimeApi, _, MemoryDb>::new(
+			client.backend(),
+			client,
+			storage,
+			crate::PG_POOL.clone(),
+			None,
+			3,
+			30,
+			true,
+			None,
+		));
+		for (hash, _, num, _) in &rows {
+			execute_ref(&env, H256::from_slice(hash), *num as u64).expect("failures are recorded instead");
+		}
+
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await?;
+			let tip = &rows[4].0;
+			// the aggregator inserts storage once a second
+			for _ in 0..20 {
+				if !queries::storage_changes(&mut conn, tip).await?.is_empty() {
+					break;
+				}
+				smol::Timer::after(Duration::from_millis(250)).await;
+			}
+			assert!(queries::failed_blocks(&mut conn).await?.is_empty());
+			let genesis = queries::storage_changes(&mut conn, &rows[0].0).await?;
+			assert!(genesis.iter().all(|c| c.is_full) && genesis.iter().any(|c| c.key == CODE));
+			// including the pairs of child tries, under the prefixed key of their child trie
+			let child_key = ChildInfo::new_default(CHILD_STORAGE_KEY).prefixed_storage_key().into_inner();
+			let child_pair = genesis.iter().find(|c| c.key == [child_key.as_slice(), CHILD_PAIR.0].concat());
+			assert_eq!(child_pair.and_then(|c| c.storage.clone()), Some(CHILD_PAIR.1.to_vec()));
+			for (hash, _, num, _) in &rows[1..] {
+				let changes = queries::storage_changes(&mut conn, hash).await?;
+				let changes = changes.into_iter().map(|c| (c.key, c.storage)).collect::<Vec<_>>();
+				if *num == 3 {
+					assert!(changes.contains(&(CODE.to_vec(), Some(runtime_code(2)))));
+				} else {
+					assert_eq!(changes, vec![(NUMBER_KEY.to_vec(), Some((*num as u32).encode()))], "block #{}", num);
+				}
+			}
+			assert!(queries::state_root_mismatches(&mut conn).await?.is_empty());
+			Ok(())
+		})
+	}
+
+	async fn finalized_blocks(conn: &mut sqlx::PgConnection) -> Result<Vec<(i64, bool)>> {
+		let blocks =
+			sqlx::query_as("SELECT block_num, finalized FROM blocks ORDER BY block_num").fetch_all(conn).await?;
+		Ok(blocks)
+	}
+
+	#[test]
+	fn should_check_blocks_against_finalized_chain_once() -> Result<()> {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(3);
+		let fork = chain.fork(blocks[0], Vec::new());
+		chain.finalize(blocks[2]);
+
+		smol::block_on(async {
+			let (mut indexer, _, _) = fixture_indexer(&chain).await?;
+			let mut conn = crate::PG_POOL.acquire().await?;
+			// the fork was archived as block #2 while it was the best block of the node, before a reorg
+			crate::insert_block(&mut *conn, 2, fork.as_bytes(), blocks[0].as_bytes(), 1).await?;
+
+			while !indexer.crawl(3).await? {}
+			indexer.check_finalized(3).await?;
+			// the fork is replaced by the finalized block #2
+			assert_eq!(finalized_blocks(&mut conn).await?, vec![(0, true), (1, true), (2, true), (3, true)]);
+			assert_eq!(queries::cursor(&mut conn, Stage::Finalized).await?, Some(3));
+			let (hash,): (Vec<u8>,) =
+				sqlx::query_as("SELECT hash FROM blocks WHERE block_num = 2").fetch_one(&mut *conn).await?;
+			assert_eq!(hash, blocks[1].as_bytes().to_vec());
+
+			// later checks start above the cursor
+			let tip = chain.extend(1)[0];
+			chain.finalize(tip);
+			while !indexer.crawl(4).await? {}
+			indexer.check_finalized(4).await?;
+			assert_eq!(finalized_blocks(&mut conn).await?, vec![(0, true), (1, true), (2, true), (3, true), (4, true)]);
+			assert_eq!(queries::cursor(&mut conn, Stage::Finalized).await?, Some(4));
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_replace_blocks_reorganized_out_before_finalization() -> Result<()> {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(2);
+
+		smol::block_on(async {
+			let (mut indexer, _, _) = fixture_indexer(&chain).await?;
+			let mut conn = crate::PG_POOL.acquire().await?;
+			while !indexer.crawl(2).await? {}
+			// the archived block #2 was executed before the node reorganized onto a fork
+			queries::set_cursor(&mut conn, Stage::Executed, 2).await?;
+			let fork = chain.fork(blocks[0], vec![(b"fork".to_vec(), b"value".to_vec())]);
+			chain.reorg(fork);
+			chain.finalize(fork);
+
+			indexer.check_finalized(2).await?;
+			let rows: Vec<(i64, Vec<u8>, bool)> =
+				sqlx::query_as("SELECT block_num, hash, finalized FROM blocks WHERE block_num > 0 ORDER BY block_num")
+					.fetch_all(&mut *conn)
+					.await?;
+			assert_eq!(rows, vec![(1, blocks[0].as_bytes().to_vec(), true), (2, fork.as_bytes().to_vec(), true)]);
+			// the block replacing the executed block is executed again
+			assert_eq!(queries::block_gaps(&mut conn, Stage::Executed).await?, vec![(2, 2)]);
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_archive_justifications_of_blocks_finalized_after_crawling() -> Result<()> {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(2);
+
+		smol::block_on(async {
+			let (mut indexer, _, _) = fixture_indexer(&chain).await?;
+			let mut conn = crate::PG_POOL.acquire().await?;
+			while !indexer.crawl(2).await? {}
+			assert!(queries::justifications(&mut conn, blocks[1].as_bytes()).await?.is_empty());
+
+			// the node stores the justification when it finalizes the block, after it was crawled
+			chain.justify(blocks[1], *b"TEST", vec![1, 2, 3]);
+			chain.finalize(blocks[1]);
+			indexer.check_finalized(2).await?;
+			let justifications = queries::justifications(&mut conn, blocks[1].as_bytes()).await?;
+			assert_eq!(
+				justifications,
+				vec![queries::Justification {
+					block_num: 2,
+					hash: blocks[1].as_bytes().to_vec(),
+					engine_id: b"TEST".to_vec(),
+					justification: vec![1, 2, 3],
+				}]
+			);
+			assert!(queries::justifications(&mut conn, blocks[0].as_bytes()).await?.is_empty());
+			Ok(())
+		})
+	}
+}
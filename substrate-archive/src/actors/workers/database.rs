@@ -16,36 +16,65 @@
 
 use std::{marker::PhantomData, time::Duration};
 
-use sp_runtime::traits::{Block as BlockT, NumberFor};
-
+use codec::Decode;
 use xtra::prelude::*;
 
+use sp_runtime::traits::{Block as BlockT, NumberFor};
+
 use crate::{
-	database::{models::StorageModel, queries, Database, DbConn},
+	database::{
+		models::{DedupStorage, StorageModel},
+		queries, Database, DatabaseConfig, DbConn,
+	},
 	error::Result,
-	types::{BatchBlock, BatchStorage, Block, Die, Metadata, Storage},
+	types::{millis_to_time, timestamp_key, BatchBlock, BatchStorage, Block, Die, Metadata, Storage},
 	wasm_tracing::Traces,
 };
 
 #[derive(Clone)]
 pub struct DatabaseActor<B: BlockT> {
 	db: Database,
+	/// insert storage values into the content-addressed `storage_values` table
+	dedup_storage: bool,
 	_marker: PhantomData<B>,
 }
 
 impl<B: BlockT> DatabaseActor<B> {
-	pub async fn new(url: String) -> Result<Self> {
-		Ok(Self { db: Database::new(url).await?, _marker: PhantomData })
+	pub async fn new(database: &DatabaseConfig, dedup_storage: bool) -> Result<Self> {
+		Ok(Self { db: Database::new(database).await?, dedup_storage, _marker: PhantomData })
 	}
 
 	#[allow(unused)]
 	pub fn with_db(db: Database) -> Self {
-		Self { db, _marker: PhantomData }
+		Self { db, dedup_storage: false, _marker: PhantomData }
+	}
+
+	async fn insert_storage(&self, storage: Vec<StorageModel<B>>) -> Result<()> {
+		// blocks crawled without access to their state get their timestamp from the storage changes
+		let key = timestamp_key();
+		let timestamps = storage
+			.iter()
+			.filter(|s| s.key().0 == key)
+			.filter_map(|s| {
+				let millis = u64::decode(&mut s.data()?.0.as_slice()).ok()?;
+				Some((s.hash().as_ref().to_vec(), millis_to_time(millis)?))
+			})
+			.collect::<Vec<_>>();
+		if self.dedup_storage {
+			self.db.insert(DedupStorage(storage)).await?;
+		} else {
+			self.db.insert(storage).await?;
+		}
+		if !timestamps.is_empty() {
+			let mut conn = self.db.conn().await?;
+			queries::set_missing_timestamps(&mut conn, timestamps).await?;
+		}
+		Ok(())
 	}
 
 	async fn block_handler(&self, blk: Block<B>) -> Result<()>
 	where
-		NumberFor<B>: Into<u32>,
+		NumberFor<B>: Into<u64>,
 	{
 		let mut conn = self.db.conn().await?;
 		while !queries::check_if_meta_exists(blk.spec, &mut conn).await? {
@@ -66,7 +95,7 @@ impl<B: BlockT> DatabaseActor<B> {
 
 	async fn batch_block_handler(&self, blks: BatchBlock<B>) -> Result<()>
 	where
-		NumberFor<B>: Into<u32>,
+		NumberFor<B>: Into<u64>,
 	{
 		let mut conn = self.db.conn().await?;
 		while !Self::db_contains_metadata(blks.inner(), &mut conn).await? {
@@ -85,8 +114,7 @@ impl<B: BlockT> DatabaseActor<B> {
 		}
 		let storage = Vec::<StorageModel<B>>::from(storage);
 		std::mem::drop(conn);
-		self.db.insert(storage).await?;
-		Ok(())
+		self.insert_storage(storage).await
 	}
 
 	async fn batch_storage_handler(&self, storages: BatchStorage<B>) -> Result<()> {
@@ -103,8 +131,7 @@ impl<B: BlockT> DatabaseActor<B> {
 		// we drop the connection early so that the insert() has the use of all db connections
 		std::mem::drop(conn);
 		let storage = Vec::<StorageModel<B>>::from(storages);
-		self.db.insert(storage).await?;
-		Ok(())
+		self.insert_storage(storage).await
 	}
 }
 
@@ -114,7 +141,7 @@ impl<B: BlockT> Actor for DatabaseActor<B> {}
 impl<B> Handler<Block<B>> for DatabaseActor<B>
 where
 	B: BlockT,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
 		if let Err(e) = self.block_handler(blk).await {
@@ -127,7 +154,7 @@ where
 impl<B> Handler<BatchBlock<B>> for DatabaseActor<B>
 where
 	B: BlockT,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
 		let len = blks.inner.len();
@@ -255,7 +282,7 @@ impl<B: BlockT> Handler<GetState> for DatabaseActor<B> {
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin> Handler<Die> for DatabaseActor<B>
 where
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) {
@@ -0,0 +1,99 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Watches the head of the node, and asks the [`BlocksIndexer`] to crawl when it advances.
+//! Crawling only iterates the chain data when there is something new in it.
+
+use std::{sync::Arc, time::Duration};
+
+use xtra::prelude::*;
+
+use sp_runtime::traits::{Block as BlockT, NumberFor};
+use substrate_archive_backend::{ChainHead, ReadOnlyBackend, ReadOnlyDb};
+
+use super::blocks::{BlocksIndexer, Crawl, CrawlStatus};
+
+pub struct HeadWatcher<B: BlockT, D: ReadOnlyDb> {
+	backend: Arc<ReadOnlyBackend<B, D>>,
+	/// Time between catching up with the node.
+	interval: Duration,
+	/// Longest time between catching up with the node, while its head does not advance.
+	max_backoff: Duration,
+}
+
+impl<B, D> HeadWatcher<B, D>
+where
+	B: BlockT + Unpin,
+	B::Hash: Unpin,
+	NumberFor<B>: Into<u64>,
+	D: ReadOnlyDb + 'static,
+{
+	pub fn new(backend: Arc<ReadOnlyBackend<B, D>>, interval: Duration, max_backoff: Duration) -> Self {
+		Self { backend, interval, max_backoff: max_backoff.max(interval) }
+	}
+
+	/// Watch the head until the indexer stops.
+	pub async fn run(self, indexer: Address<BlocksIndexer<B, D>>) {
+		let mut crawled: Option<ChainHead<B>> = None;
+		let mut wait = self.interval;
+		loop {
+			let backend = self.backend.clone();
+			match smol::unblock(move || backend.head()).await {
+				Ok(head) if crawled.as_ref() != Some(&head) => match self.crawl(&indexer, head).await {
+					Some((CrawlStatus::CaughtUp, head)) => {
+						crawled = Some(head);
+						wait = self.interval;
+					}
+					Some(_) => wait = self.backoff(wait),
+					None => break,
+				},
+				Ok(_) => wait = self.backoff(wait),
+				Err(e) => {
+					log::warn!("Could not read the head of the node: {}", e);
+					wait = self.backoff(wait);
+				}
+			}
+			smol::Timer::after(wait).await;
+		}
+	}
+
+	/// Crawl until the indexer has caught up with the head of the node, starting with `head`.
+	/// The head is read again after every crawl that is behind, so that the blocks the node
+	/// imports during a long backfill are crawled as the tip.
+	/// Returns how the last crawl ended and the head it crawled to, or `None` if the indexer stopped.
+	async fn crawl(
+		&self,
+		indexer: &Address<BlocksIndexer<B, D>>,
+		mut head: ChainHead<B>,
+	) -> Option<(CrawlStatus, ChainHead<B>)> {
+		loop {
+			let crawl = Crawl { best: head.best_number.into(), finalized: head.finalized_number.into() };
+			match indexer.send(crawl).await.ok()? {
+				CrawlStatus::Behind => {
+					let backend = self.backend.clone();
+					match smol::unblock(move || backend.head()).await {
+						Ok(new) => head = new,
+						Err(e) => log::warn!("Could not read the head of the node: {}", e),
+					}
+				}
+				status => return Some((status, head)),
+			}
+		}
+	}
+
+	fn backoff(&self, wait: Duration) -> Duration {
+		(wait * 2).min(self.max_backoff)
+	}
+}
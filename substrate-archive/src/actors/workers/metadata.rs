@@ -62,7 +62,7 @@ impl<B: BlockT + Unpin> MetadataActor<B> {
 
 	async fn block_handler(&mut self, blk: Block<B>) -> Result<()>
 	where
-		NumberFor<B>: Into<u32>,
+		NumberFor<B>: Into<u64>,
 	{
 		let hash = blk.inner.block.hash();
 		self.meta_checker(blk.spec, hash).await?;
@@ -72,7 +72,7 @@ impl<B: BlockT + Unpin> MetadataActor<B> {
 
 	async fn batch_block_handler(&mut self, blks: BatchBlock<B>) -> Result<()>
 	where
-		NumberFor<B>: Into<u32>,
+		NumberFor<B>: Into<u64>,
 	{
 		for blk in blks.inner().iter().unique_by(|&blk| blk.spec) {
 			self.meta_checker(blk.spec, blk.inner.block.hash()).await?;
@@ -88,7 +88,7 @@ impl<B: BlockT> Actor for MetadataActor<B> {}
 impl<B> Handler<Block<B>> for MetadataActor<B>
 where
 	B: BlockT + Unpin,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
 		if let Err(e) = self.block_handler(blk).await {
@@ -101,7 +101,7 @@ where
 impl<B> Handler<BatchBlock<B>> for MetadataActor<B>
 where
 	B: BlockT + Unpin,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 {
 	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
 		if let Err(e) = self.batch_block_handler(blks).await {
@@ -113,7 +113,7 @@ where
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin> Handler<Die> for MetadataActor<B>
 where
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) {
@@ -14,9 +14,14 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{env, fs, io, marker::PhantomData, path::PathBuf, sync::Arc};
+use std::{
+	env, fs, io,
+	marker::PhantomData,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use sc_chain_spec::ChainSpec;
 use sc_client_api::backend as api_backend;
@@ -30,22 +35,27 @@ use sp_runtime::{
 };
 
 use substrate_archive_backend::{
-	runtime_api, ExecutionMethod, ReadOnlyBackend, ReadOnlyDb, RuntimeConfig, TArchiveClient,
+	runtime_api, BackendError, DatabaseKind, ExecutionMethod, ExecutionStrategy, ReadOnlyBackend, ReadOnlyDb,
+	RuntimeConfig, TArchiveClient,
 };
 
 use crate::{
-	actors::{ControlConfig, System, SystemConfig},
-	database::{self, DatabaseConfig},
+	actors::{ControlConfig, FinalityMode, System, SystemConfig},
+	audit::{self, AuditReport},
+	database::{self, DatabaseConfig, DATABASE_URL},
 	error::Result,
 	logger::{self, FileLoggerConfig, LoggerConfig},
 	substrate_archive_default_dir,
 };
 
 /// Configure Chain.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ChainConfig {
 	/// Chain path to the rocksdb database.
 	pub(crate) data_path: Option<PathBuf>,
+	/// Database the node is running with.
+	#[serde(default)]
+	pub(crate) database: DatabaseKind,
 	/// How much cache should rocksdb keep.
 	#[serde(default = "default_cache_size")]
 	pub(crate) cache_size: usize,
@@ -60,6 +70,7 @@ impl Clone for ChainConfig {
 	fn clone(&self) -> ChainConfig {
 		ChainConfig {
 			data_path: self.data_path.clone(),
+			database: self.database,
 			cache_size: self.cache_size,
 			rocksdb_secondary_path: self.rocksdb_secondary_path.clone(),
 			spec: self.spec.as_ref().map(|s| s.cloned_box()),
@@ -69,7 +80,13 @@ impl Clone for ChainConfig {
 
 impl Default for ChainConfig {
 	fn default() -> Self {
-		Self { data_path: None, cache_size: default_cache_size(), rocksdb_secondary_path: None, spec: None }
+		Self {
+			data_path: None,
+			database: DatabaseKind::default(),
+			cache_size: default_cache_size(),
+			rocksdb_secondary_path: None,
+			spec: None,
+		}
 	}
 }
 
@@ -79,7 +96,7 @@ const fn default_cache_size() -> usize {
 }
 
 /// Configure WASM Tracing.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TracingConfig {
 	/// Targets for tracing.
 	#[serde(default)]
@@ -90,7 +107,7 @@ pub struct TracingConfig {
 	pub folder: Option<PathBuf>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ArchiveConfig {
 	/// Chain spec and database.
 	#[serde(default)]
@@ -111,6 +128,25 @@ pub struct ArchiveConfig {
 	pub wasm_tracing: Option<TracingConfig>,
 }
 
+impl ArchiveConfig {
+	/// Read the config from a TOML file, if any.
+	/// The chain data path and database url are taken from the `CHAIN_DATA_DB` and `DATABASE_URL`
+	/// environment variables if they are not set in the file.
+	pub fn load(path: Option<&Path>) -> Result<Self> {
+		let mut config = match path {
+			Some(path) => toml::from_str::<ArchiveConfig>(&fs::read_to_string(path)?)?,
+			None => ArchiveConfig::default(),
+		};
+		if config.chain.data_path.is_none() {
+			config.chain.data_path = env::var(CHAIN_DATA_DB).ok().map(Into::into);
+		}
+		if config.database.is_none() {
+			config.database = env::var(DATABASE_URL).ok().map(DatabaseConfig::new);
+		}
+		Ok(config)
+	}
+}
+
 /// The control interface of an archive system.
 #[async_trait::async_trait(?Send)]
 pub trait Archive<B: BlockT + Unpin, D: ReadOnlyDb>
@@ -173,6 +209,16 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self
 	}
 
+	/// Set the database the node is running with.
+	/// Reading a ParityDb database requires the `ChainDb` or `ParityDb` database type.
+	///
+	/// # Default
+	/// Defaults to RocksDB.
+	pub fn chain_database(mut self, database: DatabaseKind) -> Self {
+		self.config.chain.database = database;
+		self
+	}
+
 	/// Set the amount of cache RocksDB should keep.
 	///
 	/// # Default
@@ -198,7 +244,10 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 	/// # Default
 	/// Defaults to value of the environment variable DATABASE_URL.
 	pub fn pg_url<S: Into<String>>(mut self, url: S) -> Self {
-		self.config.database = Some(DatabaseConfig { url: url.into() });
+		match self.config.database.as_mut() {
+			Some(database) => database.url = url.into(),
+			None => self.config.database = Some(DatabaseConfig::new(url)),
+		}
 		self
 	}
 
@@ -211,6 +260,17 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self
 	}
 
+	/// Set whether blocks are executed with the native runtime or the on-chain Wasm.
+	/// With [`ExecutionStrategy::Both`], every block is executed both ways and
+	/// diverging storage changes are recorded in the `execution_divergences` table.
+	///
+	/// # Default
+	/// Defaults to executing natively, falling back to Wasm.
+	pub fn execution_strategy(mut self, strategy: ExecutionStrategy) -> Self {
+		self.config.runtime.exec_strategy = strategy;
+		self
+	}
+
 	/// Set the number of threads spawn for block execution.
 	///
 	/// # Default
@@ -229,6 +289,26 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self
 	}
 
+	/// Set the memory to spend on caching trie nodes shared by all block workers, in MB.
+	/// 0 disables the cache.
+	///
+	/// # Default
+	/// Defaults to 128.
+	pub fn trie_cache_size(mut self, mb: usize) -> Self {
+		self.config.runtime.trie_cache_size = mb;
+		self
+	}
+
+	/// Set the directory to cache the runtimes compiled with `ExecutionMethod::Compiled` in,
+	/// for block execution, metadata and runtime versions.
+	///
+	/// # Default
+	/// Defaults to the `wasm_cache` directory in the local substrate-archive directory.
+	pub fn wasm_cache_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.config.runtime.wasm_cache_path = Some(path.into());
+		self
+	}
+
 	/// Set the number of database actors to be spawned in the actor pool.
 	///
 	/// # Default
@@ -265,12 +345,129 @@ impl<B, R, D, DB> ArchiveBuilder<B, R, D, DB> {
 		self
 	}
 
+	/// Set the memory to spend on blocks loaded from the chain data
+	/// but not yet inserted into the database, in MB.
+	///
+	/// # Default
+	/// Defaults to 256.
+	pub fn crawl_memory_budget(mut self, mb: usize) -> Self {
+		self.config.control.crawl_memory_budget = mb;
+		self
+	}
+
 	/// Set the number of blocks to index at once.
+	/// Converted into a [`crawl_memory_budget`](Self::crawl_memory_budget) of about 2 KiB per block,
+	/// which it overrides.
+	#[deprecated(note = "use `crawl_memory_budget`, which bounds the memory of loaded blocks rather than their number")]
+	pub fn max_block_load(mut self, max_block_load: u32) -> Self {
+		self.config.control.max_block_load = Some(max_block_load);
+		self
+	}
+
+	/// Set the number of queued tasks above which backfilling pauses until the queue drains.
 	///
 	/// # Default
 	/// Defaults to 100_000.
-	pub fn max_block_load(mut self, max_block_load: u32) -> Self {
-		self.config.control.max_block_load = max_block_load;
+	pub fn max_queued_blocks(mut self, max: i64) -> Self {
+		self.config.control.max_queued_blocks = max;
+		self
+	}
+
+	/// Set the distance from the best block of the node within which blocks are
+	/// crawled and executed ahead of historical blocks.
+	///
+	/// # Default
+	/// Defaults to 64.
+	pub fn tip_distance(mut self, distance: u32) -> Self {
+		self.config.control.tip_distance = distance;
+		self
+	}
+
+	/// Set the number of workers executing the blocks near the tip,
+	/// besides the workers executing the tasks of historical blocks.
+	///
+	/// # Default
+	/// Defaults to 1.
+	pub fn tip_workers(mut self, workers: usize) -> Self {
+		self.config.control.tip_workers = workers;
+		self
+	}
+
+	/// Set whether to archive every block, or only finalized blocks.
+	///
+	/// # Default
+	/// Defaults to archiving every block, marking blocks `finalized` as finality advances.
+	pub fn finality(mut self, mode: FinalityMode) -> Self {
+		self.config.control.finality = mode;
+		self
+	}
+
+	/// Set the milliseconds between catching up with the node to check whether its head advanced.
+	///
+	/// # Default
+	/// Defaults to 500.
+	pub fn catch_up_interval(mut self, millis: u64) -> Self {
+		self.config.control.catch_up_interval = millis;
+		self
+	}
+
+	/// Set the longest time between catching up with the node, in milliseconds.
+	/// The interval doubles up to this while the head of the node does not advance.
+	///
+	/// # Default
+	/// Defaults to 6000.
+	pub fn max_catch_up_backoff(mut self, millis: u64) -> Self {
+		self.config.control.max_catch_up_backoff = millis;
+		self
+	}
+
+	/// Execute historical blocks sequentially, in ranges of `range` contiguous blocks per task.
+	/// Each block is executed on the post-state of its parent, which is kept in the trie cache.
+	///
+	/// # Default
+	/// Defaults to 1, which executes every block in its own task.
+	pub fn execution_range(mut self, range: u32) -> Self {
+		self.config.control.execution_range = range;
+		self
+	}
+
+	/// Deduplicate storage values.
+	/// Values are stored once in the `storage_values` table and referenced by their hash.
+	///
+	/// # Default
+	/// Defaults to `false`.
+	pub fn dedup_storage(mut self, dedup: bool) -> Self {
+		self.config.control.dedup_storage = dedup;
+		self
+	}
+
+	/// Set the number of times a block that failed to execute is retried.
+	/// After that, the block is marked dead in the `failed_blocks` table until re-queued manually.
+	///
+	/// # Default
+	/// Defaults to 5.
+	pub fn max_retries(mut self, max_retries: u32) -> Self {
+		self.config.control.max_retries = max_retries;
+		self
+	}
+
+	/// Set the number of seconds to wait before the first retry of a failed block.
+	/// The wait doubles with every attempt.
+	///
+	/// # Default
+	/// Defaults to 30 seconds.
+	pub fn retry_backoff(mut self, secs: u64) -> Self {
+		self.config.control.retry_backoff = secs;
+		self
+	}
+
+	/// Verify that executing a block results in the state root stated in its header.
+	/// Blocks that don't are recorded in the `state_root_mismatches` table, and their storage is not archived.
+	///
+	/// # Default
+	/// Defaults to `false`.
+	pub fn verify_state_root(mut self, verify: bool) -> Self {
+		self.config.control.verify_state_root = verify;
 		self
 	}
 
@@ -350,67 +547,104 @@ where
 		+ 'static,
 	D: NativeExecutionDispatch + 'static,
 	<R::RuntimeApi as sp_api::ApiExt<B>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
-	NumberFor<B>: Into<u32> + From<u32> + Unpin,
+	NumberFor<B>: Into<u64> + From<u32> + Unpin,
 	B::Hash: Unpin + std::str::FromStr,
 	B::Header: serde::de::DeserializeOwned,
 {
 	/// Build this instance of the Archiver.
-	/// Runs the database migrations for the database at `pg_url`.
+	/// Runs the database migrations for the database at `pg_url`,
+	/// and checks that the database belongs to the chain of the node.
 	///
 	/// # Panics
-	/// Panics if one of chain_data_db or pg_url is not passed to the builder
-	/// and their respective environment variables are not set.
+	/// Panics if chain_data_db is not passed to the builder and the `CHAIN_DATA_DB` environment variable is not set.
+	/// Fails if pg_url is not passed to the builder and the `DATABASE_URL` environment variable is not set.
 	pub fn build(mut self) -> Result<impl Archive<B, DB>> {
 		// config logger
 		logger::init(self.config.log.clone())?;
 		log::debug!("Archive Config: {:?}", self.config);
 
 		// config chain
-		const CHAIN_DATA_DB: &str = "CHAIN_DATA_DB";
 		let chain_path = self
 			.config
 			.chain
 			.data_path
+			.clone()
 			.unwrap_or_else(|| env::var(CHAIN_DATA_DB).expect("missing CHAIN_DATA_DB").into());
-		let chain_path = chain_path.to_str().expect("chain data path is invalid");
-		let db_path = create_database_path(
-			self.config.chain.rocksdb_secondary_path,
-			self.config.chain.spec.as_ref().map(AsRef::as_ref),
-		)?;
-		let db = Arc::new(DB::open_database(chain_path, self.config.chain.cache_size, db_path)?);
+		let db = open_chain_database::<DB>(&self.config.chain, chain_path)?;
 
 		// config runtime
+		let native_version = match self.config.runtime.exec_strategy {
+			ExecutionStrategy::Both if self.config.wasm_tracing.is_some() => {
+				log::warn!("Blocks are traced with Wasm only, native and Wasm execution are not compared");
+				None
+			}
+			ExecutionStrategy::Both => Some(D::native_version().runtime_version),
+			_ => None,
+		};
 		self.config.runtime.wasm_runtime_overrides = self.config.wasm_tracing.as_ref().and_then(|c| c.folder.clone());
-		if let Some(spec) = self.config.chain.spec {
+		if let Some(spec) = &self.config.chain.spec {
 			self.config.runtime.set_code_substitutes(spec.as_ref());
 		}
+		if self.config.runtime.wasm_cache_path.is_none() {
+			self.config.runtime.wasm_cache_path = Some(substrate_archive_default_dir().join("wasm_cache"));
+		}
 
 		// configure substrate client and backend
-		let client = Arc::new(runtime_api::<B, R, D, DB>(db.clone(), self.config.runtime)?);
-		let backend = Arc::new(ReadOnlyBackend::new(db, true));
+		let client = Arc::new(runtime_api::<B, R, D, DB>(db.clone(), self.config.runtime.clone())?);
+		// share the trie cache of the client
+		let backend = client.backend();
 		Self::startup_info(&*client, &*backend)?;
 
 		// config postgres database
-		const DATABASE_URL: &str = "DATABASE_URL";
-		let pg_url = self
-			.config
-			.database
-			.map(|config| config.url)
-			.unwrap_or_else(|| env::var(DATABASE_URL).expect("missing DATABASE_URL"));
-		smol::block_on(database::migrate(&pg_url))?;
+		let database =
+			DatabaseConfig::resolve(self.config.database, self.config.chain.spec.as_ref().map(|spec| spec.id()))?;
+		let genesis_hash =
+			backend.genesis_hash()?.ok_or_else(|| BackendError::from("The chain data has no genesis block"))?;
+		let chain_id = self.config.chain.spec.as_ref().map(|spec| spec.id());
+		smol::block_on(async {
+			let mut conn = database::connect(&database).await?;
+			database::check_chain(&mut conn, genesis_hash.as_ref(), chain_id).await?;
+			database::migrate(&database).await?;
+			database::verify_chain(&mut conn, genesis_hash.as_ref(), chain_id).await
+		})?;
 
 		// config actor system
+		self.config.control.apply_max_block_load();
 		let config = SystemConfig::new(
 			backend,
-			pg_url,
+			database,
 			client.clone(),
 			self.config.control,
+			self.config.runtime,
 			self.config.wasm_tracing.map(|t| t.targets),
+			native_version,
 		);
 		let sys = System::<_, R, _, _>::new(client, config)?;
 		Ok(sys)
 	}
 
+	/// Audit the archive database for holes and inconsistencies.
+	/// If a chain data path is configured, the archived blocks are also compared
+	/// against the canonical chain of the node.
+	///
+	/// Fails if pg_url is not passed to the builder and the `DATABASE_URL` environment variable is not set.
+	pub fn audit(self) -> Result<AuditReport> {
+		let chain_path = self.config.chain.data_path.clone().or_else(|| env::var(CHAIN_DATA_DB).ok().map(Into::into));
+		let backend = match chain_path {
+			Some(path) => {
+				let db = open_chain_database::<DB>(&self.config.chain, path)?;
+				Some(ReadOnlyBackend::<B, DB>::new(db, true))
+			}
+			None => None,
+		};
+		let database =
+			DatabaseConfig::resolve(self.config.database, self.config.chain.spec.as_ref().map(|spec| spec.id()))?;
+		smol::block_on(async {
+			let mut conn = database::connect(&database).await?;
+			audit::audit(&mut conn, backend.as_ref()).await
+		})
+	}
+
 	/// Log some general startup info
 	fn startup_info(client: &TArchiveClient<B, R, D, DB>, backend: &ReadOnlyBackend<B, DB>) -> Result<()> {
 		let last_finalized_block = backend.last_finalized()?;
@@ -438,6 +672,15 @@ where
 	}
 }
 
+const CHAIN_DATA_DB: &str = "CHAIN_DATA_DB";
+
+/// Open the chain database of the node at `chain_path`.
+fn open_chain_database<DB: ReadOnlyDb>(config: &ChainConfig, chain_path: PathBuf) -> Result<Arc<DB>> {
+	let chain_path = chain_path.to_str().expect("chain data path is invalid");
+	let db_path = create_database_path(config.rocksdb_secondary_path.clone(), config.spec.as_ref().map(AsRef::as_ref))?;
+	Ok(Arc::new(DB::open_database(config.database, chain_path, config.cache_size, db_path)?))
+}
+
 /// Create the secondary RocksDB directory if it doesn't exist yet.
 /// If the ChainSpec is not specified, a temporary directory is used.
 /// Returns the path to that directory.
@@ -465,3 +708,62 @@ fn create_database_path(db_path: Option<PathBuf>, spec: Option<&dyn ChainSpec>)
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_load_documented_configs() -> Result<()> {
+		for bin in &["edgeware-archive", "generic-archive", "node-template-archive", "polkadot-archive"] {
+			let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../bin").join(bin).join("archive.toml");
+			let config = ArchiveConfig::load(Some(&path))?;
+			assert!(config.chain.data_path.is_some());
+			assert!(config.database.is_some());
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_map_max_block_load_onto_crawl_memory_budget() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let path = dir.path().join("archive.toml");
+		fs::write(&path, "[control]\nmax_block_load = 1048576\n")?;
+		let mut control = ArchiveConfig::load(Some(&path))?.control;
+		control.apply_max_block_load();
+		assert_eq!(control.crawl_memory_budget, 2048);
+		assert_eq!(control.max_block_load, None);
+
+		fs::write(&path, "[control]\nmax_block_load = 10\n")?;
+		let mut control = ArchiveConfig::load(Some(&path))?.control;
+		control.apply_max_block_load();
+		assert_eq!(control.crawl_memory_budget, 1);
+		Ok(())
+	}
+
+	#[test]
+	fn should_fall_back_to_environment() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let path = dir.path().join("archive.toml");
+		fs::write(&path, "[database]\nurl = \"postgres://file/archive\"\n\n[control]\ntask_timeout = 30\n")?;
+		env::set_var(CHAIN_DATA_DB, "/env/db");
+		env::set_var(DATABASE_URL, "postgres://env/archive");
+
+		// values of the file take precedence
+		let config = ArchiveConfig::load(Some(&path))?;
+		assert_eq!(config.chain.data_path, Some(PathBuf::from("/env/db")));
+		assert_eq!(config.database.map(|d| d.url), Some("postgres://file/archive".to_string()));
+		assert_eq!(config.control.task_timeout, 30);
+
+		let config = ArchiveConfig::load(None)?;
+		assert_eq!(config.chain.data_path, Some(PathBuf::from("/env/db")));
+		assert_eq!(config.database.map(|d| d.url), Some("postgres://env/archive".to_string()));
+
+		env::remove_var(CHAIN_DATA_DB);
+		env::remove_var(DATABASE_URL);
+		let config = ArchiveConfig::load(None)?;
+		assert_eq!(config.chain.data_path, None);
+		assert!(config.database.is_none());
+		Ok(())
+	}
+}
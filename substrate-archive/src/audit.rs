@@ -0,0 +1,390 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Integrity checks of an existing archive.
+//! Checks the relational database for holes and inconsistencies,
+//! and optionally compares it against the chain data of the node.
+
+use serde::Serialize;
+use sqlx::PgConnection;
+
+use sp_blockchain::{Backend as _, HeaderBackend as _};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Header as _, NumberFor},
+};
+
+use substrate_archive_backend::{ReadOnlyBackend, ReadOnlyDb};
+
+use crate::error::Result;
+
+/// Number of blocks loaded at once when comparing the archive against the node.
+const NODE_CHUNK_SIZE: i64 = 100_000;
+
+/// Problems found in an archive.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuditReport {
+	/// Number of blocks in the archive.
+	pub blocks: i64,
+	/// Ranges of block numbers missing from the archive, inclusive.
+	pub gaps: Vec<Gap>,
+	/// Blocks whose parent is not archived, although blocks at the number before them are.
+	/// Blocks after a gap are only reported in `gaps`.
+	pub broken_parent_links: Vec<BrokenParentLink>,
+	/// Blocks without any storage rows.
+	pub missing_storage: Vec<MissingStorage>,
+	/// Runtime versions of blocks without a `metadata` row.
+	pub missing_metadata: Vec<MissingMetadata>,
+	/// Storage rows that don't reference an archived block at their block number.
+	pub orphaned_storage: Vec<OrphanedStorage>,
+	/// Comparison against the chain data of the node, if the node was audited.
+	pub node: Option<NodeReport>,
+}
+
+impl AuditReport {
+	/// Whether no problems were found.
+	pub fn is_healthy(&self) -> bool {
+		self.gaps.is_empty()
+			&& self.broken_parent_links.is_empty()
+			&& self.missing_storage.is_empty()
+			&& self.missing_metadata.is_empty()
+			&& self.orphaned_storage.is_empty()
+			&& self.node.as_ref().map(NodeReport::is_healthy).unwrap_or(true)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct Gap {
+	pub start: i64,
+	pub end: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenParentLink {
+	pub block_num: i64,
+	pub hash: String,
+	pub parent_hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MissingStorage {
+	pub block_num: i64,
+	pub hash: String,
+	/// Known reason the storage is missing, if any.
+	/// One of `failed`, `dead` or `state_root_mismatch`.
+	pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct MissingMetadata {
+	pub spec: i32,
+	/// Number of blocks with this runtime version.
+	pub blocks: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrphanedStorage {
+	pub block_num: i64,
+	pub hash: String,
+	/// Number of storage rows.
+	pub rows: i64,
+	/// Block number of the archived block with this hash, if any.
+	pub archived_block_num: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeReport {
+	/// Latest finalized block of the node.
+	pub finalized: u64,
+	/// Finalized blocks of the node after the latest archived block.
+	pub behind: u64,
+	/// Archived blocks that are not canonical according to the node.
+	pub mismatches: Vec<NodeMismatch>,
+	/// Blocks of archived storage rows that are not canonical according to the node.
+	pub non_canonical_storage: Vec<NodeMismatch>,
+}
+
+impl NodeReport {
+	/// Whether the archive keeps up with the node and only holds canonical blocks and storage.
+	pub fn is_healthy(&self) -> bool {
+		self.behind == 0 && self.mismatches.is_empty() && self.non_canonical_storage.is_empty()
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeMismatch {
+	pub block_num: i64,
+	pub archived_hash: String,
+	/// Canonical hash according to the node, if the node has a block at this number.
+	pub node_hash: Option<String>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	format!("0x{}", hex::encode(bytes))
+}
+
+/// Audit the archive database.
+pub async fn audit_database(conn: &mut PgConnection) -> Result<AuditReport> {
+	let (blocks,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM blocks").fetch_one(&mut *conn).await?;
+
+	let gaps = sqlx::query_as::<_, Gap>(
+		r#"
+        SELECT 0 AS start, MIN(block_num) - 1 AS "end" FROM blocks HAVING MIN(block_num) > 0
+        UNION ALL
+        SELECT block_num + 1 AS start, next_num - 1 AS "end" FROM (
+            SELECT block_num, LEAD(block_num) OVER (ORDER BY block_num) AS next_num FROM blocks
+        ) AS nums
+        WHERE next_num > block_num + 1
+        ORDER BY start
+        "#,
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+
+	// several blocks are archived at the numbers of forks, so parents are looked up by hash
+	let broken_parent_links: Vec<(i64, Vec<u8>, Vec<u8>)> = sqlx::query_as(
+		r#"
+        SELECT child.block_num, child.hash, child.parent_hash
+        FROM blocks AS child
+        WHERE child.block_num > 0
+            AND NOT EXISTS (SELECT 1 FROM blocks AS p WHERE p.hash = child.parent_hash)
+            AND EXISTS (SELECT 1 FROM blocks AS p WHERE p.block_num = child.block_num - 1)
+        ORDER BY child.block_num
+        "#,
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+	let broken_parent_links = broken_parent_links
+		.into_iter()
+		.map(|(block_num, h, p)| BrokenParentLink { block_num, hash: to_hex(&h), parent_hash: to_hex(&p) })
+		.collect();
+
+	let missing_storage: Vec<(i64, Vec<u8>, Option<String>)> = sqlx::query_as(
+		r#"
+        SELECT blocks.block_num, blocks.hash,
+            CASE WHEN state_root_mismatches.hash IS NOT NULL THEN 'state_root_mismatch'
+                WHEN failed_blocks.dead THEN 'dead'
+                WHEN failed_blocks.hash IS NOT NULL THEN 'failed'
+            END AS reason
+        FROM blocks
+        LEFT JOIN failed_blocks ON failed_blocks.hash = blocks.hash
+        LEFT JOIN state_root_mismatches ON state_root_mismatches.hash = blocks.hash
+        WHERE NOT EXISTS (SELECT 1 FROM storage_view WHERE storage_view.hash = blocks.hash)
+        ORDER BY blocks.block_num
+        "#,
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+	let missing_storage = missing_storage
+		.into_iter()
+		.map(|(block_num, hash, reason)| MissingStorage { block_num, hash: to_hex(&hash), reason })
+		.collect();
+
+	let missing_metadata = sqlx::query_as::<_, MissingMetadata>(
+		r#"
+        SELECT spec, COUNT(*) AS blocks FROM blocks
+        WHERE NOT EXISTS (SELECT 1 FROM metadata WHERE metadata.version = blocks.spec)
+        GROUP BY spec ORDER BY spec
+        "#,
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+
+	let orphaned_storage: Vec<(i64, Vec<u8>, i64, Option<i64>)> = sqlx::query_as(
+		r#"
+        SELECT storage.block_num, storage.hash, COUNT(*), MIN(blocks.block_num)
+        FROM storage
+        LEFT JOIN blocks ON blocks.hash = storage.hash
+        WHERE blocks.hash IS NULL OR blocks.block_num <> storage.block_num
+        GROUP BY storage.block_num, storage.hash
+        ORDER BY storage.block_num
+        "#,
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+	let orphaned_storage = orphaned_storage
+		.into_iter()
+		.map(|(block_num, hash, rows, archived_block_num)| OrphanedStorage {
+			block_num,
+			hash: to_hex(&hash),
+			rows,
+			archived_block_num,
+		})
+		.collect();
+
+	Ok(AuditReport {
+		blocks,
+		gaps,
+		broken_parent_links,
+		missing_storage,
+		missing_metadata,
+		orphaned_storage,
+		node: None,
+	})
+}
+
+/// Compare the archived blocks and storage against the canonical chain of the node.
+pub async fn audit_node<B, D>(conn: &mut PgConnection, backend: &ReadOnlyBackend<B, D>) -> Result<NodeReport>
+where
+	B: BlockT,
+	D: ReadOnlyDb,
+	NumberFor<B>: Into<u64> + From<u32>,
+{
+	let finalized_hash = backend.last_finalized()?;
+	let finalized: u64 =
+		backend.header(BlockId::Hash(finalized_hash))?.map(|h| (*h.number()).into()).unwrap_or_default();
+
+	let (max,): (Option<i64>,) = sqlx::query_as("SELECT MAX(block_num) FROM blocks").fetch_one(&mut *conn).await?;
+	let behind = match max {
+		Some(max) => finalized.saturating_sub(max as u64),
+		None => finalized + 1,
+	};
+
+	let mismatches = non_canonical(conn, backend, "blocks", max).await?;
+	let (storage_max,): (Option<i64>,) =
+		sqlx::query_as("SELECT MAX(block_num) FROM storage").fetch_one(&mut *conn).await?;
+	let non_canonical_storage = non_canonical(conn, backend, "storage", storage_max).await?;
+
+	Ok(NodeReport { finalized, behind, mismatches, non_canonical_storage })
+}
+
+/// Find the distinct blocks referenced by the rows of `table` up to block `max`
+/// whose hash is not the canonical hash of their number according to the node.
+async fn non_canonical<B, D>(
+	conn: &mut PgConnection,
+	backend: &ReadOnlyBackend<B, D>,
+	table: &str,
+	max: Option<i64>,
+) -> Result<Vec<NodeMismatch>>
+where
+	B: BlockT,
+	D: ReadOnlyDb,
+{
+	let query = format!("SELECT DISTINCT block_num, hash FROM {} WHERE block_num >= $1 AND block_num < $2", table);
+	let mut mismatches = Vec::new();
+	let mut start = 0;
+	while start <= max.unwrap_or(-1) {
+		let blocks: Vec<(i64, Vec<u8>)> = sqlx::query_as(&query)
+			.bind(start)
+			.bind(start.saturating_add(NODE_CHUNK_SIZE))
+			.fetch_all(&mut *conn)
+			.await?;
+		for (block_num, hash) in blocks {
+			let node_hash = backend.canonical_hash(block_num as u64)?;
+			if node_hash.as_ref().map(AsRef::as_ref) != Some(hash.as_slice()) {
+				mismatches.push(NodeMismatch {
+					block_num,
+					archived_hash: to_hex(&hash),
+					node_hash: node_hash.map(|h| to_hex(h.as_ref())),
+				});
+			}
+		}
+		start = start.saturating_add(NODE_CHUNK_SIZE);
+	}
+	mismatches.sort_by_key(|m| m.block_num);
+	Ok(mismatches)
+}
+
+/// Audit the archive database, and if a backend is passed, compare it against the node.
+pub async fn audit<B, D>(conn: &mut PgConnection, backend: Option<&ReadOnlyBackend<B, D>>) -> Result<AuditReport>
+where
+	B: BlockT,
+	D: ReadOnlyDb,
+	NumberFor<B>: Into<u64> + From<u32>,
+{
+	let mut report = audit_database(conn).await?;
+	if let Some(backend) = backend {
+		report.node = Some(audit_node(conn, backend).await?);
+	}
+	Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{initialize, insert_block, TestGuard, PG_POOL};
+	use sp_core::H256;
+	use substrate_archive_backend::MemoryDb;
+	use test_common::{runtime::Block as TestBlock, ChainBuilder};
+
+	#[test]
+	fn should_find_gaps_broken_parent_links_and_missing_metadata() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			// block 4 points to a block that isn't archived although block 3 is, blocks 3 and 8 follow gaps,
+			// and blocks 5 and 6 run a runtime version without metadata
+			for (num, hash, parent, spec) in &[
+				(3i64, [3u8; 2], [2u8; 2], 0),
+				(4, [4u8; 2], [0xff; 2], 0),
+				(5, [5u8; 2], [4u8; 2], 1),
+				(6, [6u8; 2], [5u8; 2], 1),
+				(8, [8u8; 2], [7u8; 2], 0),
+			] {
+				insert_block(&mut *conn, *num, &hash[..], &parent[..], *spec).await?;
+			}
+			let report = audit_database(&mut *conn).await?;
+			assert_eq!(report.blocks, 6);
+			assert_eq!(report.gaps, vec![Gap { start: 1, end: 2 }, Gap { start: 7, end: 7 }]);
+			assert_eq!(
+				report.broken_parent_links,
+				vec![BrokenParentLink { block_num: 4, hash: to_hex(&[4, 4]), parent_hash: to_hex(&[0xff, 0xff]) }]
+			);
+			// the genesis state of the dummy block of the test guard is not archived either
+			assert_eq!(report.missing_storage.len(), 6);
+			assert_eq!(report.missing_metadata, vec![MissingMetadata { spec: 1, blocks: 2 }]);
+			assert!(!report.is_healthy());
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_find_non_canonical_blocks_and_storage() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(2);
+		let fork = chain.fork(blocks[0], Vec::new());
+		chain.finalize(blocks[1]);
+		let backend = ReadOnlyBackend::<TestBlock, MemoryDb>::new(chain.db(), true);
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			// the dummy block of the test guard is not the genesis block of the chain
+			sqlx::query("DELETE FROM blocks").execute(&mut *conn).await?;
+			// the fork was archived as block #2 before the node reorganized onto its sibling
+			for (num, hash) in &[(1i64, blocks[0]), (2, fork)] {
+				insert_block(&mut *conn, *num, hash.as_bytes(), H256::zero().as_bytes(), 0).await?;
+				sqlx::query("INSERT INTO storage (block_num, hash, is_full, key) VALUES ($1, $2, false, $2)")
+					.bind(num)
+					.bind(hash.as_bytes())
+					.execute(&mut *conn)
+					.await?;
+			}
+			let report = audit_node(&mut *conn, &backend).await?;
+			assert_eq!((report.finalized, report.behind), (2, 0));
+			let fork = vec![NodeMismatch {
+				block_num: 2,
+				archived_hash: to_hex(fork.as_bytes()),
+				node_hash: Some(to_hex(blocks[1].as_bytes())),
+			}];
+			assert_eq!(report.mismatches, fork);
+			assert_eq!(report.non_canonical_storage, fork);
+			assert!(!report.is_healthy());
+			Ok(())
+		})
+	}
+}
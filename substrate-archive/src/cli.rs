@@ -0,0 +1,323 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Command line interface shared by the archive binaries.
+//! A binary only supplies the chain spec and runtime types of its chain.
+
+use std::{
+	fs,
+	io::{self, Write},
+	marker::PhantomData,
+	path::PathBuf,
+	str::FromStr,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::PgConnection;
+use structopt::StructOpt;
+
+use sc_chain_spec::ChainSpec;
+use sc_client_api::backend as api_backend;
+use sc_executor::NativeExecutionDispatch;
+use sp_api::{ApiExt, ConstructRuntimeApi};
+use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_runtime::traits::{BlakeTwo256, Block as BlockT, NumberFor};
+
+use substrate_archive_backend::{ReadOnlyBackend, ReadOnlyDb, TArchiveClient};
+
+use crate::{
+	archive::{Archive, ArchiveBuilder, ArchiveConfig},
+	database::{self, queries, BlockModel, DatabaseConfig},
+	error::{ArchiveError, Result},
+	tasks::execute_block,
+};
+
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(author, about)]
+pub struct CliOpts {
+	/// Sets a custom config file
+	#[structopt(short = "c", long, name = "FILE", global = true)]
+	pub config: Option<PathBuf>,
+	/// The chain to archive. Which values are accepted depends on the binary.
+	#[structopt(short = "s", long = "spec", name = "CHAIN", global = true)]
+	pub chain_spec: Option<String>,
+	/// Defaults to `run`.
+	#[structopt(subcommand)]
+	pub cmd: Option<Command>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum Command {
+	/// Run the archive until interrupted.
+	Run,
+	/// Run the database migrations.
+	Migrate,
+	/// Print an overview of the progress of the archive as JSON.
+	Status,
+	/// Check the archive for holes and inconsistencies, and print a JSON report.
+	/// Compares the archive against the node if a chain data path is configured.
+	Audit,
+	/// Queue the archived blocks in a range to be executed again by a running archive.
+	Reindex {
+		/// First block to execute.
+		#[structopt(long)]
+		from: u64,
+		/// Last block to execute. Defaults to the latest archived block.
+		#[structopt(long)]
+		to: Option<u64>,
+	},
+	/// List the blocks that failed to execute, or show the last error of one.
+	Failed {
+		/// Hex-encoded hash of the block.
+		hash: Option<String>,
+	},
+	/// Re-queue a failed block, or all blocks which have exhausted their retries.
+	RequeueFailed {
+		/// Hex-encoded hash of the block.
+		#[structopt(required_unless = "all")]
+		hash: Option<String>,
+		/// Re-queue all blocks which have exhausted their retries.
+		#[structopt(long)]
+		all: bool,
+	},
+	/// Export the archived blocks in a range, with their storage changes, as JSON lines.
+	Export {
+		/// First block to export.
+		#[structopt(long)]
+		from: u64,
+		/// Last block to export. Defaults to the latest archived block.
+		#[structopt(long)]
+		to: Option<u64>,
+		/// File to write to. Defaults to stdout.
+		#[structopt(short = "o", long)]
+		output: Option<PathBuf>,
+	},
+	/// Print the effective config, after merging the config file and environment variables.
+	PrintConfig,
+}
+
+impl CliOpts {
+	pub fn init() -> Self {
+		CliOpts::from_args()
+	}
+
+	/// Read the config file and environment variables.
+	pub fn config(&self) -> Result<ArchiveConfig> {
+		ArchiveConfig::load(self.config.as_deref())
+	}
+
+	/// Execute the command with the runtime types of the chain.
+	pub fn run<B, R, D, DB>(&self, spec: Box<dyn ChainSpec>, config: ArchiveConfig) -> Result<()>
+	where
+		DB: ReadOnlyDb + 'static,
+		B: BlockT + Unpin + DeserializeOwned,
+		R: ConstructRuntimeApi<B, TArchiveClient<B, R, D, DB>> + Send + Sync + 'static,
+		R::RuntimeApi: BlockBuilderApi<B>
+			+ sp_api::Metadata<B>
+			+ ApiExt<B, StateBackend = api_backend::StateBackendFor<ReadOnlyBackend<B, DB>, B>>
+			+ Send
+			+ Sync
+			+ 'static,
+		D: NativeExecutionDispatch + 'static,
+		<R::RuntimeApi as sp_api::ApiExt<B>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
+		NumberFor<B>: Into<u64> + From<u32> + Unpin,
+		B::Hash: Unpin + FromStr,
+		B::Header: DeserializeOwned,
+	{
+		// Postgres config of the archive, in the schema of the chain if `schema_per_chain` is set
+		let database_config = || DatabaseConfig::resolve(config.database.clone(), Some(spec.id()));
+		match self.cmd.clone().unwrap_or(Command::Run) {
+			Command::Run => {
+				let mut archive = ArchiveBuilder::<B, R, D, DB>::with_config(Some(config)).chain_spec(spec).build()?;
+				archive.drive()?;
+				wait_for_interrupt();
+				archive.shutdown()?;
+			}
+			Command::Migrate => {
+				smol::block_on(async {
+					let config = database_config()?;
+					connect_to_chain(&config, spec.id()).await?;
+					database::migrate(&config).await
+				})?;
+				println!("Migrations applied");
+			}
+			Command::Status => {
+				let status = smol::block_on(async {
+					let mut conn = database::connect(&database_config()?).await?;
+					queries::archive_status(&mut conn).await
+				})?;
+				println!("{}", serde_json::to_string_pretty(&status)?);
+			}
+			Command::Audit => {
+				let report = ArchiveBuilder::<B, R, D, DB>::with_config(Some(config)).chain_spec(spec).audit()?;
+				println!("{}", serde_json::to_string_pretty(&report)?);
+			}
+			Command::Reindex { from, to } => {
+				let queued = smol::block_on(async {
+					let mut conn = connect_to_chain(&database_config()?, spec.id()).await?;
+					let to = to.unwrap_or(u64::MAX);
+					let (mut queued, mut last) = (0, None);
+					loop {
+						let mut blocks =
+							queries::blocks_in_range(&mut conn, from, to, last.as_ref(), PAGE_SIZE).await?;
+						if blocks.is_empty() {
+							break;
+						}
+						let jobs = blocks
+							.iter()
+							.map(|b| {
+								let (hash, number) = b.hash_and_number::<B>()?;
+								Ok(execute_block::<B, R, TArchiveClient<B, R, D, DB>, DB>(hash, number, PhantomData))
+							})
+							.collect::<Result<Vec<_>>>()?;
+						queued += jobs.len();
+						coil::JobExt::enqueue_batch(jobs, &mut conn).await?;
+						last = blocks.pop();
+					}
+					Ok::<_, ArchiveError>(queued)
+				})?;
+				println!("Queued {} blocks", queued);
+			}
+			Command::Failed { hash } => smol::block_on(async {
+				let mut conn = database::connect(&database_config()?).await?;
+				match hash {
+					Some(hash) => {
+						let failed = queries::failed_block(&mut conn, &decode_hash(&hash)?).await?;
+						println!("{}", serde_json::to_string_pretty(&failed)?);
+					}
+					None => println!("{}", serde_json::to_string_pretty(&queries::failed_blocks(&mut conn).await?)?),
+				}
+				Ok::<_, ArchiveError>(())
+			})?,
+			Command::RequeueFailed { hash, all } => smol::block_on(async {
+				let mut conn = connect_to_chain(&database_config()?, spec.id()).await?;
+				if all {
+					let n = queries::requeue_dead_blocks(&mut conn).await?;
+					println!("Re-queued {} blocks", n);
+				} else if let Some(hash) = hash {
+					if !queries::requeue_failed_block(&mut conn, &decode_hash(&hash)?).await? {
+						return Err(ArchiveError::NotFailed(hash));
+					}
+					println!("Re-queued {}", hash);
+				}
+				Ok::<_, ArchiveError>(())
+			})?,
+			Command::Export { from, to, output } => {
+				let mut out: Box<dyn Write> = match output {
+					Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+					None => Box::new(io::stdout()),
+				};
+				smol::block_on(async {
+					let mut conn = connect_to_chain(&database_config()?, spec.id()).await?;
+					let to = to.unwrap_or(u64::MAX);
+					let mut last = None;
+					loop {
+						let mut blocks =
+							queries::blocks_in_range(&mut conn, from, to, last.as_ref(), PAGE_SIZE).await?;
+						if blocks.is_empty() {
+							break;
+						}
+						for block in &blocks {
+							let storage = queries::storage_changes(&mut conn, &block.hash).await?;
+							serde_json::to_writer(&mut out, &ExportedBlock::new(block, storage))?;
+							writeln!(out)?;
+						}
+						last = blocks.pop();
+					}
+					Ok::<_, ArchiveError>(())
+				})?;
+				out.flush()?;
+			}
+			Command::PrintConfig => {
+				let config =
+					ArchiveConfig { database: config.database.as_ref().map(DatabaseConfig::redacted), ..config };
+				print!("{}", toml::to_string(&config)?)
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Number of blocks `reindex` and `export` load at once.
+const PAGE_SIZE: i64 = 1000;
+
+/// Connect to the database of the archive, refusing databases recorded for a chain other than `chain_id`.
+async fn connect_to_chain(config: &DatabaseConfig, chain_id: &str) -> Result<PgConnection> {
+	let mut conn = database::connect(config).await?;
+	database::check_chain_id(&mut conn, chain_id).await?;
+	Ok(conn)
+}
+
+/// Block until the process receives SIGINT or SIGTERM.
+fn wait_for_interrupt() {
+	let running = Arc::new(AtomicBool::new(true));
+	let r = running.clone();
+	ctrlc::set_handler(move || {
+		r.store(false, Ordering::SeqCst);
+	})
+	.expect("Error setting Ctrl-C handler");
+	while running.load(Ordering::SeqCst) {
+		std::thread::sleep(Duration::from_millis(100));
+	}
+}
+
+fn decode_hash(hash: &str) -> Result<Vec<u8>> {
+	Ok(hex::decode(hash.trim_start_matches("0x"))?)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	format!("0x{}", hex::encode(bytes))
+}
+
+#[derive(Serialize)]
+struct ExportedBlock {
+	block_num: i64,
+	hash: String,
+	parent_hash: String,
+	state_root: String,
+	extrinsics_root: String,
+	spec: i32,
+	storage: Vec<ExportedStorage>,
+}
+
+#[derive(Serialize)]
+struct ExportedStorage {
+	key: String,
+	/// `None` if the key was deleted.
+	value: Option<String>,
+}
+
+impl ExportedBlock {
+	fn new(block: &BlockModel, storage: Vec<queries::StorageEntry>) -> Self {
+		Self {
+			block_num: block.block_num,
+			hash: to_hex(&block.hash),
+			parent_hash: to_hex(&block.parent_hash),
+			state_root: to_hex(&block.state_root),
+			extrinsics_root: to_hex(&block.extrinsics_root),
+			spec: block.spec,
+			storage: storage
+				.into_iter()
+				.map(|s| ExportedStorage { key: to_hex(&s.key), value: s.storage.as_deref().map(to_hex) })
+				.collect(),
+		}
+	}
+}
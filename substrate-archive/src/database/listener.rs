@@ -23,12 +23,9 @@ use std::{fmt::Display, str::FromStr, time::Duration};
 
 use futures::{future::BoxFuture, FutureExt, StreamExt};
 use serde::{Deserialize, Deserializer, Serialize};
-use sqlx::{
-	postgres::{PgConnection, PgListener, PgNotification},
-	prelude::*,
-};
+use sqlx::postgres::{PgConnection, PgListener, PgNotification};
 
-use crate::error::Result;
+use crate::{database::DatabaseConfig, error::Result};
 
 /// A notification from Postgres about a new row
 #[derive(PartialEq, Debug, Deserialize)]
@@ -99,15 +96,15 @@ where
 {
 	task: F,
 	channels: Vec<Channel>,
-	pg_url: String,
+	database: DatabaseConfig,
 }
 
 impl<F> Builder<F>
 where
 	F: 'static + Send + Sync + for<'a> Fn(Notif, &'a mut PgConnection) -> BoxFuture<'a, Result<()>>,
 {
-	pub fn new(url: &str, f: F) -> Self {
-		Self { task: f, channels: Vec::new(), pg_url: url.to_string() }
+	pub fn new(database: &DatabaseConfig, f: F) -> Self {
+		Self { task: f, channels: Vec::new(), database: database.clone() }
 	}
 
 	pub fn listen_on(mut self, channel: Channel) -> Self {
@@ -119,10 +116,10 @@ where
 	pub async fn spawn(self) -> Result<Listener> {
 		let (tx, rx) = flume::bounded(1);
 
-		let mut listener = PgListener::connect(&self.pg_url).await?;
-		let channels = self.channels.iter().map(String::from).collect::<Vec<String>>();
+		let mut listener = PgListener::connect(&self.database.url).await?;
+		let channels = self.channels.iter().map(|c| self.database.channel(&String::from(c))).collect::<Vec<String>>();
 		listener.listen_all(channels.iter().map(|s| s.as_ref())).await?;
-		let mut conn = PgConnection::connect(&self.pg_url).await.unwrap();
+		let mut conn = crate::database::connect(&self.database).await?;
 
 		let fut = async move {
 			let mut listener = listener.into_stream();
@@ -190,11 +187,11 @@ pub struct Listener {
 }
 
 impl Listener {
-	pub fn builder<F>(pg_url: &str, f: F) -> Builder<F>
+	pub fn builder<F>(database: &DatabaseConfig, f: F) -> Builder<F>
 	where
 		F: 'static + Send + Sync + for<'a> Fn(Notif, &'a mut PgConnection) -> BoxFuture<'a, Result<()>>,
 	{
-		Builder::new(pg_url, f)
+		Builder::new(database, f)
 	}
 
 	#[allow(unused)]
@@ -220,7 +217,7 @@ mod tests {
 		smol::block_on(async move {
 			let (tx, mut rx) = futures::channel::mpsc::channel(5);
 
-			let _listener = Builder::new(&crate::DATABASE_URL, move |_, _| {
+			let _listener = Builder::new(&DatabaseConfig::new(crate::DATABASE_URL.as_str()), move |_, _| {
 				let mut tx1 = tx.clone();
 				async move {
 					log::info!("Hello");
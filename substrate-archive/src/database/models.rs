@@ -18,15 +18,17 @@
 //! Only some types implemented, for convenience most types are already in their database model
 //! equivalents
 
-use std::marker::PhantomData;
+use std::{convert::TryFrom, marker::PhantomData};
 
 use codec::{Decode, Encode, Error as DecodeError};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use sp_core::ed25519;
 use sp_runtime::{
 	generic::SignedBlock,
-	traits::{Block as BlockT, Header as HeaderT},
+	traits::{Block as BlockT, Header as HeaderT, NumberFor},
+	ConsensusEngineId, Justifications,
 };
 use sp_storage::{StorageData, StorageKey};
 
@@ -38,7 +40,7 @@ pub struct BlockModel {
 	pub id: i32,
 	pub parent_hash: Vec<u8>,
 	pub hash: Vec<u8>,
-	pub block_num: i32,
+	pub block_num: i64,
 	pub state_root: Vec<u8>,
 	pub extrinsics_root: Vec<u8>,
 	pub digest: Vec<u8>,
@@ -48,7 +50,8 @@ pub struct BlockModel {
 
 impl BlockModel {
 	pub fn into_block_and_spec<B: BlockT>(self) -> Result<(B, u32), DecodeError> {
-		let block_num = Decode::decode(&mut (self.block_num as u32).encode().as_slice())?;
+		let block_num = NumberFor::<B>::try_from(self.block_num as u64)
+			.map_err(|_| DecodeError::from("Block number does not fit into the block number type of the chain"))?;
 		let extrinsics_root = Decode::decode(&mut self.extrinsics_root.as_slice())?;
 		let state_root = Decode::decode(&mut self.state_root.as_slice())?;
 		let parent_hash = Decode::decode(&mut self.parent_hash.as_slice())?;
@@ -60,6 +63,11 @@ impl BlockModel {
 
 		Ok((B::new(header, ext), spec))
 	}
+
+	/// The hash and number of the block, which is all an `execute_block` job carries.
+	pub fn hash_and_number<B: BlockT>(&self) -> Result<(B::Hash, u64), DecodeError> {
+		Ok((Decode::decode(&mut self.hash.as_slice())?, self.block_num as u64))
+	}
 }
 
 /// Helper struct for decoding block modeling data into block type.
@@ -69,6 +77,8 @@ pub struct BlockModelDecoder<B: BlockT> {
 
 impl<'a, B: BlockT> BlockModelDecoder<B> {
 	/// With a vector of BlockModel
+	///
+	/// The blocks carry no justifications, which are kept in the `justifications` table.
 	pub fn with_vec(blocks: Vec<BlockModel>) -> Result<Vec<Block<B>>, DecodeError> {
 		blocks
 			.into_iter()
@@ -81,10 +91,124 @@ impl<'a, B: BlockT> BlockModelDecoder<B> {
 	}
 }
 
+/// Engine ID of GRANDPA justifications.
+pub const GRANDPA_ENGINE_ID: ConsensusEngineId = *b"FRNK";
+
+/// A justification of a block, as stored by the node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JustificationModel {
+	pub block_num: u64,
+	pub hash: Vec<u8>,
+	pub engine_id: ConsensusEngineId,
+	pub justification: Vec<u8>,
+}
+
+impl JustificationModel {
+	/// All justifications of a block, one per consensus engine.
+	pub fn from_block<B: BlockT>(block: &Block<B>) -> Vec<Self>
+	where
+		NumberFor<B>: Into<u64>,
+	{
+		let header = block.inner.block.header();
+		match &block.inner.justifications {
+			Some(justifications) => {
+				Self::from_justifications((*header.number()).into(), header.hash().as_ref(), justifications)
+			}
+			None => Vec::new(),
+		}
+	}
+
+	/// The justifications of the block `hash`, one per consensus engine.
+	pub fn from_justifications(block_num: u64, hash: &[u8], justifications: &Justifications) -> Vec<Self> {
+		justifications
+			.iter()
+			.map(|(engine_id, justification)| Self {
+				block_num,
+				hash: hash.to_vec(),
+				engine_id: *engine_id,
+				justification: justification.clone(),
+			})
+			.collect()
+	}
+
+	/// Decode the precommits of a GRANDPA justification.
+	/// Returns `None` for justifications of other consensus engines.
+	pub fn grandpa_precommits<B: BlockT>(&self) -> Option<Result<Vec<PrecommitModel>, DecodeError>>
+	where
+		NumberFor<B>: Into<u64>,
+	{
+		if self.engine_id != GRANDPA_ENGINE_ID {
+			return None;
+		}
+		let decoded = GrandpaJustification::<B>::decode(&mut self.justification.as_slice()).map(|justification| {
+			justification
+				.commit
+				.precommits
+				.into_iter()
+				.map(|signed| PrecommitModel {
+					block_num: self.block_num,
+					hash: self.hash.clone(),
+					round: justification.round,
+					target_hash: signed.precommit.target_hash.as_ref().to_vec(),
+					target_num: signed.precommit.target_number.into(),
+					signer: signed.id.0.to_vec(),
+					signature: signed.signature.0.to_vec(),
+				})
+				.collect()
+		});
+		Some(decoded)
+	}
+}
+
+/// A signed precommit of the commit in a GRANDPA justification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrecommitModel {
+	/// Number of the justified block.
+	pub block_num: u64,
+	/// Hash of the justified block.
+	pub hash: Vec<u8>,
+	pub round: u64,
+	pub target_hash: Vec<u8>,
+	pub target_num: u64,
+	pub signer: Vec<u8>,
+	pub signature: Vec<u8>,
+}
+
+/// GRANDPA justification, in the encoding of `sc-finality-grandpa`.
+#[derive(Encode, Decode)]
+pub struct GrandpaJustification<B: BlockT> {
+	pub round: u64,
+	pub commit: GrandpaCommit<B>,
+	pub votes_ancestries: Vec<B::Header>,
+}
+
+/// Commit of a GRANDPA round, finalizing `target_hash`.
+#[derive(Encode, Decode)]
+pub struct GrandpaCommit<B: BlockT> {
+	pub target_hash: B::Hash,
+	pub target_number: NumberFor<B>,
+	pub precommits: Vec<SignedPrecommit<B>>,
+}
+
+/// Precommit of a GRANDPA authority, with its signature.
+#[derive(Encode, Decode)]
+pub struct SignedPrecommit<B: BlockT> {
+	pub precommit: Precommit<B>,
+	pub signature: ed25519::Signature,
+	pub id: ed25519::Public,
+}
+
+/// Vote of a GRANDPA authority to finalize `target_hash` and its ancestors.
+#[derive(Encode, Decode)]
+pub struct Precommit<B: BlockT> {
+	pub target_hash: B::Hash,
+	pub target_number: NumberFor<B>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct StorageModel<Block: BlockT> {
 	hash: Block::Hash,
-	block_num: u32,
+	block_num: u64,
 	full_storage: bool,
 	key: StorageKey,
 	data: Option<StorageData>,
@@ -93,7 +217,7 @@ pub struct StorageModel<Block: BlockT> {
 impl<Block: BlockT> StorageModel<Block> {
 	pub fn new(
 		hash: Block::Hash,
-		block_num: u32,
+		block_num: u64,
 		full_storage: bool,
 		key: StorageKey,
 		data: Option<StorageData>,
@@ -105,7 +229,7 @@ impl<Block: BlockT> StorageModel<Block> {
 		self.full_storage
 	}
 
-	pub fn block_num(&self) -> u32 {
+	pub fn block_num(&self) -> u64 {
 		self.block_num
 	}
 
@@ -122,6 +246,9 @@ impl<Block: BlockT> StorageModel<Block> {
 	}
 }
 
+/// Storage entries whose values are inserted into the content-addressed `storage_values` table.
+pub struct DedupStorage<Block: BlockT>(pub Vec<StorageModel<Block>>);
+
 impl<Block: BlockT> From<Storage<Block>> for Vec<StorageModel<Block>> {
 	fn from(original: Storage<Block>) -> Vec<StorageModel<Block>> {
 		let hash = *original.hash();
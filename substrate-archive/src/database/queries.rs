@@ -19,8 +19,8 @@
 use std::convert::TryFrom;
 
 use hashbrown::HashSet;
-use serde::{de::DeserializeOwned, Deserialize};
-use sqlx::PgConnection;
+use serde::{de::IgnoredAny, Deserialize};
+use sqlx::{Connection, PgConnection};
 
 use sp_runtime::traits::Block as BlockT;
 
@@ -31,14 +31,9 @@ struct Version {
 	version: i32,
 }
 
-/// Return type of queries that `SELECT missing_num ... FROM ... GENERATE_SERIES(a, z)`
-pub struct Series {
-	missing_num: Option<i32>,
-}
-
-/// Return type of queries that `SELECT MAX(int)`
-struct Max {
-	max: Option<i32>,
+/// Return type of queries that `SELECT block_num`
+struct BlockNum {
+	block_num: i64,
 }
 
 /// Return type of queries that `SELECT EXISTS`
@@ -46,70 +41,252 @@ struct DoesExist {
 	exists: Option<bool>,
 }
 
-// Return type of queries that `SELECT block_num`
-struct BlockNum {
-	block_num: i32,
+/// A stage of indexing whose progress is persisted in `indexer_cursor` and `block_gaps`,
+/// so that restarts only need to look at the blocks above the cursor and in the gaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+	/// The block is in the `blocks` table.
+	Crawled,
+	/// The storage changes of the block are in the `storage` table.
+	Executed,
+	/// The digest of the block is decoded into the `digest_logs` and `authority_changes` tables.
+	Decoded,
+	/// The block was checked against the finalized chain of the node, and marked `finalized` if it is part of it.
+	/// Its cursor is set with [`set_cursor`] once a range is checked, so that blocks which were
+	/// reorganized out of the chain are not checked again.
+	Finalized,
 }
 
-// Return type of queries that `SELECT data`
-struct Bytes {
-	data: Vec<u8>,
+impl Stage {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Stage::Crawled => "crawled",
+			Stage::Executed => "executed",
+			Stage::Decoded => "decoded",
+			Stage::Finalized => "finalized",
+		}
+	}
+
+	/// Table or subquery whose rows mark the blocks that passed this stage by their `block_num`.
+	fn table(&self) -> &'static str {
+		match self {
+			Stage::Crawled => "blocks",
+			Stage::Executed => "storage",
+			Stage::Decoded => "(SELECT block_num FROM blocks WHERE decoded) AS decoded_blocks",
+			Stage::Finalized => "(SELECT block_num FROM blocks WHERE finalized) AS finalized_blocks",
+		}
+	}
 }
 
-/// Get missing blocks from the relational database between numbers `min` and
-/// MAX(block_num). LIMIT result to length `max_block_load`. The highest effective
-/// value for `min` is i32::MAX.
-pub(crate) async fn missing_blocks_min_max(
-	conn: &mut PgConnection,
-	min: u32,
-	max_block_load: u32,
-) -> Result<HashSet<u32>> {
-	let min = i32::try_from(min).unwrap_or(i32::MAX);
-	let max_block_load = i64::try_from(max_block_load).unwrap_or(i64::MAX);
-	// Remove after launchbadge/sqlx#594 is fixed
-	#[allow(clippy::toplevel_ref_arg)]
-	Ok(sqlx::query_as!(
-		Series,
-		"SELECT missing_num
-        FROM (SELECT MAX(block_num) AS max_num FROM blocks) max,
-            GENERATE_SERIES($1, max_num) AS missing_num
-        WHERE
-        NOT EXISTS (SELECT id FROM blocks WHERE block_num = missing_num)
-        ORDER BY missing_num ASC
-        LIMIT $2
-        ",
-		min,
-		max_block_load
+/// Get the cursor of `stage`: every block up to it has passed the stage, except the blocks in its gaps.
+/// `None` if the cursor was never advanced.
+pub async fn cursor(conn: &mut PgConnection, stage: Stage) -> Result<Option<u64>> {
+	let cursor: Option<(i64,)> = sqlx::query_as("SELECT block_num FROM indexer_cursor WHERE stage = $1")
+		.bind(stage.as_str())
+		.fetch_optional(conn)
+		.await?;
+	Ok(cursor.map(|(n,)| n as u64))
+}
+
+/// Get the inclusive ranges of block numbers below the cursor of `stage` which have not passed it.
+pub async fn block_gaps(conn: &mut PgConnection, stage: Stage) -> Result<Vec<(u64, u64)>> {
+	let gaps: Vec<(i64, i64)> =
+		sqlx::query_as("SELECT start_num, end_num FROM block_gaps WHERE stage = $1 ORDER BY start_num")
+			.bind(stage.as_str())
+			.fetch_all(conn)
+			.await?;
+	Ok(gaps.into_iter().map(|(start, end)| (start as u64, end as u64)).collect())
+}
+
+/// Move the cursor of `stage` up to `block_num`, if it is below.
+pub(crate) async fn set_cursor(conn: &mut PgConnection, stage: Stage, block_num: u64) -> Result<()> {
+	sqlx::query(
+		r#"
+        INSERT INTO indexer_cursor (stage, block_num) VALUES ($1, $2)
+        ON CONFLICT (stage) DO UPDATE SET block_num = EXCLUDED.block_num, updated_at = (now() at time zone 'utc')
+        WHERE indexer_cursor.block_num < EXCLUDED.block_num
+        "#,
 	)
-	.fetch_all(conn)
-	.await?
-	.iter()
-	.map(|t| t.missing_num.unwrap() as u32)
-	.collect())
+	.bind(stage.as_str())
+	.bind(i64::try_from(block_num)?)
+	.execute(conn)
+	.await?;
+	Ok(())
 }
 
-/// Get the maximum block number from the relational database
-pub(crate) async fn max_block(conn: &mut PgConnection) -> Result<Option<u32>> {
-	let max = sqlx::query_as!(Max, "SELECT MAX(block_num) FROM blocks").fetch_one(conn).await?;
-	Ok(max.max.map(|v| v as u32))
+/// Maximum number of block numbers that passed a stage which one step of [`advance_cursor`] looks up.
+pub(crate) const CURSOR_STEP_BLOCKS: i64 = 100_000;
+
+/// Move the cursor of `stage` to the highest block that passed it,
+/// recording the blocks it skips over as gaps, and drop the gaps that have been filled since.
+///
+/// Advances in steps of [`step_cursor`] until the cursor caught up.
+/// Returns the new cursor, or `None` if no block has passed the stage yet.
+pub(crate) async fn advance_cursor(conn: &mut PgConnection, stage: Stage) -> Result<Option<u64>> {
+	loop {
+		let (cursor, caught_up) = step_cursor(&mut *conn, stage, CURSOR_STEP_BLOCKS).await?;
+		if caught_up {
+			return Ok(cursor);
+		}
+	}
 }
 
-/// Will get blocks such that they exist in the `blocks` table but they
-/// do not exist in the `storage` table
-/// blocks are ordered by spec version
+/// Advance the cursor of `stage` like [`advance_cursor`], but look up at most `limit` blocks that passed it.
 ///
-/// # Returns full blocks
-pub(crate) async fn blocks_storage_intersection(conn: &mut sqlx::PgConnection) -> Result<Vec<BlockModel>> {
-	#[allow(clippy::toplevel_ref_arg)]
-	sqlx::query_as!(
-		BlockModel,
-		"SELECT *
+/// Only the blocks in the previous gaps and above the previous cursor are looked up, lowest first,
+/// one index lookup per block that passed the stage. Gaps and the range above the cursor that are not
+/// reached within `limit`, which must be positive, are left as they are.
+/// Returns the new cursor, and whether it caught up with the highest block that passed the stage.
+pub(crate) async fn step_cursor(conn: &mut PgConnection, stage: Stage, limit: i64) -> Result<(Option<u64>, bool)> {
+	let mut tx = conn.begin().await?;
+	// serialize concurrent advances, which would otherwise insert the same gaps
+	sqlx::query("LOCK TABLE block_gaps IN SHARE ROW EXCLUSIVE MODE").execute(&mut tx).await?;
+
+	let cursor: Option<(i64,)> = sqlx::query_as("SELECT block_num FROM indexer_cursor WHERE stage = $1")
+		.bind(stage.as_str())
+		.fetch_optional(&mut tx)
+		.await?;
+	let cursor = cursor.map(|(n,)| n);
+	let (max,): (Option<i64>,) =
+		sqlx::query_as(&format!("SELECT MAX(block_num) FROM {}", stage.table())).fetch_one(&mut tx).await?;
+	let mut new_cursor = match cursor.max(max) {
+		Some(n) => n,
+		None => return Ok((None, true)),
+	};
+
+	let gaps: Vec<(i64, i64)> =
+		sqlx::query_as("SELECT start_num, end_num FROM block_gaps WHERE stage = $1 ORDER BY start_num")
+			.bind(stage.as_str())
+			.fetch_all(&mut tx)
+			.await?;
+	let above = cursor.map(|c| c + 1).unwrap_or(0);
+	let mut ranges = gaps.into_iter().map(|gap| (gap, true)).collect::<Vec<_>>();
+	if above <= new_cursor {
+		ranges.push(((above, new_cursor), false));
+	}
+
+	// skip from one block that passed the stage to the next, instead of reading every row of the range
+	let passed_query = format!(
+		r#"
+        WITH RECURSIVE passed(num) AS (
+            (SELECT block_num FROM {0} WHERE block_num BETWEEN $1 AND $2 ORDER BY block_num LIMIT 1)
+            UNION ALL
+            SELECT (
+                SELECT block_num FROM {0} WHERE block_num > passed.num AND block_num <= $2
+                ORDER BY block_num LIMIT 1
+            )
+            FROM passed WHERE passed.num IS NOT NULL
+        )
+        SELECT num FROM passed WHERE num IS NOT NULL LIMIT $3
+        "#,
+		stage.table()
+	);
+	let mut new_gaps = Vec::new();
+	let mut budget = limit;
+	let mut caught_up = true;
+	for ((start, end), is_gap) in ranges {
+		if budget <= 0 {
+			caught_up = false;
+			if is_gap {
+				new_gaps.push((start, end));
+			} else {
+				new_cursor = above - 1;
+			}
+			continue;
+		}
+		let passed: Vec<(i64,)> =
+			sqlx::query_as(&passed_query).bind(start).bind(end).bind(budget).fetch_all(&mut tx).await?;
+		budget -= passed.len() as i64;
+		// the range is only looked up to the last block found once the budget is spent
+		let looked_up_to = match passed.last() {
+			Some((last,)) if budget <= 0 => *last,
+			_ => end,
+		};
+		let mut next = start;
+		for (num,) in passed {
+			if num > next {
+				new_gaps.push((next, num - 1));
+			}
+			next = num + 1;
+		}
+		if looked_up_to == end && next <= end {
+			new_gaps.push((next, end));
+		} else if looked_up_to < end {
+			caught_up = false;
+			if is_gap {
+				new_gaps.push((looked_up_to + 1, end));
+			} else {
+				new_cursor = looked_up_to;
+			}
+		}
+	}
+
+	sqlx::query("DELETE FROM block_gaps WHERE stage = $1").bind(stage.as_str()).execute(&mut tx).await?;
+	let (starts, ends): (Vec<i64>, Vec<i64>) = new_gaps.into_iter().unzip();
+	sqlx::query(
+		r#"
+        INSERT INTO block_gaps (stage, start_num, end_num)
+        SELECT $1, * FROM UNNEST($2::bigint[], $3::bigint[])
+        "#,
+	)
+	.bind(stage.as_str())
+	.bind(starts)
+	.bind(ends)
+	.execute(&mut tx)
+	.await?;
+	sqlx::query(
+		r#"
+        INSERT INTO indexer_cursor (stage, block_num) VALUES ($1, $2)
+        ON CONFLICT (stage) DO UPDATE SET block_num = EXCLUDED.block_num, updated_at = (now() at time zone 'utc')
+        "#,
+	)
+	.bind(stage.as_str())
+	.bind(new_cursor)
+	.execute(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok((Some(new_cursor as u64), caught_up))
+}
+
+/// Get the blocks that have not been executed according to the `executed` cursor and its gaps,
+/// and the genesis block if its state has not been archived.
+/// Blocks are ordered by spec version.
+///
+/// Returns the hash and number of each block.
+pub(crate) async fn unexecuted_blocks(conn: &mut PgConnection) -> Result<Vec<(Vec<u8>, u64)>> {
+	let blocks: Vec<(Vec<u8>, i64)> = sqlx::query_as(
+		r#"
+        SELECT hash, block_num
         FROM blocks
-        WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.block_num = blocks.block_num)
-        AND blocks.block_num != 0
-        ORDER BY blocks.spec",
+        WHERE (
+            block_num > COALESCE((SELECT block_num FROM indexer_cursor WHERE stage = 'executed'), -1)
+            OR EXISTS (
+                SELECT 1 FROM block_gaps
+                WHERE stage = 'executed' AND blocks.block_num BETWEEN start_num AND end_num
+            )
+            -- archives from before the genesis state was archived have passed it
+            OR blocks.block_num = 0
+        )
+        AND NOT EXISTS (SELECT 1 FROM storage_view WHERE storage_view.block_num = blocks.block_num)
+        ORDER BY blocks.spec
+        "#,
 	)
 	.fetch_all(conn)
+	.await?;
+	Ok(blocks.into_iter().map(|(hash, num)| (hash, num as u64)).collect())
+}
+
+/// Get a block by hash from the relational database
+pub(crate) async fn get_full_block_by_hash(conn: &mut PgConnection, hash: &[u8]) -> Result<Option<BlockModel>> {
+	sqlx::query_as::<_, BlockModel>(
+		r#"
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+        FROM blocks
+        WHERE hash = $1
+        "#,
+	)
+	.bind(hash)
+	.fetch_optional(conn)
 	.await
 	.map_err(Into::into)
 }
@@ -156,14 +333,14 @@ pub(crate) async fn has_block<B: BlockT>(hash: B::Hash, conn: &mut PgConnection)
 
 /// Get a list of block_numbers, out of the passed-in blocknumbers, which exist in the relational
 /// database
-pub(crate) async fn has_blocks<B: BlockT>(nums: &[u32], conn: &mut PgConnection) -> Result<Vec<u32>> {
-	let nums: Vec<i32> = nums.iter().filter_map(|n| i32::try_from(*n).ok()).collect();
+pub(crate) async fn has_blocks<B: BlockT>(nums: &[u64], conn: &mut PgConnection) -> Result<Vec<u64>> {
+	let nums: Vec<i64> = nums.iter().filter_map(|n| i64::try_from(*n).ok()).collect();
 	#[allow(clippy::toplevel_ref_arg)]
 	Ok(sqlx::query_as!(BlockNum, "SELECT block_num FROM blocks WHERE block_num = ANY ($1)", &nums,)
 		.fetch_all(conn)
 		.await?
 		.into_iter()
-		.map(|r| r.block_num as u32)
+		.map(|r| r.block_num as u64)
 		.collect())
 }
 
@@ -178,22 +355,1106 @@ pub(crate) async fn get_versions(conn: &mut PgConnection) -> Result<Vec<u32>> {
 		.collect())
 }
 
-/// Get all the blocks queued for execution in the background task queue.
-pub(crate) async fn get_all_blocks<B: BlockT + DeserializeOwned>(
+/// Get the numbers of all the blocks queued for execution in the tip queue or the background task queue,
+/// either on their own or as part of a range.
+/// Every queued job is decoded, so this is only meant to be called on startup.
+pub(crate) async fn queued_block_numbers(conn: &mut PgConnection) -> Result<HashSet<u64>> {
+	let tip: Vec<(i64,)> = sqlx::query_as("SELECT block_num FROM tip_queue").fetch_all(&mut *conn).await?;
+	let jobs: Vec<(String, Vec<u8>)> = sqlx::query_as(
+		"SELECT job_type, data FROM _background_tasks WHERE job_type IN ('execute_block', 'execute_block_range')",
+	)
+	.fetch_all(conn)
+	.await?;
+
+	// temporary structs to deserialize jobs
+	#[derive(Deserialize)]
+	struct JobIn {
+		#[serde(rename = "hash")]
+		_hash: IgnoredAny,
+		number: u64,
+	}
+	#[derive(Deserialize)]
+	struct RangeJobIn {
+		hashes: Vec<IgnoredAny>,
+		start: u64,
+	}
+	let mut numbers = tip.into_iter().map(|(n,)| n as u64).collect::<HashSet<_>>();
+	for (job_type, data) in jobs {
+		if job_type == "execute_block_range" {
+			let job: RangeJobIn = rmp_serde::from_read(data.as_slice())?;
+			numbers.extend((job.start..).take(job.hashes.len()));
+		} else {
+			let job: JobIn = rmp_serde::from_read(data.as_slice())?;
+			numbers.insert(job.number);
+		}
+	}
+	Ok(numbers)
+}
+
+/// Get the hashes and numbers of the blocks in the inclusive range `from..=to` that have not been executed,
+/// ordered by number.
+pub(crate) async fn unexecuted_block_refs(conn: &mut PgConnection, from: u64, to: u64) -> Result<Vec<(Vec<u8>, u64)>> {
+	let blocks: Vec<(Vec<u8>, i64)> = sqlx::query_as(
+		r#"
+        SELECT hash, block_num FROM blocks
+        WHERE block_num >= $1 AND block_num <= $2
+        AND NOT EXISTS (SELECT 1 FROM storage_view WHERE storage_view.block_num = blocks.block_num)
+        ORDER BY block_num
+        "#,
+	)
+	.bind(i64::try_from(from).unwrap_or(i64::MAX))
+	.bind(i64::try_from(to).unwrap_or(i64::MAX))
+	.fetch_all(conn)
+	.await?;
+	Ok(blocks.into_iter().map(|(hash, num)| (hash, num as u64)).collect())
+}
+
+/// Get the code hashes and SCALE encoded runtime versions of all the persisted runtime versions.
+pub(crate) async fn runtime_versions(conn: &mut PgConnection) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+	sqlx::query_as("SELECT code_hash, version FROM runtime_versions").fetch_all(conn).await.map_err(Into::into)
+}
+
+/// Persist runtime versions, given as code hash, spec version and SCALE encoded `RuntimeVersion`.
+pub(crate) async fn insert_runtime_versions(
 	conn: &mut PgConnection,
-) -> Result<impl Iterator<Item = Result<B>>> {
-	#[allow(clippy::toplevel_ref_arg)]
-	let blocks = sqlx::query_as!(Bytes, "SELECT data FROM _background_tasks WHERE job_type = 'execute_block'",)
+	versions: Vec<(Vec<u8>, u32, Vec<u8>)>,
+) -> Result<()> {
+	let (mut hashes, mut specs, mut encoded) = (Vec::new(), Vec::new(), Vec::new());
+	for (hash, spec, version) in versions {
+		hashes.push(hash);
+		specs.push(spec as i32);
+		encoded.push(version);
+	}
+	sqlx::query(
+		r#"
+        INSERT INTO runtime_versions (code_hash, spec_version, version)
+        SELECT * FROM UNNEST($1::bytea[], $2::int[], $3::bytea[])
+        ON CONFLICT (code_hash) DO NOTHING
+        "#,
+	)
+	.bind(hashes)
+	.bind(specs)
+	.bind(encoded)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Get the hash of the first archived block of every spec version that has metadata,
+/// but no persisted runtime version.
+pub(crate) async fn unversioned_spec_blocks(conn: &mut PgConnection) -> Result<Vec<Vec<u8>>> {
+	let hashes: Vec<(Vec<u8>,)> = sqlx::query_as(
+		r#"
+        SELECT DISTINCT ON (blocks.spec) blocks.hash
+        FROM blocks
+        JOIN metadata ON metadata.version = blocks.spec
+        WHERE NOT EXISTS (SELECT 1 FROM runtime_versions WHERE runtime_versions.spec_version = blocks.spec)
+        ORDER BY blocks.spec, blocks.block_num
+        "#,
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(hashes.into_iter().map(|(h,)| h).collect())
+}
+
+/// Get the number of tasks in the background task queue.
+pub(crate) async fn queued_tasks(conn: &mut PgConnection) -> Result<i64> {
+	let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM _background_tasks").fetch_one(conn).await?;
+	Ok(count)
+}
+
+/// Queue a block near the tip of the chain, to be executed ahead of the tasks of coil.
+pub(crate) async fn enqueue_tip_block(conn: &mut PgConnection, hash: &[u8], block_num: u64) -> Result<()> {
+	sqlx::query("INSERT INTO tip_queue (hash, block_num) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+		.bind(hash)
+		.bind(i64::try_from(block_num)?)
+		.execute(conn)
+		.await?;
+	Ok(())
+}
+
+/// Take at most `limit` blocks that no worker is executing off the tip queue, lowest first.
+/// The blocks stay queued until `finish_tip_block` is called for them.
+pub(crate) async fn take_tip_blocks(conn: &mut PgConnection, limit: i64) -> Result<Vec<(Vec<u8>, u64)>> {
+	let mut blocks: Vec<(Vec<u8>, i64)> = sqlx::query_as(
+		r#"
+        UPDATE tip_queue SET taken = true WHERE hash IN (
+            SELECT hash FROM tip_queue WHERE NOT taken ORDER BY block_num LIMIT $1 FOR UPDATE SKIP LOCKED
+        )
+        RETURNING hash, block_num
+        "#,
+	)
+	.bind(limit)
+	.fetch_all(conn)
+	.await?;
+	blocks.sort_by_key(|(_, num)| *num);
+	Ok(blocks.into_iter().map(|(hash, num)| (hash, num as u64)).collect())
+}
+
+/// Remove a block that has been executed, or recorded as failed, from the tip queue.
+pub(crate) async fn finish_tip_block(conn: &mut PgConnection, hash: &[u8]) -> Result<()> {
+	sqlx::query("DELETE FROM tip_queue WHERE hash = $1").bind(hash).execute(conn).await?;
+	Ok(())
+}
+
+/// Release the blocks taken by workers that stopped before executing them.
+pub(crate) async fn release_tip_blocks(conn: &mut PgConnection) -> Result<u64> {
+	Ok(sqlx::query("UPDATE tip_queue SET taken = false WHERE taken").execute(conn).await?.rows_affected())
+}
+
+/// Release a block that was taken off the tip queue but not finished, so that it is taken again.
+pub(crate) async fn release_tip_block(conn: &mut PgConnection, hash: &[u8]) -> Result<()> {
+	sqlx::query("UPDATE tip_queue SET taken = false WHERE hash = $1").bind(hash).execute(conn).await?;
+	Ok(())
+}
+
+/// Get the numbers of the blocks in the tip queue in the inclusive range `from..=to`.
+pub(crate) async fn tip_block_numbers(conn: &mut PgConnection, from: u64, to: u64) -> Result<HashSet<u64>> {
+	let numbers: Vec<(i64,)> = sqlx::query_as("SELECT block_num FROM tip_queue WHERE block_num BETWEEN $1 AND $2")
+		.bind(i64::try_from(from).unwrap_or(i64::MAX))
+		.bind(i64::try_from(to).unwrap_or(i64::MAX))
 		.fetch_all(conn)
 		.await?;
+	Ok(numbers.into_iter().map(|(n,)| n as u64).collect())
+}
 
-	// temporary struct to deserialize job
-	#[derive(Deserialize)]
-	struct JobIn<BL: BlockT> {
-		block: BL,
+/// A storage entry as resolved by `storage_view`.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntry {
+	pub block_num: i64,
+	pub is_full: bool,
+	pub key: Vec<u8>,
+	pub storage: Option<Vec<u8>>,
+}
+
+/// Get all storage changes recorded for the block with hash `hash`.
+///
+/// Values are resolved through `storage_view`, so this works whether or not
+/// the storage was inserted with deduplication enabled.
+pub async fn storage_changes(conn: &mut PgConnection, hash: &[u8]) -> Result<Vec<StorageEntry>> {
+	sqlx::query_as::<_, StorageEntry>(
+		"SELECT block_num, is_full, key, storage FROM storage_view WHERE hash = $1 ORDER BY key",
+	)
+	.bind(hash)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// A block whose execution failed.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FailedBlock {
+	pub block_num: i64,
+	pub hash: Vec<u8>,
+	/// Runtime version of the block, if the block has been indexed.
+	pub spec: Option<i32>,
+	/// Error of the last attempt.
+	pub error: String,
+	/// Times the block has been executed.
+	pub attempts: i32,
+	/// Retries are exhausted, and the block will only be executed again if re-queued.
+	pub dead: bool,
+	/// When the block is re-queued next.
+	pub next_retry: Option<chrono::NaiveDateTime>,
+	pub last_attempt: chrono::NaiveDateTime,
+}
+
+/// Get all failed blocks, ordered by block number.
+pub async fn failed_blocks(conn: &mut PgConnection) -> Result<Vec<FailedBlock>> {
+	sqlx::query_as::<_, FailedBlock>(
+		r#"
+        SELECT block_num, hash, spec, error, attempts, dead, next_retry, last_attempt
+        FROM failed_blocks ORDER BY block_num
+        "#,
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Get the failed block with hash `hash`.
+pub async fn failed_block(conn: &mut PgConnection, hash: &[u8]) -> Result<Option<FailedBlock>> {
+	sqlx::query_as::<_, FailedBlock>(
+		r#"
+        SELECT block_num, hash, spec, error, attempts, dead, next_retry, last_attempt
+        FROM failed_blocks WHERE hash = $1
+        "#,
+	)
+	.bind(hash)
+	.fetch_optional(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Schedule the failed block with hash `hash` to be executed again,
+/// resetting its attempts. A running archive picks the block up on its next retry sweep.
+///
+/// Returns `false` if the block has not failed, or is being retried already.
+pub async fn requeue_failed_block(conn: &mut PgConnection, hash: &[u8]) -> Result<bool> {
+	let res = sqlx::query(
+		r#"
+        UPDATE failed_blocks SET attempts = 0, dead = false, next_retry = (now() at time zone 'utc')
+        WHERE hash = $1 AND (dead OR next_retry IS NOT NULL)
+        "#,
+	)
+	.bind(hash)
+	.execute(conn)
+	.await?;
+	Ok(res.rows_affected() > 0)
+}
+
+/// Schedule all dead blocks to be executed again. Returns the number of blocks re-queued.
+pub async fn requeue_dead_blocks(conn: &mut PgConnection) -> Result<u64> {
+	let res = sqlx::query(
+		r#"
+        UPDATE failed_blocks SET attempts = 0, dead = false, next_retry = (now() at time zone 'utc')
+        WHERE dead
+        "#,
+	)
+	.execute(conn)
+	.await?;
+	Ok(res.rows_affected())
+}
+
+/// Record a failed execution of a block.
+///
+/// Until the block has been retried `max_retries` times, the next retry is scheduled
+/// with an exponential backoff starting at `backoff` seconds. After that the block is marked dead.
+pub(crate) async fn record_failed_block(
+	conn: &mut PgConnection,
+	hash: &[u8],
+	block_num: u64,
+	error: &str,
+	max_retries: u32,
+	backoff: u64,
+) -> Result<()> {
+	sqlx::query(
+		r#"
+        INSERT INTO failed_blocks (block_num, hash, spec, error, attempts, dead, next_retry, last_attempt)
+        VALUES (
+            $1, $2, (SELECT spec FROM blocks WHERE hash = $2), $3, 1, $4 < 1,
+            CASE WHEN $4 < 1 THEN NULL
+                ELSE (now() at time zone 'utc') + make_interval(secs => $5) END,
+            (now() at time zone 'utc')
+        )
+        ON CONFLICT (hash) DO UPDATE SET
+            spec = EXCLUDED.spec,
+            error = EXCLUDED.error,
+            attempts = failed_blocks.attempts + 1,
+            dead = failed_blocks.attempts >= $4,
+            next_retry = CASE WHEN failed_blocks.attempts >= $4 THEN NULL
+                ELSE (now() at time zone 'utc') + make_interval(secs => $5 * power(2, failed_blocks.attempts)) END,
+            last_attempt = EXCLUDED.last_attempt
+        "#,
+	)
+	.bind(i64::try_from(block_num)?)
+	.bind(hash)
+	.bind(error)
+	.bind(i32::try_from(max_retries)?)
+	.bind(backoff as f64)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Take the hash and number of the failed blocks that are due for a retry.
+/// The retry is unscheduled, to be scheduled again only if the block fails again.
+pub(crate) async fn take_due_failed_blocks(conn: &mut PgConnection) -> Result<Vec<(Vec<u8>, u64)>> {
+	let blocks: Vec<(Vec<u8>, i64)> = sqlx::query_as(
+		r#"
+        WITH due AS (
+            UPDATE failed_blocks SET next_retry = NULL
+            WHERE NOT dead AND next_retry <= (now() at time zone 'utc')
+            RETURNING hash
+        )
+        SELECT hash, block_num FROM blocks WHERE hash IN (SELECT hash FROM due)
+        "#,
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(blocks.into_iter().map(|(hash, num)| (hash, num as u64)).collect())
+}
+
+/// Get at most `limit` blocks whose digest has not been decoded yet, lowest first.
+pub(crate) async fn undecoded_blocks(conn: &mut PgConnection, limit: i64) -> Result<Vec<BlockModel>> {
+	sqlx::query_as::<_, BlockModel>(
+		r#"
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+        FROM blocks WHERE NOT decoded
+        ORDER BY block_num
+        LIMIT $1
+        "#,
+	)
+	.bind(limit)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Remove failed blocks that have been retried successfully.
+pub(crate) async fn clear_recovered_blocks(conn: &mut PgConnection) -> Result<u64> {
+	let res = sqlx::query(
+		r#"
+        DELETE FROM failed_blocks
+        WHERE NOT dead AND next_retry IS NULL
+        AND EXISTS (SELECT 1 FROM storage_view WHERE storage_view.hash = failed_blocks.hash)
+        "#,
+	)
+	.execute(conn)
+	.await?;
+	Ok(res.rows_affected())
+}
+
+/// Hashes of blocks that are not to be restored on startup:
+/// failed blocks that are dead or waiting for a retry, and blocks with a mismatching state root.
+pub(crate) async fn skipped_block_hashes(conn: &mut PgConnection) -> Result<HashSet<Vec<u8>>> {
+	let hashes: Vec<(Vec<u8>,)> = sqlx::query_as(
+		r#"
+        SELECT hash FROM failed_blocks WHERE dead OR next_retry IS NOT NULL
+        UNION SELECT hash FROM state_root_mismatches
+        "#,
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(hashes.into_iter().map(|(h,)| h).collect())
+}
+
+/// A block whose execution did not result in the state root stated in its header.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StateRootMismatch {
+	pub block_num: i64,
+	pub hash: Vec<u8>,
+	pub spec: Option<i32>,
+	/// State root from the block header.
+	pub expected: Vec<u8>,
+	/// Storage root computed by executing the block.
+	pub computed: Vec<u8>,
+	pub detected_at: chrono::NaiveDateTime,
+}
+
+/// Get all blocks with a mismatching state root, ordered by block number.
+pub async fn state_root_mismatches(conn: &mut PgConnection) -> Result<Vec<StateRootMismatch>> {
+	sqlx::query_as::<_, StateRootMismatch>(
+		r#"
+        SELECT block_num, hash, spec, expected, computed, detected_at
+        FROM state_root_mismatches ORDER BY block_num
+        "#,
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Record a block whose execution did not result in the state root stated in its header.
+pub(crate) async fn record_state_root_mismatch(
+	conn: &mut PgConnection,
+	hash: &[u8],
+	block_num: u64,
+	expected: &[u8],
+	computed: &[u8],
+) -> Result<()> {
+	sqlx::query(
+		r#"
+        INSERT INTO state_root_mismatches (block_num, hash, spec, expected, computed)
+        VALUES ($1, $2, (SELECT spec FROM blocks WHERE hash = $2), $3, $4)
+        ON CONFLICT (hash) DO UPDATE SET
+            spec = EXCLUDED.spec,
+            expected = EXCLUDED.expected,
+            computed = EXCLUDED.computed,
+            detected_at = EXCLUDED.detected_at
+        "#,
+	)
+	.bind(i64::try_from(block_num)?)
+	.bind(hash)
+	.bind(expected)
+	.bind(computed)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// A block whose storage changes differ between native and Wasm execution.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ExecutionDivergence {
+	pub block_num: i64,
+	pub hash: Vec<u8>,
+	pub spec: Option<i32>,
+	/// Error of the native execution, if it failed.
+	pub native_error: Option<String>,
+	/// Storage keys whose value differs between both executions.
+	pub keys: Vec<Vec<u8>>,
+	pub detected_at: chrono::NaiveDateTime,
+}
+
+/// Get all blocks whose native and Wasm execution diverged, ordered by block number.
+pub async fn execution_divergences(conn: &mut PgConnection) -> Result<Vec<ExecutionDivergence>> {
+	sqlx::query_as::<_, ExecutionDivergence>(
+		r#"
+        SELECT block_num, hash, spec, native_error, keys, detected_at
+        FROM execution_divergences ORDER BY block_num
+        "#,
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Record a block whose native and Wasm execution diverged.
+pub(crate) async fn record_execution_divergence(
+	conn: &mut PgConnection,
+	hash: &[u8],
+	block_num: u64,
+	native_error: Option<&str>,
+	keys: Vec<Vec<u8>>,
+) -> Result<()> {
+	sqlx::query(
+		r#"
+        INSERT INTO execution_divergences (block_num, hash, spec, native_error, keys)
+        VALUES ($1, $2, (SELECT spec FROM blocks WHERE hash = $2), $3, $4)
+        ON CONFLICT (hash) DO UPDATE SET
+            spec = EXCLUDED.spec,
+            native_error = EXCLUDED.native_error,
+            keys = EXCLUDED.keys,
+            detected_at = EXCLUDED.detected_at
+        "#,
+	)
+	.bind(i64::try_from(block_num)?)
+	.bind(hash)
+	.bind(native_error)
+	.bind(keys)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// A justification of a block, as stored by the node.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Justification {
+	pub block_num: i64,
+	pub hash: Vec<u8>,
+	/// `ConsensusEngineId` of the justification.
+	pub engine_id: Vec<u8>,
+	/// Justification in the encoding of its consensus engine.
+	pub justification: Vec<u8>,
+}
+
+/// Get the justifications of a block.
+pub async fn justifications(conn: &mut PgConnection, hash: &[u8]) -> Result<Vec<Justification>> {
+	sqlx::query_as::<_, Justification>(
+		r#"
+        SELECT block_num, hash, engine_id, justification
+        FROM justifications WHERE hash = $1 ORDER BY engine_id
+        "#,
+	)
+	.bind(hash)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// A signed precommit of the commit that justifies a block.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct GrandpaPrecommit {
+	pub block_num: i64,
+	pub hash: Vec<u8>,
+	pub round: i64,
+	/// Block the authority voted for, the justified block or one of its descendants.
+	pub target_hash: Vec<u8>,
+	pub target_num: i64,
+	/// ed25519 public key of the authority.
+	pub signer: Vec<u8>,
+	pub signature: Vec<u8>,
+}
+
+/// Get the precommits of the GRANDPA justification of a block, ordered by signer.
+pub async fn grandpa_precommits(conn: &mut PgConnection, hash: &[u8]) -> Result<Vec<GrandpaPrecommit>> {
+	sqlx::query_as::<_, GrandpaPrecommit>(
+		r#"
+        SELECT block_num, hash, round, target_hash, target_num, signer, signature
+        FROM grandpa_precommits WHERE hash = $1 ORDER BY signer, target_num
+        "#,
+	)
+	.bind(hash)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Set the timestamps of blocks, identified by their hash, that do not have one yet.
+pub(crate) async fn set_missing_timestamps(
+	conn: &mut PgConnection,
+	timestamps: Vec<(Vec<u8>, chrono::NaiveDateTime)>,
+) -> Result<()> {
+	let (hashes, timestamps): (Vec<Vec<u8>>, Vec<chrono::NaiveDateTime>) = timestamps.into_iter().unzip();
+	sqlx::query(
+		r#"
+        UPDATE blocks SET timestamp = new.timestamp
+        FROM UNNEST($1::bytea[], $2::timestamp[]) AS new (hash, timestamp)
+        WHERE blocks.hash = new.hash AND blocks.timestamp IS NULL
+        "#,
+	)
+	.bind(hashes)
+	.bind(timestamps)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Get the number of the last block authored at or before `time`.
+pub async fn block_at_time(conn: &mut PgConnection, time: chrono::NaiveDateTime) -> Result<Option<u64>> {
+	let block: Option<(i64,)> = sqlx::query_as(
+		r#"
+        SELECT block_num FROM blocks WHERE timestamp <= $1
+        ORDER BY timestamp DESC, block_num DESC LIMIT 1
+        "#,
+	)
+	.bind(time)
+	.fetch_optional(conn)
+	.await?;
+	Ok(block.map(|(num,)| num as u64))
+}
+
+/// Get the first and last number of the blocks authored in the time range `from..to`.
+/// Returns `None` if no block was authored in the range.
+pub async fn blocks_between(
+	conn: &mut PgConnection,
+	from: chrono::NaiveDateTime,
+	to: chrono::NaiveDateTime,
+) -> Result<Option<(u64, u64)>> {
+	let range: (Option<i64>, Option<i64>) = sqlx::query_as(
+		r#"
+        SELECT MIN(block_num), MAX(block_num) FROM blocks
+        WHERE timestamp >= $1 AND timestamp < $2
+        "#,
+	)
+	.bind(from)
+	.bind(to)
+	.fetch_one(conn)
+	.await?;
+	Ok(match range {
+		(Some(first), Some(last)) => Some((first as u64, last as u64)),
+		_ => None,
+	})
+}
+
+/// Get at most `limit` blocks that are not marked finalized, with numbers in the inclusive range `from..=to`.
+/// Returns their numbers and hashes, ordered by block number.
+pub(crate) async fn unfinalized_blocks(
+	conn: &mut PgConnection,
+	from: u64,
+	to: u64,
+	limit: i64,
+) -> Result<Vec<(u64, Vec<u8>)>> {
+	let blocks: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+		r#"
+        SELECT block_num, hash FROM blocks
+        WHERE NOT finalized AND block_num >= $1 AND block_num <= $2
+        ORDER BY block_num LIMIT $3
+        "#,
+	)
+	.bind(i64::try_from(from).unwrap_or(i64::MAX))
+	.bind(i64::try_from(to).unwrap_or(i64::MAX))
+	.bind(limit)
+	.fetch_all(conn)
+	.await?;
+	blocks.into_iter().map(|(num, hash)| Ok((u64::try_from(num)?, hash))).collect()
+}
+
+/// Mark the blocks with `hashes` as finalized.
+pub(crate) async fn mark_finalized(conn: &mut PgConnection, hashes: Vec<Vec<u8>>) -> Result<u64> {
+	let res = sqlx::query("UPDATE blocks SET finalized = true WHERE hash = ANY($1::bytea[])")
+		.bind(hashes)
+		.execute(conn)
+		.await?;
+	Ok(res.rows_affected())
+}
+
+/// Delete the blocks with `hashes`, which were reorganized out of the canonical chain,
+/// along with their storage, digests and justifications, and the tip queue entries of them.
+///
+/// The numbers of deleted blocks below the `executed` cursor become its gaps again,
+/// so that the blocks archived in their place are executed.
+/// Returns the number of deleted blocks.
+pub(crate) async fn remove_blocks(conn: &mut PgConnection, hashes: Vec<Vec<u8>>) -> Result<u64> {
+	let mut tx = conn.begin().await?;
+	sqlx::query("LOCK TABLE block_gaps IN SHARE ROW EXCLUSIVE MODE").execute(&mut tx).await?;
+	for table in &["digest_logs", "authority_changes", "justifications", "grandpa_precommits", "tip_queue"] {
+		sqlx::query(&format!("DELETE FROM {} WHERE hash = ANY($1::bytea[])", table))
+			.bind(&hashes)
+			.execute(&mut tx)
+			.await?;
+	}
+	// storage, failures and state root mismatches are deleted along with the blocks
+	let nums: Vec<(i64,)> = sqlx::query_as("DELETE FROM blocks WHERE hash = ANY($1::bytea[]) RETURNING block_num")
+		.bind(&hashes)
+		.fetch_all(&mut tx)
+		.await?;
+	let nums = nums.into_iter().map(|(n,)| n).collect::<Vec<_>>();
+	sqlx::query(
+		r#"
+        INSERT INTO block_gaps (stage, start_num, end_num)
+        SELECT $1, num, num FROM UNNEST($2::bigint[]) AS num
+        WHERE num <= (SELECT block_num FROM indexer_cursor WHERE stage = $1)
+        AND NOT EXISTS (SELECT 1 FROM block_gaps WHERE stage = $1 AND num BETWEEN start_num AND end_num)
+        "#,
+	)
+	.bind(Stage::Executed.as_str())
+	.bind(&nums)
+	.execute(&mut tx)
+	.await?;
+	tx.commit().await?;
+	Ok(nums.len() as u64)
+}
+
+/// Get a page of at most `limit` blocks with numbers in the inclusive range `from..=to`,
+/// ordered by block number and id.
+///
+/// The next page starts after the last block of the previous page, given as `after`.
+pub(crate) async fn blocks_in_range(
+	conn: &mut PgConnection,
+	from: u64,
+	to: u64,
+	after: Option<&BlockModel>,
+	limit: i64,
+) -> Result<Vec<BlockModel>> {
+	// ids are positive, so `(from, 0)` precedes all blocks numbered `from`
+	let (after_num, after_id) = match after {
+		Some(block) => (block.block_num, block.id),
+		None => (i64::try_from(from).unwrap_or(i64::MAX), 0),
+	};
+	sqlx::query_as::<_, BlockModel>(
+		r#"
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+        FROM blocks WHERE (block_num, id) > ($1, $2) AND block_num <= $3
+        ORDER BY block_num, id
+        LIMIT $4
+        "#,
+	)
+	.bind(after_num)
+	.bind(after_id)
+	.bind(i64::try_from(to).unwrap_or(i64::MAX))
+	.bind(limit)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Identity of the chain an archive belongs to.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ArchiveInfo {
+	pub genesis_hash: Vec<u8>,
+	/// ID of the chain spec, if the archive was started with one.
+	pub chain_id: Option<String>,
+	/// Version of the last migration of the archive that wrote to the database.
+	pub schema_version: i64,
+}
+
+/// Get the identity of the chain recorded in the database, if any.
+pub async fn archive_info(conn: &mut PgConnection) -> Result<Option<ArchiveInfo>> {
+	sqlx::query_as::<_, ArchiveInfo>("SELECT genesis_hash, chain_id, schema_version FROM archive_info")
+		.fetch_optional(conn)
+		.await
+		.map_err(Into::into)
+}
+
+/// Check whether `table` exists in the schema of the connection, before the migrations that create it ran.
+pub(crate) async fn table_exists(conn: &mut PgConnection, table: &str) -> Result<bool> {
+	let (exists,): (bool,) =
+		sqlx::query_as("SELECT to_regclass($1::text) IS NOT NULL").bind(table).fetch_one(conn).await?;
+	Ok(exists)
+}
+
+/// Get the hash of the archived genesis block, if it is archived.
+pub(crate) async fn genesis_block_hash(conn: &mut PgConnection) -> Result<Option<Vec<u8>>> {
+	let hash: Option<(Vec<u8>,)> =
+		sqlx::query_as("SELECT hash FROM blocks WHERE block_num = 0").fetch_optional(conn).await?;
+	Ok(hash.map(|(hash,)| hash))
+}
+
+/// Record the identity of the chain, replacing the one recorded before.
+pub(crate) async fn set_archive_info(conn: &mut PgConnection, info: &ArchiveInfo) -> Result<()> {
+	sqlx::query(
+		r#"
+        INSERT INTO archive_info (genesis_hash, chain_id, schema_version) VALUES ($1, $2, $3)
+        ON CONFLICT (id) DO UPDATE SET
+        genesis_hash = EXCLUDED.genesis_hash,
+        chain_id = EXCLUDED.chain_id,
+        schema_version = EXCLUDED.schema_version
+        "#,
+	)
+	.bind(info.genesis_hash.as_slice())
+	.bind(info.chain_id.as_deref())
+	.bind(info.schema_version)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Overview of the progress of an archive.
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ArchiveStatus {
+	/// Number of archived blocks.
+	pub blocks: i64,
+	/// Highest archived block number.
+	pub max_block: Option<i64>,
+	/// Archived blocks without storage.
+	pub missing_storage: i64,
+	/// Tasks queued in coil, each executing a block or a range of blocks.
+	pub queued: i64,
+	/// Blocks at the tip of the chain queued for execution ahead of the tasks of coil.
+	pub queued_tip: i64,
+	/// Failed blocks waiting for a retry.
+	pub failed: i64,
+	/// Failed blocks which have exhausted their retries.
+	pub dead: i64,
+	pub state_root_mismatches: i64,
+	pub execution_divergences: i64,
+	/// Cursor of the `crawled` stage.
+	pub crawled: Option<i64>,
+	/// Cursor of the `executed` stage.
+	pub executed: Option<i64>,
+	/// Cursor of the `decoded` stage.
+	pub decoded: Option<i64>,
+	/// Cursor of the `finalized` stage.
+	pub finalized: Option<i64>,
+}
+
+/// Get an overview of the progress of the archive.
+pub async fn archive_status(conn: &mut PgConnection) -> Result<ArchiveStatus> {
+	sqlx::query_as::<_, ArchiveStatus>(
+		r#"
+        SELECT
+            (SELECT COUNT(*) FROM blocks) AS blocks,
+            (SELECT MAX(block_num) FROM blocks) AS max_block,
+            (SELECT COUNT(*) FROM blocks
+                WHERE NOT EXISTS (SELECT 1 FROM storage_view WHERE storage_view.hash = blocks.hash)
+            ) AS missing_storage,
+            (SELECT COUNT(*) FROM _background_tasks) AS queued,
+            (SELECT COUNT(*) FROM tip_queue) AS queued_tip,
+            (SELECT COUNT(*) FROM failed_blocks WHERE NOT dead) AS failed,
+            (SELECT COUNT(*) FROM failed_blocks WHERE dead) AS dead,
+            (SELECT COUNT(*) FROM state_root_mismatches) AS state_root_mismatches,
+            (SELECT COUNT(*) FROM execution_divergences) AS execution_divergences,
+            (SELECT block_num FROM indexer_cursor WHERE stage = 'crawled') AS crawled,
+            (SELECT block_num FROM indexer_cursor WHERE stage = 'executed') AS executed,
+            (SELECT block_num FROM indexer_cursor WHERE stage = 'decoded') AS decoded,
+            (SELECT block_num FROM indexer_cursor WHERE stage = 'finalized') AS finalized
+        "#,
+	)
+	.fetch_one(conn)
+	.await
+	.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{initialize, TestGuard, PG_POOL};
+
+	/// Insert block `num`, whose hash is its number.
+	async fn insert_num(conn: &mut PgConnection, num: i64) -> Result<()> {
+		let hash = [num as u8; 2];
+		Ok(crate::insert_num(conn, num, &hash, &hash, 0).await?)
+	}
+
+	#[test]
+	fn should_advance_cursor_over_gaps() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			for num in &[1, 2, 5] {
+				insert_num(&mut *conn, *num).await?;
+			}
+			// the dummy block 0 of the test guard and block 1 use up the first step
+			assert_eq!(step_cursor(&mut *conn, Stage::Crawled, 2).await?, (Some(1), false));
+			assert!(block_gaps(&mut *conn, Stage::Crawled).await?.is_empty());
+			assert_eq!(step_cursor(&mut *conn, Stage::Crawled, 2).await?, (Some(5), true));
+			assert_eq!(block_gaps(&mut *conn, Stage::Crawled).await?, vec![(3, 4)]);
+			assert_eq!(advance_cursor(&mut *conn, Stage::Crawled).await?, Some(5));
+			assert_eq!(block_gaps(&mut *conn, Stage::Crawled).await?, vec![(3, 4)]);
+
+			insert_num(&mut *conn, 3).await?;
+			insert_num(&mut *conn, 7).await?;
+			assert_eq!(advance_cursor(&mut *conn, Stage::Crawled).await?, Some(7));
+			assert_eq!(cursor(&mut *conn, Stage::Crawled).await?, Some(7));
+			assert_eq!(block_gaps(&mut *conn, Stage::Crawled).await?, vec![(4, 4), (6, 6)]);
+
+			// gaps that are not reached within the limit are kept
+			insert_num(&mut *conn, 4).await?;
+			insert_num(&mut *conn, 6).await?;
+			assert_eq!(step_cursor(&mut *conn, Stage::Crawled, 1).await?, (Some(7), false));
+			assert_eq!(block_gaps(&mut *conn, Stage::Crawled).await?, vec![(6, 6)]);
+			assert_eq!(advance_cursor(&mut *conn, Stage::Crawled).await?, Some(7));
+			assert!(block_gaps(&mut *conn, Stage::Crawled).await?.is_empty());
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_take_and_release_tip_blocks() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			for num in &[3u64, 1, 2, 1] {
+				enqueue_tip_block(&mut *conn, &[*num as u8; 2], *num).await?;
+			}
+			assert_eq!(queued_block_numbers(&mut *conn).await?, [1, 2, 3].iter().copied().collect());
+			assert_eq!(take_tip_blocks(&mut *conn, 2).await?, vec![(vec![1; 2], 1), (vec![2; 2], 2)]);
+			assert_eq!(take_tip_blocks(&mut *conn, 2).await?, vec![(vec![3; 2], 3)]);
+			assert!(take_tip_blocks(&mut *conn, 2).await?.is_empty());
+
+			// taken blocks stay queued until they are finished, and are released if their worker stopped
+			assert_eq!(tip_block_numbers(&mut *conn, 2, 3).await?, [2, 3].iter().copied().collect());
+			finish_tip_block(&mut *conn, &[1; 2]).await?;
+			assert_eq!(release_tip_blocks(&mut *conn).await?, 2);
+			assert_eq!(take_tip_blocks(&mut *conn, 2).await?, vec![(vec![2; 2], 2), (vec![3; 2], 3)]);
+			release_tip_block(&mut *conn, &[3; 2]).await?;
+			assert_eq!(take_tip_blocks(&mut *conn, 2).await?, vec![(vec![3; 2], 3)]);
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_retry_failed_blocks_with_backoff() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			insert_num(&mut *conn, 1).await?;
+			let hash = [1u8; 2];
+
+			// retries back off exponentially, until the block is marked dead
+			for (attempts, backoff) in &[(1, Some(60)), (2, Some(120)), (3, None)] {
+				record_failed_block(&mut *conn, &hash, 1, "failed", 2, 60).await?;
+				let failed = failed_block(&mut *conn, &hash).await?.expect("block has failed");
+				assert_eq!(failed.attempts, *attempts);
+				assert_eq!(failed.dead, backoff.is_none());
+				assert_eq!(failed.next_retry.map(|next| (next - failed.last_attempt).num_seconds()), *backoff);
+			}
+			assert!(take_due_failed_blocks(&mut *conn).await?.is_empty());
+
+			assert!(requeue_failed_block(&mut *conn, &hash).await?);
+			let due = take_due_failed_blocks(&mut *conn).await?;
+			assert_eq!(due, vec![(hash.to_vec(), 1)]);
+			// the block is being retried, and can't be re-queued until it fails again
+			assert!(!requeue_failed_block(&mut *conn, &hash).await?);
+			assert!(!requeue_failed_block(&mut *conn, &[9u8; 2]).await?);
+
+			// the retry is recovered once its storage is archived
+			assert_eq!(clear_recovered_blocks(&mut *conn).await?, 0);
+			sqlx::query("INSERT INTO storage (block_num, hash, is_full, key) VALUES (1, $1, false, $1)")
+				.bind(&hash[..])
+				.execute(&mut *conn)
+				.await?;
+			assert_eq!(clear_recovered_blocks(&mut *conn).await?, 1);
+			assert_eq!(failed_block(&mut *conn, &hash).await?, None);
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_page_through_blocks_in_range() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			for num in 1..=5 {
+				insert_num(&mut *conn, num).await?;
+			}
+			let mut pages = Vec::new();
+			let mut last = None;
+			loop {
+				let mut page = blocks_in_range(&mut *conn, 2, 5, last.as_ref(), 2).await?;
+				if page.is_empty() {
+					break;
+				}
+				pages.push(page.iter().map(|b| b.block_num).collect::<Vec<_>>());
+				last = page.pop();
+			}
+			assert_eq!(pages, vec![vec![2, 3], vec![4, 5]]);
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_mark_finalized_blocks() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			for num in 1..=4 {
+				insert_num(&mut *conn, num).await?;
+			}
+			assert_eq!(mark_finalized(&mut *conn, vec![vec![1u8; 2], vec![3u8; 2]]).await?, 2);
+			let unfinalized = unfinalized_blocks(&mut *conn, 1, 3, 10).await?;
+			assert_eq!(unfinalized, vec![(2, vec![2u8; 2])]);
+			let unfinalized = unfinalized_blocks(&mut *conn, 1, 4, 1).await?;
+			assert_eq!(unfinalized, vec![(2, vec![2u8; 2])]);
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_archive_precommits_of_grandpa_justifications() -> Result<()> {
+		use crate::{
+			database::{models::*, Insert},
+			types::{BatchBlock, Block},
+		};
+		use codec::Encode;
+		use sp_core::ed25519;
+		use sp_runtime::{generic::SignedBlock, traits::Header as _, Justifications};
+		use test_common::runtime::{Block as TestBlock, Header};
+
+		initialize();
+		let _guard = TestGuard::lock();
+		let header = Header::new(1, Default::default(), Default::default(), Default::default(), Default::default());
+		let hash = header.hash();
+		let precommit = |signer: u8| SignedPrecommit::<TestBlock> {
+			precommit: Precommit { target_hash: hash, target_number: 1 },
+			signature: ed25519::Signature([signer; 64]),
+			id: ed25519::Public([signer; 32]),
+		};
+		let justification = GrandpaJustification::<TestBlock> {
+			round: 7,
+			commit: GrandpaCommit { target_hash: hash, target_number: 1, precommits: vec![precommit(2), precommit(1)] },
+			votes_ancestries: Vec::new(),
+		};
+		let proofs = Justifications::from((GRANDPA_ENGINE_ID, justification.encode()));
+		let block = SignedBlock { block: TestBlock::new(header, Vec::new()), justifications: Some(proofs) };
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			BatchBlock::new(vec![Block::new(block, 0)]).insert(&mut conn).await?;
+
+			let stored = justifications(&mut *conn, hash.as_ref()).await?;
+			assert_eq!(stored.len(), 1);
+			assert_eq!(stored[0].engine_id, GRANDPA_ENGINE_ID.to_vec());
+			let precommits = grandpa_precommits(&mut *conn, hash.as_ref()).await?;
+			assert_eq!(precommits.iter().map(|p| p.signer[0]).collect::<Vec<_>>(), vec![1, 2]);
+			assert!(precommits.iter().all(|p| p.round == 7 && p.target_num == 1 && p.block_num == 1));
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_map_time_ranges_to_blocks() -> Result<()> {
+		use crate::types::millis_to_time;
+
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			let time = |secs: u64| millis_to_time(secs * 1000).unwrap();
+			for num in 1..=4 {
+				insert_num(&mut *conn, num).await?;
+			}
+			let timestamps = (1..=3).map(|num| (vec![num as u8; 2], time(num * 6))).collect();
+			set_missing_timestamps(&mut *conn, timestamps).await?;
+			// timestamps which are already set are kept
+			set_missing_timestamps(&mut *conn, vec![(vec![3u8; 2], time(100))]).await?;
+
+			assert_eq!(block_at_time(&mut *conn, time(5)).await?, None);
+			assert_eq!(block_at_time(&mut *conn, time(13)).await?, Some(2));
+			assert_eq!(block_at_time(&mut *conn, time(100)).await?, Some(3));
+			assert_eq!(blocks_between(&mut *conn, time(6), time(18)).await?, Some((1, 2)));
+			assert_eq!(blocks_between(&mut *conn, time(19), time(100)).await?, None);
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_seed_runtime_versions_from_blocks_with_metadata() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			insert_num(&mut *conn, 2).await?;
+			insert_num(&mut *conn, 1).await?;
+			// the dummy block of the test guard is the first block of spec 0
+			assert_eq!(unversioned_spec_blocks(&mut *conn).await?, vec![crate::test::DUMMY_HASH.to_vec()]);
+
+			insert_runtime_versions(&mut *conn, vec![(vec![7; 32], 0, vec![1, 2, 3])]).await?;
+			// inserting a version twice is a no-op
+			insert_runtime_versions(&mut *conn, vec![(vec![7; 32], 0, vec![1, 2, 3])]).await?;
+			assert_eq!(runtime_versions(&mut *conn).await?, vec![(vec![7; 32], vec![1, 2, 3])]);
+			assert!(unversioned_spec_blocks(&mut *conn).await?.is_empty());
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_keep_chains_apart_in_schemas() -> Result<()> {
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut config = crate::DatabaseConfig::new(crate::DATABASE_URL.as_str());
+			config.schema_per_chain = true;
+			config.resolve_schema(Some("test_parachain"))?;
+			crate::database::migrate(&config).await?;
+			let mut conn = crate::database::connect(&config).await?;
+			// the test guard inserted metadata of version 0 into the public schema
+			sqlx::query("INSERT INTO metadata (version, meta) VALUES (0, $1)")
+				.bind(&[1u8][..])
+				.execute(&mut conn)
+				.await?;
+			insert_num(&mut conn, 5).await?;
+			let status = archive_status(&mut conn).await?;
+
+			let mut public = PG_POOL.acquire().await?;
+			let public_status = archive_status(&mut *public).await?;
+			sqlx::query("DROP SCHEMA test_parachain CASCADE").execute(&mut *public).await?;
+
+			assert_eq!((status.blocks, status.max_block), (1, Some(5)));
+			assert_eq!((public_status.blocks, public_status.max_block), (1, Some(0)));
+			assert_eq!(config.channel("blocks_update"), "test_parachain.blocks_update");
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_refuse_databases_of_other_chains() -> Result<()> {
+		use crate::{
+			database::{check_chain, check_chain_id, verify_chain},
+			error::ArchiveError,
+			test::DUMMY_HASH,
+		};
+		initialize();
+		let _guard = TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PG_POOL.acquire().await?;
+			// before the chain is recorded, databases are checked against their archived genesis block
+			let other_genesis_block = check_chain(&mut *conn, &[1; 32], Some("kusama")).await;
+			assert!(matches!(other_genesis_block, Err(ArchiveError::WrongChain(_))));
+			check_chain(&mut *conn, &DUMMY_HASH, Some("kusama")).await?;
+			// without a recorded chain, commands without the genesis hash cannot tell chains apart
+			check_chain_id(&mut *conn, "polkadot").await?;
+
+			verify_chain(&mut *conn, &DUMMY_HASH, Some("kusama")).await?;
+			verify_chain(&mut *conn, &DUMMY_HASH, Some("kusama")).await?;
+			// archives started without a chain spec only check the genesis hash
+			verify_chain(&mut *conn, &DUMMY_HASH, None).await?;
+			let other_genesis = verify_chain(&mut *conn, &[2; 32], Some("kusama")).await;
+			assert!(matches!(other_genesis, Err(ArchiveError::WrongChain(_))));
+			let other_spec = verify_chain(&mut *conn, &DUMMY_HASH, Some("polkadot")).await;
+			assert!(matches!(other_spec, Err(ArchiveError::WrongChain(_))));
+			check_chain_id(&mut *conn, "kusama").await?;
+			assert!(matches!(check_chain_id(&mut *conn, "polkadot").await, Err(ArchiveError::WrongChain(_))));
+
+			let mut info = archive_info(&mut *conn).await?.expect("recorded on the first run");
+			assert_eq!(info.chain_id.as_deref(), Some("kusama"));
+			info.schema_version += 1;
+			set_archive_info(&mut *conn, &info).await?;
+			assert!(matches!(
+				verify_chain(&mut *conn, &DUMMY_HASH, Some("kusama")).await,
+				Err(ArchiveError::SchemaVersionTooNew(_, _))
+			));
+			// reported before the migrations run, which would fail on the migrations of the newer archive
+			assert!(matches!(
+				check_chain(&mut *conn, &DUMMY_HASH, Some("kusama")).await,
+				Err(ArchiveError::SchemaVersionTooNew(_, _))
+			));
+			Ok(())
+		})
 	}
-	Ok(blocks.into_iter().map(|r| {
-		let b: JobIn<B> = rmp_serde::from_read(r.data.as_slice())?;
-		Ok(b.block)
-	}))
 }
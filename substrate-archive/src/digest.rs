@@ -0,0 +1,354 @@
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the digest logs of block headers.
+//! Covers the pre-runtime digests and consensus logs of BABE, Aura and GRANDPA,
+//! without depending on the crates of the consensus engines.
+
+use std::convert::TryInto;
+
+use codec::{Compact, Decode, Encode};
+
+use sp_core::hashing::twox_128;
+use sp_runtime::{
+	generic::DigestItem,
+	traits::{Block as BlockT, Header as HeaderT},
+	ConsensusEngineId,
+};
+
+use substrate_archive_backend::{ReadOnlyBackend, ReadOnlyDb};
+
+use crate::database::models::GRANDPA_ENGINE_ID;
+
+pub const BABE_ENGINE_ID: ConsensusEngineId = *b"BABE";
+pub const AURA_ENGINE_ID: ConsensusEngineId = *b"aura";
+
+/// A digest log of a header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DigestLog {
+	/// Position of the log in the digest.
+	pub index: u32,
+	/// Name of the `DigestItem` variant.
+	pub kind: &'static str,
+	pub engine_id: Option<ConsensusEngineId>,
+	/// Payload of the log, without the variant and engine ID.
+	pub data: Vec<u8>,
+}
+
+/// Slot of a block, and the index of its author if the pre-runtime digest states it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlotClaim {
+	pub engine_id: ConsensusEngineId,
+	pub slot: u64,
+	/// Index of the author in the authority set. Only BABE includes it in the digest,
+	/// for Aura it follows from the slot and the number of authorities.
+	pub author_index: Option<u32>,
+}
+
+/// A change of the authorities, epoch or configuration of a consensus engine.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthorityChange {
+	/// Position of the consensus log in the digest.
+	pub index: u32,
+	pub engine_id: ConsensusEngineId,
+	/// Name of the consensus log variant, e.g. `NextEpochData` or `ScheduledChange`.
+	pub kind: &'static str,
+	/// Public keys of the new authorities.
+	pub authorities: Option<Vec<Vec<u8>>>,
+	/// Weights of the new authorities, if the engine weighs them.
+	pub weights: Option<Vec<u64>>,
+	/// Randomness of the next BABE epoch.
+	pub randomness: Option<Vec<u8>>,
+	/// Number of blocks until a GRANDPA change, pause or resume takes effect.
+	pub delay: Option<u32>,
+	/// Index of the disabled authority of an `OnDisabled` log.
+	pub disabled: Option<u64>,
+	/// Probability constant `c` of the next BABE configuration, as numerator and denominator.
+	pub c: Option<(u64, u64)>,
+	/// Allowed slots of the next BABE configuration.
+	pub allowed_slots: Option<&'static str>,
+}
+
+/// All the logs of a header, with the slot claim and authority changes decoded from them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedDigest {
+	pub logs: Vec<DigestLog>,
+	pub slot: Option<SlotClaim>,
+	pub changes: Vec<AuthorityChange>,
+}
+
+impl DecodedDigest {
+	/// Decode the digest of a header.
+	/// Logs of unknown engines, or which fail to decode, are only kept as raw `logs`.
+	pub fn new<H: HeaderT>(header: &H) -> Self {
+		let mut digest = Self::default();
+		for (index, item) in header.digest().logs().iter().enumerate() {
+			let log = digest_log(index as u32, item);
+			match (log.kind, log.engine_id) {
+				("PreRuntime", Some(engine_id)) if digest.slot.is_none() => {
+					digest.slot = slot_claim(engine_id, &log.data);
+				}
+				("Consensus", Some(engine_id)) => {
+					if let Some(change) = authority_change::<H::Number>(engine_id, &log.data) {
+						digest.changes.push(AuthorityChange { index: log.index, engine_id, ..change });
+					}
+				}
+				_ => {}
+			}
+			digest.logs.push(log);
+		}
+		digest
+	}
+}
+
+/// Split a digest item into its variant, engine and payload.
+///
+/// The variants without an accessor, like `ChangesTrieRoot`, are told apart by their encoding.
+/// Digest items of variants this version of `sp-runtime` does not know about fail to decode with the header,
+/// so they never get here.
+fn digest_log<H: Encode>(index: u32, item: &DigestItem<H>) -> DigestLog {
+	let (kind, engine_id, data) = if let Some((id, data)) = item.as_pre_runtime() {
+		("PreRuntime", Some(id), data.to_vec())
+	} else if let Some((id, data)) = item.as_consensus() {
+		("Consensus", Some(id), data.to_vec())
+	} else if let Some((id, data)) = item.as_seal() {
+		("Seal", Some(id), data.to_vec())
+	} else if let Some(data) = item.as_other() {
+		("Other", None, data.to_vec())
+	} else {
+		let encoded = item.encode();
+		let kind = match encoded.first() {
+			Some(2) => "ChangesTrieRoot",
+			Some(7) => "ChangesTrieSignal",
+			Some(8) => "RuntimeEnvironmentUpdated",
+			_ => "Unknown",
+		};
+		(kind, None, encoded.get(1..).unwrap_or_default().to_vec())
+	};
+	DigestLog { index, kind, engine_id, data }
+}
+
+/// Decode the slot claimed by a BABE or Aura pre-runtime digest.
+fn slot_claim(engine_id: ConsensusEngineId, mut data: &[u8]) -> Option<SlotClaim> {
+	match engine_id {
+		// every variant of the BABE `PreDigest` starts with the authority index and slot
+		BABE_ENGINE_ID => {
+			let _variant = u8::decode(&mut data).ok()?;
+			let author_index = u32::decode(&mut data).ok()?;
+			let slot = u64::decode(&mut data).ok()?;
+			Some(SlotClaim { engine_id, slot, author_index: Some(author_index) })
+		}
+		AURA_ENGINE_ID => Some(SlotClaim { engine_id, slot: u64::decode(&mut data).ok()?, author_index: None }),
+		_ => None,
+	}
+}
+
+/// Decode a consensus log of BABE, Aura or GRANDPA, of a chain with block numbers `N`.
+fn authority_change<N: Decode + TryInto<u32>>(
+	engine_id: ConsensusEngineId,
+	mut data: &[u8],
+) -> Option<AuthorityChange> {
+	let input = &mut data;
+	let variant = u8::decode(input).ok()?;
+	let change = match (engine_id, variant) {
+		(BABE_ENGINE_ID, 1) => {
+			let (authorities, weights) = weighted_authorities(input)?;
+			AuthorityChange {
+				kind: "NextEpochData",
+				authorities: Some(authorities),
+				weights: Some(weights),
+				randomness: Some(<[u8; 32]>::decode(input).ok()?.to_vec()),
+				..Default::default()
+			}
+		}
+		(BABE_ENGINE_ID, 2) => AuthorityChange {
+			kind: "OnDisabled",
+			disabled: Some(u32::decode(input).ok()?.into()),
+			..Default::default()
+		},
+		(BABE_ENGINE_ID, 3) => {
+			// `NextConfigDescriptor::V1`
+			let _version = u8::decode(input).ok()?;
+			let c = <(u64, u64)>::decode(input).ok()?;
+			let allowed_slots = match u8::decode(input).ok()? {
+				0 => "PrimarySlots",
+				1 => "PrimaryAndSecondaryPlainSlots",
+				2 => "PrimaryAndSecondaryVRFSlots",
+				_ => return None,
+			};
+			AuthorityChange {
+				kind: "NextConfigData",
+				c: Some(c),
+				allowed_slots: Some(allowed_slots),
+				..Default::default()
+			}
+		}
+		(AURA_ENGINE_ID, 1) => AuthorityChange {
+			kind: "AuthoritiesChange",
+			authorities: Some(<Vec<[u8; 32]>>::decode(input).ok()?.iter().map(|a| a.to_vec()).collect()),
+			..Default::default()
+		},
+		(AURA_ENGINE_ID, 2) => AuthorityChange {
+			kind: "OnDisabled",
+			disabled: Some(u32::decode(input).ok()?.into()),
+			..Default::default()
+		},
+		(GRANDPA_ENGINE_ID, 1) => AuthorityChange { kind: "ScheduledChange", ..scheduled_change::<N>(input)? },
+		(GRANDPA_ENGINE_ID, 2) => {
+			// the median last finalized block the forced change was signaled at
+			let _median = N::decode(input).ok()?;
+			AuthorityChange { kind: "ForcedChange", ..scheduled_change::<N>(input)? }
+		}
+		(GRANDPA_ENGINE_ID, 3) => {
+			AuthorityChange { kind: "OnDisabled", disabled: Some(u64::decode(input).ok()?), ..Default::default() }
+		}
+		(GRANDPA_ENGINE_ID, 4) => {
+			AuthorityChange { kind: "Pause", delay: Some(block_number::<N>(input)?), ..Default::default() }
+		}
+		(GRANDPA_ENGINE_ID, 5) => {
+			AuthorityChange { kind: "Resume", delay: Some(block_number::<N>(input)?), ..Default::default() }
+		}
+		_ => return None,
+	};
+	Some(change)
+}
+
+/// Decode a GRANDPA `ScheduledChange`.
+fn scheduled_change<N: Decode + TryInto<u32>>(input: &mut &[u8]) -> Option<AuthorityChange> {
+	let (authorities, weights) = weighted_authorities(input)?;
+	let delay = block_number::<N>(input)?;
+	Some(AuthorityChange {
+		authorities: Some(authorities),
+		weights: Some(weights),
+		delay: Some(delay),
+		..Default::default()
+	})
+}
+
+/// Decode a block number of type `N`, which the database stores as 32 bit integer.
+fn block_number<N: Decode + TryInto<u32>>(input: &mut &[u8]) -> Option<u32> {
+	N::decode(input).ok()?.try_into().ok()
+}
+
+/// Decode a list of 32 byte public keys with their weights.
+fn weighted_authorities(input: &mut &[u8]) -> Option<(Vec<Vec<u8>>, Vec<u64>)> {
+	let authorities = <Vec<([u8; 32], u64)>>::decode(input).ok()?;
+	Some(authorities.into_iter().map(|(key, weight)| (key.to_vec(), weight)).unzip())
+}
+
+/// Storage key of a storage value of a pallet.
+pub(crate) fn storage_value_key(pallet: &str, item: &str) -> Vec<u8> {
+	[twox_128(pallet.as_bytes()), twox_128(item.as_bytes())].concat()
+}
+
+/// Get the element at `index` of a SCALE encoded vector of fixed-size elements,
+/// without knowing the size of the elements.
+fn nth_fixed_size(encoded: &[u8], index: u32) -> Option<&[u8]> {
+	let mut input = encoded;
+	let len = Compact::<u32>::decode(&mut input).ok()?.0;
+	if len == 0 || input.len() % len as usize != 0 || index >= len {
+		return None;
+	}
+	let size = input.len() / len as usize;
+	input.get(index as usize * size..(index as usize + 1) * size)
+}
+
+/// Index of the author of a block, and its account if it can be resolved.
+///
+/// The index is resolved to an account through the `Session::Validators` at the parent of the block,
+/// falling back to the public key of the authority. Storage of other pallets, and accounts that are
+/// not of a fixed size, can not be resolved.
+pub fn resolve_author<B, D>(
+	backend: &ReadOnlyBackend<B, D>,
+	header: &B::Header,
+	claim: &SlotClaim,
+) -> (Option<u32>, Option<Vec<u8>>)
+where
+	B: BlockT,
+	D: ReadOnlyDb + 'static,
+{
+	let parent = *header.parent_hash();
+	let storage = |pallet: &str, item: &str| backend.storage(parent, &storage_value_key(pallet, item));
+	let authorities = match claim.engine_id {
+		BABE_ENGINE_ID => storage("Babe", "Authorities"),
+		AURA_ENGINE_ID => storage("Aura", "Authorities"),
+		_ => None,
+	};
+	let author_index = claim.author_index.or_else(|| {
+		let mut input = authorities.as_deref()?;
+		let len = Compact::<u32>::decode(&mut input).ok()?.0;
+		if len == 0 {
+			return None;
+		}
+		Some((claim.slot % u64::from(len)) as u32)
+	});
+	let author = author_index.and_then(|index| {
+		let validators = storage("Session", "Validators");
+		if let Some(validator) = validators.as_deref().and_then(|v| nth_fixed_size(v, index)) {
+			return Some(validator.to_vec());
+		}
+		let authority = nth_fixed_size(authorities.as_deref()?, index)?;
+		// BABE authorities are weighted by a trailing u64
+		let key_len = if claim.engine_id == BABE_ENGINE_ID { authority.len().checked_sub(8)? } else { authority.len() };
+		Some(authority[..key_len].to_vec())
+	});
+	(author_index, author)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_runtime::{generic::Digest, testing::Header};
+
+	#[test]
+	fn should_decode_babe_and_grandpa_logs() {
+		let authorities = vec![([1u8; 32], 1u64), ([2u8; 32], 1u64)];
+		// secondary plain pre-digest of authority 1 at slot 42
+		let pre_digest = (2u8, 1u32, 42u64).encode();
+		let next_epoch = (1u8, authorities.clone(), [7u8; 32]).encode();
+		// the delay is a block number, which is a u64 for the testing header
+		let scheduled_change = (1u8, authorities, 10u64).encode();
+		let digest = Digest {
+			logs: vec![
+				DigestItem::PreRuntime(BABE_ENGINE_ID, pre_digest),
+				DigestItem::Consensus(BABE_ENGINE_ID, next_epoch),
+				DigestItem::Consensus(GRANDPA_ENGINE_ID, scheduled_change),
+				DigestItem::Seal(BABE_ENGINE_ID, vec![0; 64]),
+			],
+		};
+		let header = Header::new(1, Default::default(), Default::default(), Default::default(), digest);
+		let decoded = DecodedDigest::new(&header);
+
+		let kinds = decoded.logs.iter().map(|l| l.kind).collect::<Vec<_>>();
+		assert_eq!(kinds, ["PreRuntime", "Consensus", "Consensus", "Seal"]);
+		assert_eq!(decoded.slot, Some(SlotClaim { engine_id: BABE_ENGINE_ID, slot: 42, author_index: Some(1) }));
+		assert_eq!(decoded.changes.len(), 2);
+		assert_eq!(decoded.changes[0].kind, "NextEpochData");
+		assert_eq!(decoded.changes[0].randomness, Some(vec![7; 32]));
+		assert_eq!(decoded.changes[1].kind, "ScheduledChange");
+		assert_eq!(decoded.changes[1].index, 2);
+		assert_eq!(decoded.changes[1].authorities, Some(vec![vec![1; 32], vec![2; 32]]));
+		assert_eq!(decoded.changes[1].delay, Some(10));
+	}
+
+	#[test]
+	fn should_index_vectors_of_fixed_size_elements() {
+		let validators = vec![[1u8; 32], [2u8; 32], [3u8; 32]].encode();
+		assert_eq!(nth_fixed_size(&validators, 1), Some(&[2u8; 32][..]));
+		assert_eq!(nth_fixed_size(&validators, 3), None);
+		assert_eq!(nth_fixed_size(&Vec::<[u8; 32]>::new().encode(), 0), None);
+	}
+}
@@ -34,6 +34,12 @@ pub enum ArchiveError {
 	Codec(#[from] codec::Error),
 	#[error(transparent)]
 	Serialization(#[from] serde_json::Error),
+	#[error("config error: {0}")]
+	ConfigDe(#[from] toml::de::Error),
+	#[error("config error: {0}")]
+	ConfigSer(#[from] toml::ser::Error),
+	#[error("invalid hex: {0}")]
+	Hex(#[from] hex::FromHexError),
 
 	// database error
 	#[error(transparent)]
@@ -52,6 +58,8 @@ pub enum ArchiveError {
 	BgJobGet(#[from] coil::FetchError),
 	#[error("Error while decoding job data {0}")]
 	De(#[from] rmp_serde::decode::Error),
+	#[error("Error while encoding job data {0}")]
+	En(#[from] rmp_serde::encode::Error),
 
 	// actor and channel error
 	#[error("Trying to send to disconnected actor")]
@@ -75,12 +83,21 @@ pub enum ArchiveError {
 
 	#[error("Rust Standard Library does not support negative durations")]
 	TimestampOutOfRange,
+	#[error("Block {0} has not failed, or is being retried already")]
+	NotFailed(String),
+
+	#[error("`database.schema_per_chain` is set, but no chain spec was given")]
+	NoChainSpecForSchema,
+	#[error("The database belongs to another chain: {0}")]
+	WrongChain(String),
+	#[error("The database was migrated to schema version {0} by a newer archive, this archive knows version {1}")]
+	SchemaVersionTooNew(i64, i64),
 }
 
 #[derive(Error, Debug)]
 pub enum TracingError {
 	#[error("Traces for block {0} not found")]
-	NoTraceForBlock(u32),
+	NoTraceForBlock(u64),
 	#[error("Traces could not be accessed from within Arc")]
 	NoTraceAccess,
 	#[error("Parent ID for span does not exist in the tree")]
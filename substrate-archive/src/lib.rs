@@ -21,19 +21,26 @@
 pub use sc_executor::native_executor_instance;
 pub use sp_blockchain::Error as BlockchainError;
 pub use sp_runtime::MultiSignature;
-pub use substrate_archive_backend::{ExecutionMethod, ReadOnlyDb, RuntimeConfig, SecondaryRocksDb};
+pub use substrate_archive_backend::{
+	wasm_runtime, ChainDb, DatabaseKind, ExecutionMethod, ExecutionStrategy, ParityDb, ReadOnlyDb, RuntimeConfig,
+	SecondaryRocksDb,
+};
 
 mod actors;
 pub mod archive;
+pub mod audit;
+pub mod cli;
 pub mod database;
+mod digest;
 mod error;
 mod logger;
 mod tasks;
 mod types;
 mod wasm_tracing;
 
-pub use self::actors::{ControlConfig, System};
+pub use self::actors::{ControlConfig, FinalityMode, System};
 pub use self::archive::{Archive, ArchiveBuilder, ArchiveConfig, ChainConfig, TracingConfig};
+pub use self::cli::CliOpts;
 pub use self::database::{queries, DatabaseConfig};
 pub use self::error::ArchiveError;
 
@@ -58,7 +65,7 @@ pub fn substrate_archive_default_dir() -> std::path::PathBuf {
 }
 
 #[cfg(test)]
-use test::{initialize, TestGuard, DATABASE_URL, PG_POOL};
+use test::{initialize, insert_block, TestGuard, DATABASE_URL, PG_POOL};
 
 #[cfg(test)]
 mod test {
@@ -88,13 +95,35 @@ mod test {
 	pub fn initialize() {
 		INIT.call_once(|| {
 			pretty_env_logger::init();
-			let url: &str = &DATABASE_URL;
+			let config = crate::DatabaseConfig::new(DATABASE_URL.as_str());
 			smol::block_on(async {
-				crate::database::migrate(url).await.unwrap();
+				crate::database::migrate(&config).await.unwrap();
 			});
 		});
 	}
 
+	/// Insert a block `num` with `hash` on top of `parent_hash`, running runtime `spec`.
+	/// Every other column is filled with the parent hash.
+	pub async fn insert_block(
+		conn: &mut sqlx::PgConnection,
+		num: i64,
+		hash: &[u8],
+		parent_hash: &[u8],
+		spec: i32,
+	) -> Result<(), sqlx::Error> {
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
+            VALUES($1, $2, $3, $1, $1, $1, $1, $4)",
+		)
+		.bind(parent_hash)
+		.bind(hash)
+		.bind(num)
+		.bind(spec)
+		.execute(conn)
+		.await?;
+		Ok(())
+	}
+
 	static TEST_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
 	pub struct TestGuard<'a>(MutexGuard<'a, ()>);
@@ -115,22 +144,8 @@ mod test {
 				.unwrap();
 
 				// insert a dummy block
-				sqlx::query(
-                    "
-                        INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec)
-                        VALUES($1, $2, $3, $4, $5, $6, $7, $8)
-                    ")
-                    .bind(&DUMMY_HASH[0..2])
-                    .bind(&DUMMY_HASH[0..2])
-                    .bind(0)
-                    .bind(&DUMMY_HASH[0..2])
-                    .bind(&DUMMY_HASH[0..2])
-                    .bind(&DUMMY_HASH[0..2])
-                    .bind(&DUMMY_HASH[0..2])
-                    .bind(0)
-                    .execute(&*PG_POOL)
-                    .await
-                    .expect("INSERT");
+				let mut conn = PG_POOL.acquire().await.unwrap();
+				insert_block(&mut conn, 0, &DUMMY_HASH, &DUMMY_HASH, 0).await.expect("INSERT");
 			});
 			guard
 		}
@@ -144,7 +159,17 @@ mod test {
 					"
                     TRUNCATE TABLE metadata CASCADE;
                     TRUNCATE TABLE storage CASCADE;
+                    TRUNCATE TABLE storage_values CASCADE;
                     TRUNCATE TABLE blocks CASCADE;
+                    TRUNCATE TABLE indexer_cursor;
+                    TRUNCATE TABLE block_gaps;
+                    TRUNCATE TABLE runtime_versions;
+                    TRUNCATE TABLE justifications;
+                    TRUNCATE TABLE grandpa_precommits;
+                    TRUNCATE TABLE digest_logs;
+                    TRUNCATE TABLE authority_changes;
+                    TRUNCATE TABLE archive_info;
+                    TRUNCATE TABLE tip_queue;
                     TRUNCATE TABLE _background_tasks
                     ",
 				)
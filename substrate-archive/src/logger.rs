@@ -19,11 +19,11 @@
 use std::{fs, io, path::PathBuf};
 
 use fern::colors::{Color, ColoredLevelConfig};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::substrate_archive_default_dir;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct LoggerConfig {
 	pub(crate) std: log::LevelFilter,
 	pub(crate) file: Option<FileLoggerConfig>,
@@ -35,7 +35,7 @@ impl Default for LoggerConfig {
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FileLoggerConfig {
 	#[serde(default = "default_file_log_level")]
 	pub(crate) level: log::LevelFilter,
@@ -0,0 +1,18 @@
+-- Content-addressed storage values.
+-- When deduplication is enabled, `storage.storage` is left NULL and the value is
+-- referenced through `storage.value_hash` instead.
+CREATE TABLE IF NOT EXISTS storage_values (
+  hash bytea PRIMARY KEY,
+  value bytea NOT NULL
+);
+
+ALTER TABLE storage ADD COLUMN value_hash bytea REFERENCES storage_values(hash);
+
+CREATE UNIQUE INDEX only_unique_hash_key_value_hash ON storage (hash, key, value_hash) WHERE value_hash IS NOT NULL;
+
+-- Resolves both inline and deduplicated values.
+CREATE VIEW storage_view AS
+  SELECT storage.id, storage.block_num, storage.hash, storage.is_full, storage.key,
+    COALESCE(storage.storage, storage_values.value) AS storage
+  FROM storage
+  LEFT JOIN storage_values ON storage_values.hash = storage.value_hash;
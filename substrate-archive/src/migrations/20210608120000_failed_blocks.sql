@@ -0,0 +1,16 @@
+-- Blocks whose execution failed.
+-- Rows with a `next_retry` are waiting to be re-queued,
+-- rows marked `dead` have exhausted their retries and are only re-queued manually.
+CREATE TABLE IF NOT EXISTS failed_blocks (
+  id SERIAL PRIMARY KEY,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL UNIQUE REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  spec int,
+  error text NOT NULL,
+  attempts int NOT NULL DEFAULT 1,
+  dead boolean NOT NULL DEFAULT false,
+  next_retry timestamp,
+  last_attempt timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX IF NOT EXISTS failed_blocks_next_retry ON failed_blocks (next_retry) WHERE next_retry IS NOT NULL;
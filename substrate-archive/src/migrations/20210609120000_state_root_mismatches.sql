@@ -0,0 +1,13 @@
+-- Blocks whose execution did not result in the state root stated in their header.
+-- Storage of these blocks is not archived.
+CREATE TABLE IF NOT EXISTS state_root_mismatches (
+  id SERIAL PRIMARY KEY,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL UNIQUE REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  spec int,
+  -- state root from the block header
+  expected bytea NOT NULL,
+  -- storage root computed by executing the block
+  computed bytea NOT NULL,
+  detected_at timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
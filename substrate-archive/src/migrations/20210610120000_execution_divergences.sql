@@ -0,0 +1,13 @@
+-- Blocks whose storage changes differ between native and Wasm execution.
+-- The storage changes of the Wasm execution are archived.
+CREATE TABLE IF NOT EXISTS execution_divergences (
+  id SERIAL PRIMARY KEY,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL UNIQUE REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  spec int,
+  -- error of the native execution, if it failed
+  native_error text,
+  -- storage keys whose value differs between both executions
+  keys bytea[] NOT NULL,
+  detected_at timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
@@ -0,0 +1,16 @@
+-- Progress of each stage of indexing.
+-- Every block up to `block_num` has passed the stage, except the blocks in `block_gaps`.
+CREATE TABLE IF NOT EXISTS indexer_cursor (
+  stage text PRIMARY KEY,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  updated_at timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+-- Inclusive ranges of block numbers below the cursor of a stage which have not passed that stage.
+CREATE TABLE IF NOT EXISTS block_gaps (
+  stage text NOT NULL,
+  start_num int NOT NULL,
+  end_num int NOT NULL,
+  PRIMARY KEY (stage, start_num),
+  CHECK (start_num <= end_num)
+);
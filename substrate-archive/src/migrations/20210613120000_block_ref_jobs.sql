@@ -0,0 +1,4 @@
+-- `execute_block` jobs now carry the hash and number of the block instead of the whole block.
+-- Jobs in the old format can't be decoded anymore; the blocks they referenced have no storage,
+-- so they are queued again on startup.
+DELETE FROM _background_tasks WHERE job_type = 'execute_block';
@@ -0,0 +1,9 @@
+-- Runtime versions read from the on-chain Wasm, keyed by the blake2-256 hash of the code,
+-- so that restarts don't need to execute the Wasm again to know its version.
+CREATE TABLE IF NOT EXISTS runtime_versions (
+  code_hash bytea PRIMARY KEY,
+  spec_version int NOT NULL,
+  -- SCALE encoded `RuntimeVersion`
+  version bytea NOT NULL,
+  created_at timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
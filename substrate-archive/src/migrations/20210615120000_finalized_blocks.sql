@@ -0,0 +1,4 @@
+-- Whether the block is part of the finalized chain of the node.
+-- Blocks that were archived but never finalized, because they were reorganized out, stay false.
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS finalized boolean NOT NULL DEFAULT false;
+CREATE INDEX IF NOT EXISTS blocks_unfinalized_idx ON blocks (block_num) WHERE NOT finalized;
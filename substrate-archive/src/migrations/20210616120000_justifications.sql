@@ -0,0 +1,30 @@
+-- Justifications the node stored for a block, one row per consensus engine.
+CREATE TABLE IF NOT EXISTS justifications (
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL,
+  -- four byte `ConsensusEngineId`, e.g. 'FRNK' for GRANDPA
+  engine_id bytea NOT NULL,
+  -- justification as encoded by the consensus engine
+  justification bytea NOT NULL,
+  PRIMARY KEY (hash, engine_id)
+);
+
+CREATE INDEX IF NOT EXISTS justifications_block_num ON justifications (block_num);
+
+-- Signed precommits of the commits in GRANDPA justifications.
+-- A commit may contain several precommits of one authority if it equivocated.
+CREATE TABLE IF NOT EXISTS grandpa_precommits (
+  -- the justified block
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL,
+  round bigint NOT NULL,
+  -- the block the precommit voted for, which is the justified block or one of its descendants
+  target_hash bytea NOT NULL,
+  target_num int NOT NULL,
+  -- ed25519 public key and signature of the authority
+  signer bytea NOT NULL,
+  signature bytea NOT NULL,
+  PRIMARY KEY (hash, signer, target_hash)
+);
+
+CREATE INDEX IF NOT EXISTS grandpa_precommits_signer ON grandpa_precommits (signer);
@@ -0,0 +1,49 @@
+-- Slot and author of blocks, decoded from their pre-runtime digest.
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS slot bigint;
+-- index of the author in the authority set of the consensus engine
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS author_index int;
+-- account of the author from `Session::Validators`, or the public key of the authority
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS author bytea;
+
+CREATE INDEX IF NOT EXISTS blocks_author ON blocks (author) WHERE author IS NOT NULL;
+
+-- Digest logs of block headers.
+CREATE TABLE IF NOT EXISTS digest_logs (
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL,
+  -- position of the log in the digest
+  log_index int NOT NULL,
+  -- `DigestItem` variant, e.g. 'PreRuntime', 'Consensus', 'Seal' or 'RuntimeEnvironmentUpdated'
+  kind text NOT NULL,
+  engine_id bytea,
+  data bytea NOT NULL,
+  PRIMARY KEY (hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS digest_logs_kind ON digest_logs (kind, block_num);
+
+-- Changes of the authorities, epochs and configuration of consensus engines, decoded from consensus logs.
+CREATE TABLE IF NOT EXISTS authority_changes (
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL,
+  log_index int NOT NULL,
+  engine_id bytea NOT NULL,
+  -- consensus log variant, e.g. 'NextEpochData' of BABE or 'ScheduledChange' of GRANDPA
+  kind text NOT NULL,
+  -- public keys and weights of the new authorities
+  authorities bytea[],
+  weights bigint[],
+  -- randomness of the next BABE epoch
+  randomness bytea,
+  -- blocks until a GRANDPA change, pause or resume takes effect
+  delay int,
+  -- index of a disabled authority
+  disabled bigint,
+  -- next BABE configuration
+  c_numerator bigint,
+  c_denominator bigint,
+  allowed_slots text,
+  PRIMARY KEY (hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS authority_changes_block_num ON authority_changes (engine_id, block_num);
@@ -0,0 +1,4 @@
+-- Time a block was authored at, from `Timestamp::Now` in UTC.
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS timestamp timestamp;
+
+CREATE INDEX IF NOT EXISTS blocks_timestamp ON blocks (timestamp) WHERE timestamp IS NOT NULL;
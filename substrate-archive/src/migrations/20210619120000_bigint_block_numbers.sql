@@ -0,0 +1,56 @@
+-- Block numbers are stored as `bigint`, so chains with 64-bit block numbers can be archived.
+-- The view depends on `storage.block_num`, so it is recreated after the column changes type.
+DROP VIEW IF EXISTS storage_view;
+
+ALTER TABLE blocks DROP CONSTRAINT IF EXISTS blocks_block_num_check;
+ALTER TABLE blocks ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE blocks ADD CONSTRAINT blocks_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE storage DROP CONSTRAINT IF EXISTS storage_block_num_check;
+ALTER TABLE storage ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE storage ADD CONSTRAINT storage_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE state_traces DROP CONSTRAINT IF EXISTS state_traces_block_num_check;
+ALTER TABLE state_traces ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE state_traces ADD CONSTRAINT state_traces_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE failed_blocks DROP CONSTRAINT IF EXISTS failed_blocks_block_num_check;
+ALTER TABLE failed_blocks ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE failed_blocks ADD CONSTRAINT failed_blocks_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE state_root_mismatches DROP CONSTRAINT IF EXISTS state_root_mismatches_block_num_check;
+ALTER TABLE state_root_mismatches ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE state_root_mismatches ADD CONSTRAINT state_root_mismatches_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE execution_divergences DROP CONSTRAINT IF EXISTS execution_divergences_block_num_check;
+ALTER TABLE execution_divergences ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE execution_divergences ADD CONSTRAINT execution_divergences_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE indexer_cursor DROP CONSTRAINT IF EXISTS indexer_cursor_block_num_check;
+ALTER TABLE indexer_cursor ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE indexer_cursor ADD CONSTRAINT indexer_cursor_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE block_gaps ALTER COLUMN start_num TYPE bigint, ALTER COLUMN end_num TYPE bigint;
+
+ALTER TABLE justifications DROP CONSTRAINT IF EXISTS justifications_block_num_check;
+ALTER TABLE justifications ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE justifications ADD CONSTRAINT justifications_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE grandpa_precommits DROP CONSTRAINT IF EXISTS grandpa_precommits_block_num_check;
+ALTER TABLE grandpa_precommits ALTER COLUMN block_num TYPE bigint, ALTER COLUMN target_num TYPE bigint;
+ALTER TABLE grandpa_precommits ADD CONSTRAINT grandpa_precommits_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE digest_logs DROP CONSTRAINT IF EXISTS digest_logs_block_num_check;
+ALTER TABLE digest_logs ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE digest_logs ADD CONSTRAINT digest_logs_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE authority_changes DROP CONSTRAINT IF EXISTS authority_changes_block_num_check;
+ALTER TABLE authority_changes ALTER COLUMN block_num TYPE bigint;
+ALTER TABLE authority_changes ADD CONSTRAINT authority_changes_block_num_check CHECK (block_num >= 0);
+
+-- Resolves both inline and deduplicated values.
+CREATE VIEW storage_view AS
+  SELECT storage.id, storage.block_num, storage.hash, storage.is_full, storage.key,
+    COALESCE(storage.storage, storage_values.value) AS storage
+  FROM storage
+  LEFT JOIN storage_values ON storage_values.hash = storage.value_hash;
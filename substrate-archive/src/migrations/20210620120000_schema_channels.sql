@@ -0,0 +1,34 @@
+-- Notify on channels prefixed with the schema of the table, so that the archives of
+-- several chains kept in schemas of one database do not receive each other's notifications.
+-- Tables in the `public` schema keep notifying on the bare channel.
+CREATE OR REPLACE FUNCTION table_update_trigger_fn()
+   RETURNS TRIGGER
+   LANGUAGE PLPGSQL
+AS $BODY$
+DECLARE
+  channel TEXT := TG_ARGV[0];
+  id JSON;
+  notification JSON;
+BEGIN
+
+    IF (TG_TABLE_SCHEMA <> 'public') THEN
+      channel = TG_TABLE_SCHEMA || '.' || channel;
+    END IF;
+
+    IF (TG_OP = 'DELETE') THEN
+      id = OLD.id;
+    ELSE
+      id = NEW.id;
+    END IF;
+
+    -- create json payload
+     notification := json_build_object(
+        'table',TG_TABLE_NAME,
+        'action', TG_OP,
+        'id', id
+    );
+
+    PERFORM pg_notify(channel, notification::TEXT);
+    RETURN NULL;
+END;
+$BODY$
@@ -0,0 +1,11 @@
+-- Identity of the chain an archive belongs to.
+-- Recorded on the first run, and verified on every start.
+CREATE TABLE IF NOT EXISTS archive_info (
+  -- the table holds a single row
+  id boolean PRIMARY KEY DEFAULT true CHECK (id),
+  genesis_hash bytea NOT NULL,
+  chain_id text,
+  -- version of the last migration of the archive that wrote to the database
+  schema_version bigint NOT NULL,
+  created_at timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
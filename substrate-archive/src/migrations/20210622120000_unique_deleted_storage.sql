@@ -0,0 +1,8 @@
+-- Deleted keys have neither an inline value nor a value hash, so no other unique index
+-- on `storage` covers them and archiving a block twice duplicated its deletions.
+DELETE FROM storage a USING storage b
+  WHERE a.storage IS NULL AND a.value_hash IS NULL
+  AND b.storage IS NULL AND b.value_hash IS NULL
+  AND a.hash = b.hash AND a.key = b.key AND a.id > b.id;
+
+CREATE UNIQUE INDEX only_unique_hash_key_deleted ON storage (hash, key) WHERE storage IS NULL AND value_hash IS NULL;
@@ -0,0 +1,7 @@
+-- Marks the blocks whose digest is decoded into `digest_logs` and `authority_changes`.
+-- Blocks archived before digests were decoded are marked `false` and decoded in the background.
+-- New blocks are decoded when they are inserted, so the column defaults to `true` from here on.
+ALTER TABLE blocks ADD COLUMN decoded boolean NOT NULL DEFAULT false;
+ALTER TABLE blocks ALTER COLUMN decoded SET DEFAULT true;
+
+CREATE INDEX blocks_undecoded ON blocks (block_num) WHERE NOT decoded;
@@ -0,0 +1,7 @@
+-- Blocks near the tip of the chain are queued in `tip_queue` and executed by workers of the archive,
+-- ahead of and independently of the tasks of coil.
+CREATE TABLE IF NOT EXISTS tip_queue (
+  hash bytea PRIMARY KEY NOT NULL,
+  block_num bigint NOT NULL CHECK (block_num >= 0),
+  queued_at timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
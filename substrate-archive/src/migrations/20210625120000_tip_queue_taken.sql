@@ -0,0 +1,3 @@
+-- Blocks stay in the tip queue while they are executed, so that the sweep does not queue them again.
+-- Blocks taken by a worker that stopped before executing them are released on startup.
+ALTER TABLE tip_queue ADD COLUMN taken boolean NOT NULL DEFAULT false;
@@ -19,22 +19,26 @@
 
 use std::{marker::PhantomData, panic::AssertUnwindSafe, sync::Arc};
 
+use codec::Encode;
+use hashbrown::HashMap;
 use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use xtra::prelude::*;
 
 use sc_client_api::backend;
-use sp_api::{ApiExt, ApiRef, ConstructRuntimeApi};
+use sp_api::{ApiExt, ApiRef, ConstructRuntimeApi, RuntimeVersion};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_core::ExecutionContext;
 use sp_runtime::{
 	generic::BlockId,
 	traits::{Block as BlockT, Header, NumberFor},
 };
 
-use substrate_archive_backend::{ApiAccess, ReadOnlyBackend as Backend, ReadOnlyDb};
+use substrate_archive_backend::{ApiAccess, BackendError, ReadOnlyBackend as Backend, ReadOnlyDb};
 
 use crate::{
 	actors::StorageAggregator,
+	database::queries,
 	error::ArchiveError,
 	types::Storage,
 	wasm_tracing::{SpansAndEvents, TraceHandler, Traces},
@@ -54,10 +58,19 @@ where
 	backend: Arc<Backend<B, D>>,
 	client: Arc<C>,
 	storage: Address<StorageAggregator<B>>,
+	pool: sqlx::PgPool,
+	/// Times a failed block is retried before it is marked dead.
+	max_retries: u32,
+	/// Seconds to wait before the first retry of a failed block.
+	retry_backoff: u64,
+	/// Check the storage root of executed blocks against their header.
+	verify_state_root: bool,
+	/// Version of the native runtime, if blocks are executed both natively and with Wasm to compare the results.
+	native_version: Option<RuntimeVersion>,
 	_marker: PhantomData<R>,
 }
 
-type Env<B, R, C, D> = AssertUnwindSafe<Environment<B, R, C, D>>;
+pub(crate) type Env<B, R, C, D> = AssertUnwindSafe<Environment<B, R, C, D>>;
 impl<B, R, C, D> Environment<B, R, C, D>
 where
 	D: ReadOnlyDb,
@@ -68,9 +81,47 @@ where
 		backend: Arc<Backend<B, D>>,
 		client: Arc<C>,
 		storage: Address<StorageAggregator<B>>,
+		pool: sqlx::PgPool,
 		tracing_targets: Option<String>,
+		max_retries: u32,
+		retry_backoff: u64,
+		verify_state_root: bool,
+		native_version: Option<RuntimeVersion>,
 	) -> Self {
-		Self { backend, client, storage, tracing_targets, _marker: PhantomData }
+		Self {
+			backend,
+			client,
+			storage,
+			pool,
+			tracing_targets,
+			max_retries,
+			retry_backoff,
+			verify_state_root,
+			native_version,
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<B, R, C, D> Clone for Environment<B, R, C, D>
+where
+	D: ReadOnlyDb,
+	B: BlockT + Unpin,
+	B::Hash: Unpin,
+{
+	fn clone(&self) -> Self {
+		Self {
+			tracing_targets: self.tracing_targets.clone(),
+			backend: self.backend.clone(),
+			client: self.client.clone(),
+			storage: self.storage.clone(),
+			pool: self.pool.clone(),
+			max_retries: self.max_retries,
+			retry_backoff: self.retry_backoff,
+			verify_state_root: self.verify_state_root,
+			native_version: self.native_version.clone(),
+			_marker: PhantomData,
+		}
 	}
 }
 
@@ -90,18 +141,29 @@ pub struct BlockChanges<Block: BlockT> {
 	pub hash: Block::Hash,
 	/// Number of the block these changes come from
 	pub number: NumberFor<Block>,
+	/// State root stated in the header of the block.
+	pub state_root: Block::Hash,
+	/// Storage root computed from executing the block.
+	pub storage_root: Block::Hash,
+}
+
+impl<Block: BlockT> BlockChanges<Block> {
+	/// Whether executing the block resulted in the state root stated in its header.
+	pub fn state_root_matches(&self) -> bool {
+		self.state_root == self.storage_root
+	}
 }
 
 impl<Block> From<BlockChanges<Block>> for Storage<Block>
 where
 	Block: BlockT,
-	NumberFor<Block>: Into<u32>,
+	NumberFor<Block>: Into<u64>,
 {
 	fn from(changes: BlockChanges<Block>) -> Storage<Block> {
 		use sp_storage::{StorageData, StorageKey};
 
 		let hash = changes.hash;
-		let num: u32 = changes.number.into();
+		let num: u64 = changes.number.into();
 
 		Storage::new(
 			hash,
@@ -134,16 +196,20 @@ struct BlockPrep<Block, S, H, N> {
 	state: S,
 	hash: H,
 	parent_hash: H,
+	state_root: H,
 	number: N,
 }
 
 type BlockParams<Block, Backend> =
 	BlockPrep<Block, <Backend as backend::Backend<Block>>::State, <Block as BlockT>::Hash, NumberFor<Block>>;
 
+/// Trie nodes written by executing a block.
+type PostState<Block, Backend> = backend::TransactionFor<Backend, Block>;
+
 impl<'a, Block, Api, B> BlockExecutor<'a, Block, Api, B>
 where
 	Block: BlockT,
-	NumberFor<Block>: Into<u32>,
+	NumberFor<Block>: Into<u64>,
 	Api: BlockBuilderApi<Block> + ApiExt<Block, StateBackend = backend::StateBackendFor<B, Block>>,
 	B: backend::Backend<Block>,
 {
@@ -159,6 +225,7 @@ where
 		let header = block.header();
 		let parent_hash = *header.parent_hash();
 		let hash = header.hash();
+		let state_root = *header.state_root();
 		let number = *header.number();
 
 		let state = backend.state_at(*id)?;
@@ -170,28 +237,44 @@ where
 		// popping a digest item has no effect on storage changes afaik
 		let (mut header, ext) = block.deconstruct();
 		header.digest_mut().pop();
-		Ok(BlockPrep { block: Block::new(header, ext), state, hash, parent_hash, number })
+		Ok(BlockPrep { block: Block::new(header, ext), state, hash, parent_hash, state_root, number })
+	}
+
+	/// Execute the block in the context `execute_block` of the runtime api uses.
+	fn execute(self) -> Result<(BlockChanges<Block>, PostState<Block, B>), ArchiveError> {
+		self.execute_with_context(ExecutionContext::OffchainCall(None))
 	}
 
-	fn execute(self) -> Result<BlockChanges<Block>, ArchiveError> {
-		let BlockPrep { block, state, hash, parent_hash, number } =
+	/// Execute the block in `context`, which selects the execution strategy of the client.
+	fn execute_with_context(
+		self,
+		context: ExecutionContext,
+	) -> Result<(BlockChanges<Block>, PostState<Block, B>), ArchiveError> {
+		let BlockPrep { block, state, hash, parent_hash, state_root, number } =
 			Self::prepare_block(self.block, &self.backend, &self.id)?;
 
-		self.api.execute_block(&self.id, block)?;
+		self.api.execute_block_with_context(&self.id, context, block)?;
 		let storage_changes =
 			self.api.into_storage_changes(&state, None, parent_hash).map_err(ArchiveError::ConvertStorageChanges)?;
 
-		Ok(BlockChanges {
+		let changes = BlockChanges {
 			storage_changes: storage_changes.main_storage_changes,
 			child_storage: storage_changes.child_storage_changes,
 			hash,
 			number,
-		})
+			state_root,
+			storage_root: storage_changes.transaction_storage_root,
+		};
+		Ok((changes, storage_changes.transaction))
 	}
 
-	fn execute_with_tracing(self, targets: &str) -> Result<(BlockChanges<Block>, Traces), ArchiveError> {
+	fn execute_with_tracing(
+		self,
+		targets: &str,
+	) -> Result<(BlockChanges<Block>, Traces, PostState<Block, B>), ArchiveError> {
 		let BlockExecutor { block, backend, id, api } = self;
-		let BlockPrep { block, state, hash, parent_hash, number } = Self::prepare_block(block, &backend, &id)?;
+		let BlockPrep { block, state, hash, parent_hash, state_root, number } =
+			Self::prepare_block(block, &backend, &id)?;
 
 		let span_events = Arc::new(Mutex::new(SpansAndEvents { spans: Vec::new(), events: Vec::new() }));
 		let handler = TraceHandler::new(&targets, span_events);
@@ -205,18 +288,20 @@ where
 			api.execute_block(&id, block).map_err(ArchiveError::from)
 		})?;
 
-		let changes =
+		let storage_changes =
 			api.into_storage_changes(&state, None, parent_hash).map_err(ArchiveError::ConvertStorageChanges)?;
 
 		let changes = BlockChanges {
-			storage_changes: changes.main_storage_changes,
-			child_storage: changes.child_storage_changes,
+			storage_changes: storage_changes.main_storage_changes,
+			child_storage: storage_changes.child_storage_changes,
 			hash,
 			number,
+			state_root,
+			storage_root: storage_changes.transaction_storage_root,
 		};
 
 		let traces = Traces::new(number.into(), hash.as_ref().to_vec(), events, spans);
-		Ok((changes, traces))
+		Ok((changes, traces, storage_changes.transaction))
 	}
 }
 
@@ -245,28 +330,171 @@ impl sp_core::traits::SpawnNamed for TaskExecutor {
 // + DeserializeOwned so that the types work.
 // This is a little bit wonky (and entirely confusing), could be fixed with a better proc-macro in `coil`
 // TODO: We should detect when the chain is behind our node, and not execute blocks in this case.
-/// Execute a block, and send it to the database actor
+/// Execute a block, and send it to the database actor.
+/// The job only carries the hash and number of the block, which is loaded at execution time.
 #[coil::background_job]
 pub fn execute_block<B, RA, Api, D>(
 	env: &Env<B, RA, Api, D>,
-	block: B,
+	hash: B::Hash,
+	number: u64,
 	_m: PhantomData<(RA, Api, D)>,
 ) -> Result<(), coil::PerformError>
 where
 	D: ReadOnlyDb + 'static,
 	B: BlockT + DeserializeOwned + Unpin,
-	NumberFor<B>: Into<u32>,
+	NumberFor<B>: Into<u64>,
 	B::Hash: Unpin,
 	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
 	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
 	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
 {
-	let api = env.client.runtime_api();
+	execute_ref(env, hash, number)
+}
 
-	if *block.header().parent_hash() == Default::default() {
-		return Ok(());
+/// Execute a contiguous range of blocks in order on one worker, starting with the block numbered `start`.
+/// Every block leaves its post-state in the trie cache of the backend,
+/// so the next block in the range mostly reads its parent state from memory instead of the chain data.
+#[coil::background_job]
+pub fn execute_block_range<B, RA, Api, D>(
+	env: &Env<B, RA, Api, D>,
+	hashes: Vec<B::Hash>,
+	start: u64,
+	_m: PhantomData<(RA, Api, D)>,
+) -> Result<(), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + DeserializeOwned + Unpin,
+	NumberFor<B>: Into<u64>,
+	B::Hash: Unpin,
+	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
+	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
+	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
+{
+	execute_range(env, &hashes, start)
+}
+
+/// Execute `hashes` in order, numbering them from `start`.
+/// A block that fails is recorded as failed, and the range goes on with the next block.
+fn execute_range<B, RA, Api, D>(
+	env: &Env<B, RA, Api, D>,
+	hashes: &[B::Hash],
+	start: u64,
+) -> Result<(), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + Unpin,
+	NumberFor<B>: Into<u64>,
+	B::Hash: Unpin,
+	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
+	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
+	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
+{
+	let now = std::time::Instant::now();
+	for (number, hash) in (start..).zip(hashes.iter()) {
+		execute_ref(env, *hash, number)?;
+	}
+	log::debug!("Took {:?} to execute {} blocks starting at {}", now.elapsed(), hashes.len(), start);
+	Ok(())
+}
+
+/// Execute the block referenced by `hash` and `number`, recording it as failed if it cannot be executed.
+/// Blocks queued at the tip of the chain are executed with this directly, outside of coil.
+pub(crate) fn execute_ref<B, RA, Api, D>(
+	env: &Env<B, RA, Api, D>,
+	hash: B::Hash,
+	number: u64,
+) -> Result<(), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + Unpin,
+	NumberFor<B>: Into<u64>,
+	B::Hash: Unpin,
+	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
+	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
+	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
+{
+	let res = if number == 0 {
+		// the genesis block is not executed, its whole state is archived instead
+		archive_genesis(env, hash)
+	} else {
+		load_block(env, hash).and_then(|block| execute_and_send(env, block))
+	};
+	if let Err(e) = res {
+		// record the failure instead of handing it to coil, which would retry the job indefinitely.
+		log::error!("Failed to execute block {}:{}: {}", number, hash, e);
+		smol::block_on(async {
+			let mut conn = env.pool.acquire().await?;
+			queries::record_failed_block(
+				&mut conn,
+				hash.as_ref(),
+				number,
+				&e.to_string(),
+				env.max_retries,
+				env.retry_backoff,
+			)
+			.await
+		})?;
+	}
+	Ok(())
+}
+
+/// Send the whole state of the genesis block to the [`StorageAggregator`].
+fn archive_genesis<B, RA, Api, D>(env: &Env<B, RA, Api, D>, hash: B::Hash) -> Result<(), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + Unpin,
+	B::Hash: Unpin,
+{
+	let storage = genesis_storage(&env.backend, hash)?;
+	log::info!("Archiving {} storage entries of the genesis block {}", storage.changes.len(), hash);
+	smol::block_on(env.storage.send(storage))?;
+	Ok(())
+}
+
+/// The whole state of the genesis block, including its child tries, as a full storage set.
+fn genesis_storage<B, D>(backend: &Backend<B, D>, hash: B::Hash) -> Result<Storage<B>, ArchiveError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT,
+{
+	use sp_storage::{StorageData, StorageKey};
+
+	let pairs = backend.state_pairs(hash)?.ok_or(BackendError::StorageNotExist)?;
+	let changes = pairs.into_iter().map(|(key, value)| (StorageKey(key), Some(StorageData(value)))).collect();
+	Ok(Storage::new(hash, 0, true, changes))
+}
+
+/// Load a block from the chain data, or from the `blocks` table if the node no longer has it.
+fn load_block<B, RA, Api, D>(env: &Env<B, RA, Api, D>, hash: B::Hash) -> Result<B, coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + Unpin,
+	B::Hash: Unpin,
+{
+	if let Some(block) = env.backend.block(&BlockId::Hash(hash)) {
+		return Ok(block.block);
 	}
+	let model = smol::block_on(async {
+		let mut conn = env.pool.acquire().await?;
+		queries::get_full_block_by_hash(&mut conn, hash.as_ref()).await
+	})?;
+	match model {
+		Some(model) => Ok(model.into_block_and_spec::<B>()?.0),
+		None => Err(format!("Block {} not found", hash).into()),
+	}
+}
 
+/// Execute a block and send its storage changes and traces to the [`StorageAggregator`].
+fn execute_and_send<B, RA, Api, D>(env: &Env<B, RA, Api, D>, block: B) -> Result<(), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + Unpin,
+	NumberFor<B>: Into<u64>,
+	B::Hash: Unpin,
+	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
+	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
+	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
+{
 	log::debug!(
 		"Executing Block: {}:{}, version {}",
 		block.header().hash(),
@@ -274,15 +502,24 @@ where
 		env.client.runtime_version_at(&BlockId::Hash(block.hash())).map_err(|e| format!("{:?}", e))?.spec_version,
 	);
 
-	let block = BlockExecutor::new(api, &env.backend, block);
-
 	let now = std::time::Instant::now();
-	let (storage, traces) = if let Some(targets) = env.tracing_targets.as_ref() {
-		block.execute_with_tracing(targets)?
+	let (storage, traces, post_state) = if let Some(targets) = env.tracing_targets.as_ref() {
+		BlockExecutor::new(env.client.runtime_api(), &env.backend, block).execute_with_tracing(targets)?
+	} else if let Some(native_version) = env.native_version.as_ref() {
+		let (storage, post_state) = execute_both(env, native_version, block)?;
+		(storage, Default::default(), post_state)
 	} else {
-		(block.execute()?, Default::default())
+		let (storage, post_state) = BlockExecutor::new(env.client.runtime_api(), &env.backend, block).execute()?;
+		(storage, Default::default(), post_state)
 	};
 	log::debug!("Took {:?} to execute block", now.elapsed());
+	// trie nodes are addressed by their hash, so caching them is sound even if the state root does not match.
+	env.backend.cache_post_state(post_state);
+
+	if env.verify_state_root && !verify_state_root(&env.pool, &storage)? {
+		// don't archive storage we know to be wrong
+		return Ok(());
+	}
 
 	let now = std::time::Instant::now();
 	smol::block_on(env.storage.send(Storage::from(storage)))?;
@@ -293,3 +530,360 @@ where
 	log::trace!("Took {:?} to insert & send finished task", now.elapsed());
 	Ok(())
 }
+
+/// Check that executing a block resulted in the state root stated in its header.
+/// Blocks that don't are recorded in `state_root_mismatches`.
+fn verify_state_root<B>(pool: &sqlx::PgPool, changes: &BlockChanges<B>) -> Result<bool, ArchiveError>
+where
+	B: BlockT,
+	NumberFor<B>: Into<u64>,
+{
+	if changes.state_root_matches() {
+		return Ok(true);
+	}
+	log::warn!(
+		"State root mismatch for block {}:{}: expected {}, got {}",
+		changes.number,
+		changes.hash,
+		changes.state_root,
+		changes.storage_root
+	);
+	smol::block_on(async {
+		let mut conn = pool.acquire().await?;
+		queries::record_state_root_mismatch(
+			&mut conn,
+			changes.hash.as_ref(),
+			changes.number.into(),
+			changes.state_root.as_ref(),
+			changes.storage_root.as_ref(),
+		)
+		.await
+	})?;
+	Ok(false)
+}
+
+/// Execute a block both natively and with Wasm, and record a divergence if their storage changes differ.
+/// Returns the storage changes and post-state of the Wasm execution.
+///
+/// Blocks the native runtime can't execute are only executed with Wasm,
+/// because the client would fall back to Wasm and compare Wasm with itself.
+fn execute_both<B, RA, Api, D>(
+	env: &Env<B, RA, Api, D>,
+	native_version: &RuntimeVersion,
+	block: B,
+) -> Result<(BlockChanges<B>, PostState<B, Backend<B, D>>), coil::PerformError>
+where
+	D: ReadOnlyDb + 'static,
+	B: BlockT + Unpin,
+	NumberFor<B>: Into<u64>,
+	B::Hash: Unpin,
+	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
+	RA::RuntimeApi: BlockBuilderApi<B> + ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
+	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
+{
+	let onchain_version =
+		env.client.runtime_version_at(&BlockId::Hash(*block.header().parent_hash())).map_err(|e| format!("{:?}", e))?;
+	if !native_version.can_call_with(&onchain_version) {
+		log::debug!(
+			"Native runtime can't execute block {}:{} of {} version {}, not comparing it with Wasm",
+			block.header().hash(),
+			block.header().number(),
+			onchain_version.spec_name,
+			onchain_version.spec_version,
+		);
+		return Ok(BlockExecutor::new(env.client.runtime_api(), &env.backend, block)
+			.execute_with_context(ExecutionContext::Importing)?);
+	}
+
+	// the client executes natively when syncing, and with wasm when importing.
+	let native = BlockExecutor::new(env.client.runtime_api(), &env.backend, block.clone())
+		.execute_with_context(ExecutionContext::Syncing);
+	let (wasm, post_state) = BlockExecutor::new(env.client.runtime_api(), &env.backend, block)
+		.execute_with_context(ExecutionContext::Importing)?;
+
+	let (native_error, keys) = match native {
+		Ok((native, _)) => (None, diverging_keys(&native, &wasm)),
+		Err(e) => (Some(e.to_string()), Vec::new()),
+	};
+	if native_error.is_some() || !keys.is_empty() {
+		log::warn!(
+			"Native and Wasm execution of block {}:{} diverged in {} keys. Archiving the Wasm result.",
+			wasm.number,
+			wasm.hash,
+			keys.len()
+		);
+		smol::block_on(async {
+			let mut conn = env.pool.acquire().await?;
+			queries::record_execution_divergence(
+				&mut conn,
+				wasm.hash.as_ref(),
+				wasm.number.into(),
+				native_error.as_deref(),
+				keys,
+			)
+			.await
+		})?;
+	}
+	Ok((wasm, post_state))
+}
+
+/// Storage keys whose values differ between two sets of changes.
+/// Keys of child tries are the SCALE encoding of the storage key of their child trie and the key,
+/// which length-prefixes both, so that different pairs never result in the same key.
+fn diverging_keys<B: BlockT>(a: &BlockChanges<B>, b: &BlockChanges<B>) -> Vec<StorageKey> {
+	type Key<'a> = (Option<&'a StorageKey>, &'a StorageKey);
+	fn flatten<B: BlockT>(changes: &BlockChanges<B>) -> HashMap<Key<'_>, Option<&StorageValue>> {
+		let top = changes.storage_changes.iter().map(|(k, v)| ((None, k), v.as_ref()));
+		let children = changes
+			.child_storage
+			.iter()
+			.flat_map(|(child, c)| c.iter().map(move |(k, v)| ((Some(child), k), v.as_ref())));
+		top.chain(children).collect()
+	}
+	let (a, b) = (flatten(a), flatten(b));
+	let mut keys = a
+		.iter()
+		.filter(|(k, v)| b.get(*k) != Some(*v))
+		.map(|(k, _)| k)
+		.chain(b.keys().filter(|k| !a.contains_key(*k)))
+		.map(|(child, key)| match child {
+			Some(child) => (child, key).encode(),
+			None => key.to_vec(),
+		})
+		.collect::<Vec<_>>();
+	keys.sort();
+	keys
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_api::ProvideRuntimeApi;
+	use sp_core::H256;
+	use substrate_archive_backend::{runtime_api, ExecutionStrategy, MemoryDb, RuntimeConfig};
+	use test_common::{
+		runtime::{Block, Executor, RuntimeApi},
+		runtime_code, ChainBuilder, CHILD_PAIR, CHILD_STORAGE_KEY, NUMBER_KEY,
+	};
+
+	#[test]
+	fn should_execute_blocks_of_fixture_chain() -> Result<(), ArchiveError> {
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(2);
+		let upgrade = chain.upgrade(2);
+		let fork = chain.fork(blocks[0], vec![(b"fork".to_vec(), b"value".to_vec())]);
+
+		let mut config = RuntimeConfig::default();
+		config.exec_strategy = ExecutionStrategy::AlwaysWasm;
+		let client = runtime_api::<Block, RuntimeApi, Executor, MemoryDb>(chain.db(), config)?;
+		let backend = client.backend();
+		let execute = |hash| {
+			let block = backend.block(&BlockId::Hash(hash)).expect("block is part of the fixture").block;
+			BlockExecutor::new(client.runtime_api(), &backend, block).execute().map(|(changes, _)| changes)
+		};
+
+		let changes = execute(blocks[1])?;
+		assert!(changes.state_root_matches());
+		assert_eq!(changes.storage_changes, vec![(NUMBER_KEY.to_vec(), Some(2u32.encode()))]);
+
+		let changes = execute(upgrade)?;
+		assert!(changes.state_root_matches());
+		assert_eq!(changes.storage_changes[0].0, sp_storage::well_known_keys::CODE.to_vec());
+		assert_eq!(client.runtime_version_at(&BlockId::Hash(upgrade))?.spec_version, 2);
+
+		let changes = execute(fork)?;
+		assert!(changes.state_root_matches());
+		assert_eq!(changes.storage_changes, vec![(b"fork".to_vec(), Some(b"value".to_vec()))]);
+		Ok(())
+	}
+
+	#[test]
+	fn should_execute_blocks_on_top_of_cached_post_state() -> Result<(), ArchiveError> {
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(2);
+
+		let mut config = RuntimeConfig::default();
+		config.exec_strategy = ExecutionStrategy::AlwaysWasm;
+		let client = runtime_api::<Block, RuntimeApi, Executor, MemoryDb>(chain.db(), config)?;
+		let backend = client.backend();
+		let block = |hash| backend.block(&BlockId::Hash(hash)).expect("block is part of the fixture").block;
+
+		let (_, post_state) = BlockExecutor::new(client.runtime_api(), &backend, block(blocks[0])).execute()?;
+		let written = post_state
+			.clone()
+			.drain()
+			.into_iter()
+			.filter(|(_, (_, rc))| *rc > 0)
+			.map(|(key, _)| key)
+			.collect::<Vec<_>>();
+		assert!(!written.is_empty());
+		backend.cache_post_state(post_state);
+		// the nodes written by the first block can only be read from the trie cache
+		chain.db().remove_state(written.iter().map(|key| &key[..]));
+
+		let (changes, _) = BlockExecutor::new(client.runtime_api(), &backend, block(blocks[1])).execute()?;
+		assert!(changes.state_root_matches());
+		assert_eq!(changes.storage_changes, vec![(NUMBER_KEY.to_vec(), Some(2u32.encode()))]);
+		Ok(())
+	}
+
+	#[test]
+	fn should_find_diverging_top_and_child_keys() {
+		let changes = |top: &[u8], child: &[u8]| BlockChanges::<Block> {
+			storage_changes: vec![(b"same".to_vec(), Some(b"value".to_vec())), (b"top".to_vec(), Some(top.to_vec()))],
+			child_storage: vec![(b"child".to_vec(), vec![(b"key".to_vec(), Some(child.to_vec()))])],
+			hash: Default::default(),
+			number: 1,
+			state_root: Default::default(),
+			storage_root: Default::default(),
+		};
+		let child_key = (b"child".to_vec(), b"key".to_vec()).encode();
+		assert!(diverging_keys(&changes(b"a", b"a"), &changes(b"a", b"a")).is_empty());
+		assert_eq!(diverging_keys(&changes(b"a", b"a"), &changes(b"a", b"b")), vec![child_key.clone()]);
+		assert_eq!(diverging_keys(&changes(b"a", b"a"), &changes(b"b", b"b")), vec![child_key, b"top".to_vec()]);
+
+		// pairs of child storage keys and keys that concatenate to the same bytes are different keys
+		let child = |child: &[u8], key: &[u8]| BlockChanges::<Block> {
+			storage_changes: Vec::new(),
+			child_storage: vec![(child.to_vec(), vec![(key.to_vec(), Some(b"value".to_vec()))])],
+			hash: Default::default(),
+			number: 1,
+			state_root: Default::default(),
+			storage_root: Default::default(),
+		};
+		assert_eq!(
+			diverging_keys(&child(b"ab", b"c"), &child(b"a", b"bc")),
+			vec![(b"a".to_vec(), b"bc".to_vec()).encode(), (b"ab".to_vec(), b"c".to_vec()).encode()]
+		);
+	}
+
+	#[test]
+	fn should_record_state_root_mismatches_instead_of_archiving() -> Result<(), ArchiveError> {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		let valid = chain.push(vec![(b"key".to_vec(), b"value".to_vec())]);
+		let tampered = chain.push_tampered(vec![(b"key".to_vec(), b"tampered".to_vec())]);
+
+		let mut config = RuntimeConfig::default();
+		config.exec_strategy = ExecutionStrategy::AlwaysWasm;
+		let client = runtime_api::<Block, RuntimeApi, Executor, MemoryDb>(chain.db(), config)?;
+		let backend = client.backend();
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await?;
+			for (hash, num) in &[(valid, 1i64), (tampered, 2)] {
+				crate::insert_block(&mut *conn, *num, hash.as_bytes(), hash.as_bytes(), 1).await?;
+			}
+			Ok::<_, ArchiveError>(())
+		})?;
+
+		for hash in &[valid, tampered] {
+			let block = backend.block(&BlockId::Hash(*hash)).expect("block is part of the fixture").block;
+			let (changes, _) = BlockExecutor::new(client.runtime_api(), &backend, block).execute()?;
+			assert_eq!(verify_state_root(&crate::PG_POOL, &changes)?, *hash == valid);
+		}
+
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await?;
+			let mismatches = queries::state_root_mismatches(&mut conn).await?;
+			assert_eq!(mismatches.len(), 1);
+			assert_eq!(mismatches[0].hash, tampered.as_bytes().to_vec());
+			assert_eq!(mismatches[0].expected, H256::repeat_byte(0xde).as_bytes().to_vec());
+			// blocks with a mismatching state root are not queued again
+			let skipped = queries::skipped_block_hashes(&mut conn).await?;
+			assert!(skipped.contains(tampered.as_bytes()) && !skipped.contains(valid.as_bytes()));
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_execute_ranges_in_order_and_record_failed_blocks() -> Result<(), ArchiveError> {
+		use crate::actors::{ActorPool, DatabaseActor};
+		use xtra::spawn::Smol;
+
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let mut chain = ChainBuilder::new();
+		let blocks = chain.extend(3);
+		// stands in for block 2, and can't be loaded from the chain data nor decoded from its row
+		let broken = H256::repeat_byte(0xba);
+
+		let mut config = RuntimeConfig::default();
+		config.exec_strategy = ExecutionStrategy::AlwaysWasm;
+		let client = Arc::new(runtime_api::<Block, RuntimeApi, Executor, MemoryDb>(chain.db(), config)?);
+		let storage = smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await?;
+			for (hash, num) in &[(blocks[0], 1i64), (broken, 2), (blocks[2], 3)] {
+				crate::insert_block(&mut *conn, *num, hash.as_bytes(), hash.as_bytes(), 1).await?;
+			}
+			let db =
+				DatabaseActor::<Block>::new(&crate::DatabaseConfig::new(crate::DATABASE_URL.as_str()), false).await?;
+			let db = ActorPool::new(db, 1).create(None).spawn(&mut Smol::Global);
+			Ok::<_, ArchiveError>(StorageAggregator::new(db).create(None).spawn(&mut Smol::Global))
+		})?;
+		let env = AssertUnwindSafe(Environment::<Block, RuntimeApi, _, MemoryDb>::new(
+			client.backend(),
+			client.clone(),
+			storage,
+			crate::PG_POOL.clone(),
+			None,
+			3,
+			30,
+			true,
+			None,
+		));
+
+		execute_range(&env, &[blocks[0], broken, blocks[2]], 1)?;
+
+		smol::block_on(async {
+			let mut conn = crate::PG_POOL.acquire().await?;
+			// the failure of block 2 does not stop the range, and is recorded under its position in the range
+			let failed = queries::failed_blocks(&mut conn).await?;
+			assert_eq!(
+				failed.iter().map(|f| (f.hash.clone(), f.block_num)).collect::<Vec<_>>(),
+				vec![(broken.as_bytes().to_vec(), 2)]
+			);
+			// the aggregator inserts storage once a second
+			for _ in 0..20 {
+				if !queries::storage_changes(&mut conn, blocks[2].as_bytes()).await?.is_empty() {
+					break;
+				}
+				smol::Timer::after(std::time::Duration::from_millis(250)).await;
+			}
+			for (hash, num) in &[(blocks[0], 1u32), (blocks[2], 3)] {
+				let changes = queries::storage_changes(&mut conn, hash.as_bytes()).await?;
+				let changes = changes.into_iter().map(|c| (c.key, c.storage)).collect::<Vec<_>>();
+				assert_eq!(changes, vec![(NUMBER_KEY.to_vec(), Some(num.encode()))]);
+			}
+			// every executed block resulted in the state root of its header
+			assert!(queries::state_root_mismatches(&mut conn).await?.is_empty());
+			Ok(())
+		})
+	}
+
+	#[test]
+	fn should_snapshot_genesis_state() -> Result<(), ArchiveError> {
+		let mut chain = ChainBuilder::new();
+		chain.extend(1);
+		let backend = Backend::<Block, MemoryDb>::new(chain.db(), true);
+		let genesis = backend.genesis_hash()?.expect("the fixture has a genesis block");
+
+		let storage = genesis_storage(&backend, genesis)?;
+		assert!(storage.is_full());
+		assert_eq!(storage.block_num(), 0);
+		let changes = storage.changes().iter().map(|(k, v)| (k.0.clone(), v.clone().map(|v| v.0))).collect::<Vec<_>>();
+		// the pairs of the child trie follow the top trie, their keys prefixed with the key of the child trie
+		let child_key = sp_storage::ChildInfo::new_default(CHILD_STORAGE_KEY).prefixed_storage_key().into_inner();
+		let child_root = chain.storage(genesis, &child_key);
+		assert!(child_root.is_some());
+		assert_eq!(
+			changes,
+			vec![
+				(child_key.clone(), child_root),
+				(sp_storage::well_known_keys::CODE.to_vec(), Some(runtime_code(1))),
+				([child_key.as_slice(), CHILD_PAIR.0].concat(), Some(CHILD_PAIR.1.to_vec())),
+			]
+		);
+		Ok(())
+	}
+}
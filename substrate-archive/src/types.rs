@@ -14,6 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::convert::TryFrom;
+
+use chrono::NaiveDateTime;
 use codec::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 use xtra::Message;
@@ -49,14 +52,31 @@ impl Message for Metadata {
 pub struct Block<B: BlockT> {
 	pub inner: SignedBlock<B>,
 	pub spec: u32,
+	/// Index of the author in the authority set, resolved from the slot for Aura.
+	pub author_index: Option<u32>,
+	/// Account or public key of the author.
+	pub author: Option<Vec<u8>>,
+	/// `Timestamp::Now` of the block, in milliseconds since the Unix epoch.
+	pub timestamp: Option<u64>,
 }
 
 impl<B: BlockT> Block<B> {
 	pub fn new(block: SignedBlock<B>, spec: u32) -> Self {
-		Self { inner: block, spec }
+		Self { inner: block, spec, author_index: None, author: None, timestamp: None }
 	}
 }
 
+/// Storage key of `Timestamp::Now`, the time a block was authored at.
+pub fn timestamp_key() -> Vec<u8> {
+	crate::digest::storage_value_key("Timestamp", "Now")
+}
+
+/// Convert milliseconds since the Unix epoch into a UTC time.
+pub fn millis_to_time(millis: u64) -> Option<NaiveDateTime> {
+	let secs = i64::try_from(millis / 1000).ok()?;
+	NaiveDateTime::from_timestamp_opt(secs, (millis % 1000) as u32 * 1_000_000)
+}
+
 impl<B: BlockT> Message for Block<B> {
 	type Result = ();
 }
@@ -85,7 +105,7 @@ impl<B: BlockT> Message for BatchBlock<B> {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Storage<Block: BlockT> {
 	hash: Block::Hash,
-	block_num: u32,
+	block_num: u64,
 	full_storage: bool,
 	pub changes: Vec<(StorageKey, Option<StorageData>)>,
 }
@@ -93,7 +113,7 @@ pub struct Storage<Block: BlockT> {
 impl<Block: BlockT> Storage<Block> {
 	pub fn new(
 		hash: Block::Hash,
-		block_num: u32,
+		block_num: u64,
 		full_storage: bool,
 		changes: Vec<(StorageKey, Option<StorageData>)>,
 	) -> Self {
@@ -104,7 +124,7 @@ impl<Block: BlockT> Storage<Block> {
 		self.full_storage
 	}
 
-	pub fn block_num(&self) -> u32 {
+	pub fn block_num(&self) -> u64 {
 		self.block_num
 	}
 
@@ -71,14 +71,14 @@ pub struct SpanMessage {
 /// Finished Trace Data Format. Ready for insertion into a relational database.
 #[derive(Debug, Default)]
 pub struct Traces {
-	block_num: u32,
+	block_num: u64,
 	hash: Vec<u8>,
 	pub spans: Vec<SpanMessage>,
 	pub events: Vec<EventMessage>,
 }
 
 impl Traces {
-	pub fn new(block_num: u32, hash: Vec<u8>, events: Vec<EventMessage>, spans: Vec<SpanMessage>) -> Self {
+	pub fn new(block_num: u64, hash: Vec<u8>, events: Vec<EventMessage>, spans: Vec<SpanMessage>) -> Self {
 		Traces { block_num, hash, spans, events }
 	}
 
@@ -88,7 +88,7 @@ impl Traces {
 	}
 
 	/// Get the block number of the block these spans come from.
-	pub fn block_num(&self) -> u32 {
+	pub fn block_num(&self) -> u64 {
 		self.block_num
 	}
 }
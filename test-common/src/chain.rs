@@ -0,0 +1,265 @@
+// Copyright 2018-2021 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Chains authored with the `test-wasm` runtime, written into a [`MemoryDb`]
+//! in the column layout of a Substrate node.
+
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	sync::Arc,
+};
+
+use codec::{Decode, Encode};
+use sp_core::H256;
+use sp_runtime::{
+	generic::{Digest, DigestItem},
+	traits::{BlakeTwo256, Block as BlockT, Hash as HashT, Header as HeaderT},
+	ConsensusEngineId, Justifications, OpaqueExtrinsic,
+};
+use sp_storage::{well_known_keys, ChildInfo};
+use sp_trie::{trie_types::TrieDBMut, KeySpacedDBMut, PrefixedMemoryDB, TrieMut};
+
+use substrate_archive_backend::MemoryDb;
+
+use crate::runtime::{self, Block, Header};
+
+/// Key every block authored by [`ChainBuilder::extend`] writes its number to.
+pub const NUMBER_KEY: &[u8] = b"test:number";
+
+/// Storage key of the default child trie in the genesis state.
+pub const CHILD_STORAGE_KEY: &[u8] = b"test:child";
+
+/// The only `(key, value)` pair of the child trie [`CHILD_STORAGE_KEY`].
+pub const CHILD_PAIR: (&[u8], &[u8]) = (b"test:child:key", b"test:child:value");
+
+type Storage = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Authors a chain and writes every block, with its state, into a [`MemoryDb`].
+///
+/// Blocks on top of the best block are canonical. Forks are never canonical.
+pub struct ChainBuilder {
+	db: Arc<MemoryDb>,
+	/// Header and storage of every authored block.
+	blocks: HashMap<H256, (Header, Storage)>,
+	best: H256,
+	/// Number of forked blocks, which makes the hashes of forks unique.
+	forks: u32,
+}
+
+impl Default for ChainBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl ChainBuilder {
+	/// Start a chain whose genesis block runs the `test-wasm` runtime with spec version 1.
+	/// The genesis state also has the default child trie [`CHILD_STORAGE_KEY`], holding [`CHILD_PAIR`].
+	pub fn new() -> Self {
+		let db = Arc::new(MemoryDb::new());
+		let child_info = ChildInfo::new_default(CHILD_STORAGE_KEY);
+		let child_storage: Storage = std::iter::once((CHILD_PAIR.0.to_vec(), CHILD_PAIR.1.to_vec())).collect();
+		let child_root = insert_state(&db, child_info.keyspace(), &child_storage);
+
+		let mut storage = Storage::new();
+		storage.insert(well_known_keys::CODE.to_vec(), runtime_code(1));
+		storage.insert(child_info.prefixed_storage_key().into_inner(), child_root.encode());
+		let state_root = insert_state(&db, &[], &storage);
+		let genesis = Header::new(0, Default::default(), state_root, Default::default(), Default::default());
+		db.insert_block(&Block::new(genesis.clone(), Vec::new()), true);
+		db.set_genesis::<Block>(&genesis);
+		db.set_best::<Block>(&genesis);
+		db.set_finalized::<Block>(&genesis);
+
+		let best = genesis.hash();
+		let mut blocks = HashMap::new();
+		blocks.insert(best, (genesis, storage));
+		Self { db, blocks, best, forks: 0 }
+	}
+
+	/// Author a block on top of the best block, with one extrinsic writing each `(key, value)` pair.
+	pub fn push(&mut self, storage: Vec<(Vec<u8>, Vec<u8>)>) -> H256 {
+		self.best = self.author(self.best, storage, Digest::default(), true, None);
+		self.best
+	}
+
+	/// Author a block on top of the best block like [`push`](Self::push),
+	/// but with a header stating a state root that executing the block does not result in.
+	pub fn push_tampered(&mut self, storage: Vec<(Vec<u8>, Vec<u8>)>) -> H256 {
+		self.best = self.author(self.best, storage, Digest::default(), true, Some(H256::repeat_byte(0xde)));
+		self.best
+	}
+
+	/// Author `n` blocks on top of the best block, each writing its number to [`NUMBER_KEY`].
+	pub fn extend(&mut self, n: u32) -> Vec<H256> {
+		(0..n)
+			.map(|_| {
+				let number = self.header(self.best).number + 1;
+				self.push(vec![(NUMBER_KEY.to_vec(), number.encode())])
+			})
+			.collect()
+	}
+
+	/// Author a block on top of the best block upgrading the runtime to `spec_version`.
+	pub fn upgrade(&mut self, spec_version: u32) -> H256 {
+		self.push(vec![(well_known_keys::CODE.to_vec(), runtime_code(spec_version))])
+	}
+
+	/// Author a block on top of `parent` which is not part of the canonical chain.
+	pub fn fork(&mut self, parent: H256, storage: Vec<(Vec<u8>, Vec<u8>)>) -> H256 {
+		self.forks += 1;
+		let mut digest = Digest::default();
+		digest.push(DigestItem::Other(self.forks.encode()));
+		self.author(parent, storage, digest, false, None)
+	}
+
+	/// Reorganize the chain onto `hash`, making it and its ancestors canonical, and `hash` the best block.
+	/// The blocks of the previous chain above the best block stay in the number index.
+	pub fn reorg(&mut self, hash: H256) {
+		let mut canonical = HashSet::new();
+		let mut header = self.header(self.best);
+		while header.number > 0 {
+			canonical.insert(header.hash());
+			header = self.header(header.parent_hash);
+		}
+
+		let mut header = self.header(hash);
+		while header.number > 0 && !canonical.contains(&header.hash()) {
+			self.db.set_canonical::<Block>(&header);
+			header = self.header(header.parent_hash);
+		}
+		self.db.set_best::<Block>(&self.header(hash));
+		self.best = hash;
+	}
+
+	/// Mark a block as the last finalized block.
+	pub fn finalize(&self, hash: H256) {
+		self.db.set_finalized::<Block>(&self.header(hash));
+	}
+
+	/// Store a justification of `engine_id` for a block, as the node does once it is finalized.
+	pub fn justify(&self, hash: H256, engine_id: ConsensusEngineId, justification: Vec<u8>) {
+		self.db.set_justifications::<Block>(&self.header(hash), &Justifications::from((engine_id, justification)));
+	}
+
+	/// Header of an authored block.
+	///
+	/// # Panics
+	/// Panics if the block was not authored by this builder.
+	pub fn header(&self, hash: H256) -> Header {
+		self.blocks.get(&hash).expect("block was not authored by this builder").0.clone()
+	}
+
+	/// Value of `key` in the state after `hash`.
+	pub fn storage(&self, hash: H256, key: &[u8]) -> Option<Vec<u8>> {
+		self.blocks.get(&hash).and_then(|(_, storage)| storage.get(key).cloned())
+	}
+
+	/// Hash of the best block.
+	pub fn best(&self) -> H256 {
+		self.best
+	}
+
+	/// The database the chain is written to.
+	pub fn db(&self) -> Arc<MemoryDb> {
+		self.db.clone()
+	}
+
+	fn author(
+		&mut self,
+		parent: H256,
+		storage: Vec<(Vec<u8>, Vec<u8>)>,
+		digest: Digest<H256>,
+		canonical: bool,
+		tampered_state_root: Option<H256>,
+	) -> H256 {
+		let (parent_header, parent_storage) = self.blocks.get(&parent).expect("unknown parent block");
+		let number = parent_header.number + 1;
+		let mut post_storage = parent_storage.clone();
+		post_storage.extend(storage.iter().cloned());
+		let state_root = insert_state(&self.db, &[], &post_storage);
+		let state_root = tampered_state_root.unwrap_or(state_root);
+
+		let extrinsics = storage.iter().map(extrinsic).collect::<Vec<_>>();
+		let extrinsics_root = BlakeTwo256::ordered_trie_root(extrinsics.iter().map(Encode::encode).collect());
+		let header = Header::new(number, extrinsics_root, state_root, parent, digest);
+		self.db.insert_block(&Block::new(header.clone(), extrinsics), canonical);
+		if canonical {
+			self.db.set_best::<Block>(&header);
+		}
+
+		let hash = header.hash();
+		self.blocks.insert(hash, (header, post_storage));
+		hash
+	}
+}
+
+/// Extrinsic which `test-wasm` executes by writing `(key, value)` into storage.
+fn extrinsic(pair: &(Vec<u8>, Vec<u8>)) -> OpaqueExtrinsic {
+	OpaqueExtrinsic::decode(&mut &pair.encode().encode()[..]).expect("opaque extrinsics are encoded as bytes")
+}
+
+/// Write the trie of `storage` into the database and return its root.
+/// The nodes of child tries are prefixed with the `keyspace` of the child trie, the nodes of the top trie are not.
+fn insert_state(db: &MemoryDb, keyspace: &[u8], storage: &Storage) -> H256 {
+	let mut nodes = PrefixedMemoryDB::<BlakeTwo256>::default();
+	let mut root = H256::default();
+	{
+		let mut keyspaced = KeySpacedDBMut::new(&mut nodes, keyspace);
+		let mut trie = TrieDBMut::<BlakeTwo256>::new(&mut keyspaced, &mut root);
+		for (key, value) in storage {
+			trie.insert(key, value).expect("in-memory trie");
+		}
+	}
+	db.insert_state(nodes.drain().into_iter().filter(|(_, (_, rc))| *rc > 0).map(|(key, (node, _))| (key, node)));
+	root
+}
+
+/// Code of the `test-wasm` runtime, with the version for `spec_version` embedded
+/// in a `runtime_version` custom section, where the executor reads it from.
+pub fn runtime_code(spec_version: u32) -> Vec<u8> {
+	let wasm = sp_maybe_compressed_blob::decompress(
+		test_wasm::wasm_binary_unwrap(),
+		sp_maybe_compressed_blob::CODE_BLOB_BOMB_LIMIT,
+	)
+	.expect("test-wasm binary is valid");
+	let mut code = wasm.into_owned();
+	append_custom_section(&mut code, "runtime_version", &runtime::version(spec_version).encode());
+	code
+}
+
+fn append_custom_section(wasm: &mut Vec<u8>, name: &str, payload: &[u8]) {
+	let mut contents = leb128(name.len());
+	contents.extend_from_slice(name.as_bytes());
+	contents.extend_from_slice(payload);
+	// id of custom sections
+	wasm.push(0);
+	wasm.extend(leb128(contents.len()));
+	wasm.extend(contents);
+}
+
+fn leb128(mut n: usize) -> Vec<u8> {
+	let mut bytes = Vec::new();
+	loop {
+		let byte = (n & 0x7f) as u8;
+		n >>= 7;
+		if n == 0 {
+			bytes.push(byte);
+			return bytes;
+		}
+		bytes.push(byte | 0x80);
+	}
+}
@@ -14,4 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+//! Fixtures shared by the tests of substrate-archive.
+
+mod chain;
+pub mod runtime;
+
+pub use self::chain::{runtime_code, ChainBuilder, CHILD_PAIR, CHILD_STORAGE_KEY, NUMBER_KEY};
 pub use test_wasm::wasm_binary_unwrap;
@@ -1,4 +1,4 @@
-// Copyright 2018-2021 Parity Technologies (UK) Ltd.
+// Copyright 2017-2021 Parity Technologies (UK) Ltd.
 // This file is part of substrate-archive.
 
 // substrate-archive is free software: you can redistribute it and/or modify
@@ -13,3 +13,28 @@
 
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime of the chains authored by [`ChainBuilder`](crate::ChainBuilder).
+//!
+//! The chains are archived with the Wasm-only runtime of the backend, like `generic-archive` archives any chain.
+//! Blocks are always executed with the `test-wasm` runtime, which writes the `(key, value)` pair
+//! of every extrinsic into storage.
+
+use sp_runtime::create_runtime_str;
+use sp_version::RuntimeVersion;
+
+use substrate_archive_backend::wasm_runtime::RUNTIME_API_VERSIONS;
+pub use substrate_archive_backend::wasm_runtime::{Block, BlockNumber, Executor, Header, RuntimeApi};
+
+/// Version of the `test-wasm` runtime, embedded into its code by the `ChainBuilder`.
+pub fn version(spec_version: u32) -> RuntimeVersion {
+	RuntimeVersion {
+		spec_name: create_runtime_str!("test-wasm"),
+		impl_name: create_runtime_str!("test-wasm"),
+		authoring_version: 0,
+		spec_version,
+		impl_version: 0,
+		apis: RUNTIME_API_VERSIONS,
+		transaction_version: 0,
+	}
+}
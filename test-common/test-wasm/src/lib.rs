@@ -1,4 +1,6 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+// runtime api functions are exported with the name the client calls them by
+#![cfg_attr(not(feature = "std"), allow(non_snake_case))]
 
 // Make the WASM binary available.
 #[cfg(feature = "std")]
@@ -19,6 +21,22 @@ use sp_io::wasm_tracing;
 #[cfg(not(feature = "std"))]
 use sp_runtime::print;
 
+#[cfg(not(feature = "std"))]
+use codec::Decode;
+#[cfg(not(feature = "std"))]
+use sp_runtime::{generic, traits::BlakeTwo256};
+#[cfg(not(feature = "std"))]
+use sp_std::vec::Vec;
+
+/// A block authored by `test_common::ChainBuilder`.
+/// Every extrinsic is an encoded `(key, value)` pair to write into storage.
+#[cfg(not(feature = "std"))]
+#[derive(Decode)]
+struct Block {
+	_header: generic::Header<u32, BlakeTwo256>,
+	extrinsics: Vec<Vec<u8>>,
+}
+
 sp_core::wasm_export_functions! {
 	fn test_trace_handler() {
 		sp_io::init_tracing();
@@ -27,6 +45,17 @@ sp_core::wasm_export_functions! {
 		wasm_funcs::call_events_and_spans();
 		wasm_tracing::exit(id);
 	}
+
+	fn Core_execute_block(block: Block) {
+		for ext in block.extrinsics {
+			let (key, value) = <(Vec<u8>, Vec<u8>)>::decode(&mut &ext[..]).expect("extrinsic is a (key, value) pair");
+			sp_io::storage::set(&key, &value);
+		}
+	}
+
+	fn Metadata_metadata() -> Vec<u8> {
+		b"test-wasm".to_vec()
+	}
 }
 
 // check marks it as unused because we call these functions from the resulting wasm blob